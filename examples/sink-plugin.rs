@@ -0,0 +1,99 @@
+//! A `--sink-plugin` that appends every text message as a line to the file named by
+//! `--sink-plugin-config`, and a last line with how many there were when the server shuts down.
+//!
+//! It only needs the types of the plugin ABI, declared here the way a plugin in C would declare
+//! them from the documentation of `Plugin`. Build it with `cargo build --example sink-plugin` and
+//! load `target/debug/examples/libsink_plugin.so`.
+
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::raw::{c_char, c_int, c_void};
+use std::{ptr, slice, str};
+
+#[repr(C)]
+pub struct PluginStr {
+    ptr: *const u8,
+    len: usize
+}
+
+impl PluginStr {
+    unsafe fn as_str(&self) -> &str {
+        str::from_utf8_unchecked(slice::from_raw_parts(self.ptr, self.len))
+    }
+}
+
+#[repr(C)]
+pub struct PluginMessage {
+    pid: u64,
+    sequence: u64,
+    timestamp: i64,
+    received_ms: i64,
+    severity: u32,
+    truncated: u8,
+    module: PluginStr,
+    channel: PluginStr,
+    message: PluginStr
+}
+
+struct State {
+    file: File,
+    messages: u64
+}
+
+#[no_mangle]
+pub extern "C" fn eve_logger_plugin_abi() -> u32 {
+    1
+}
+
+/// # Safety
+///
+/// `config` has to be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn eve_logger_plugin_init(config: *const c_char) -> *mut c_void {
+    let path = match CStr::from_ptr(config).to_str() {
+        Ok(path) if !path.is_empty() => path,
+        _ => return ptr::null_mut()
+    };
+
+    match OpenOptions::new().create(true).append(true).open(path) {
+        Ok(file) => Box::into_raw(Box::new(State { file, messages: 0 })) as *mut c_void,
+        Err(_) => ptr::null_mut()
+    }
+}
+
+/// # Safety
+///
+/// `state` has to be what `eve_logger_plugin_init` returned, and `message` valid for the call.
+#[no_mangle]
+pub unsafe extern "C" fn eve_logger_plugin_write(state: *mut c_void, message: *const PluginMessage) -> c_int {
+    let state = &mut *(state as *mut State);
+    let message = &*message;
+
+    state.messages += 1;
+
+    match writeln!(state.file, "{} {} {} {}", message.pid, message.severity, message.module.as_str(), message.message.as_str().trim_end()) {
+        Ok(()) => 0,
+        Err(_) => 1
+    }
+}
+
+/// # Safety
+///
+/// `state` has to be what `eve_logger_plugin_init` returned.
+#[no_mangle]
+pub unsafe extern "C" fn eve_logger_plugin_flush(state: *mut c_void) -> c_int {
+    match (*(state as *mut State)).file.flush() {
+        Ok(()) => 0,
+        Err(_) => 1
+    }
+}
+
+/// # Safety
+///
+/// `state` has to be what `eve_logger_plugin_init` returned, it isn't valid afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn eve_logger_plugin_shutdown(state: *mut c_void) {
+    let mut state = Box::from_raw(state as *mut State);
+    let _ = writeln!(state.file, "shutdown after {} messages", state.messages);
+}
@@ -0,0 +1,209 @@
+//! The `--format bincode` archive, a compact alternative to the text files that replays losslessly.
+//!
+//! Every client writes `{pid}.bin` (`{YYYY-MM-DD}/{pid}.bin` with `--date-dir`), laid out as:
+//!
+//! - the 8 byte magic `EVEARCH\0`, followed by the format version as a little endian u32,
+//!   currently 1;
+//! - any number of frames, each the length of its body as a little endian u32 followed by that
+//!   many bytes of `Message` encoded with bincode 1's default options (little endian, fixed width
+//!   integers, u64 string lengths, u32 enum variant indices).
+//!
+//! The messages are stored as the server saw them after `--redact` and `--anonymize-connection`,
+//! with continuations already assembled. Each time a client starts writing to a file its
+//! Connection message is stored first, so every file replays on its own, and several runs or
+//! clients appending to the same file still replay correctly.
+
+use std::io;
+
+use std::collections::HashSet;
+use std::io::{BufRead, Read};
+use std::path::PathBuf;
+
+use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian};
+
+use bincode;
+
+use chrono::naive::datetime::NaiveDateTime;
+
+use eve_logger::{ConnectionMessage, Message, TextMessage};
+
+use sink::Sink;
+use {output_root, Config, HandleCache};
+
+const MAGIC: &[u8; 8] = b"EVEARCH\0";
+const VERSION: u32 = 1;
+
+/// Frames longer than this are treated as corruption rather than allocated.
+const MAX_FRAME: u32 = 16 * 1024 * 1024;
+
+/// Serializes exactly like `Message`, without having to own the message.
+#[derive(Serialize)]
+enum Frame<'a> {
+    Connection(&'a ConnectionMessage),
+    Text(&'a TextMessage)
+}
+
+pub fn header() -> Vec<u8> {
+    let mut header = MAGIC.to_vec();
+    header.write_u32::<LittleEndian>(VERSION).unwrap();
+    header
+}
+
+fn encode(frame: &Frame) -> io::Result<Vec<u8>> {
+    let body = bincode::serialize(frame).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    let mut encoded = Vec::with_capacity(body.len() + 4);
+    encoded.write_u32::<LittleEndian>(body.len() as u32)?;
+    encoded.extend_from_slice(&body);
+
+    Ok(encoded)
+}
+
+/// The frame holding `message`, length prefix included.
+pub fn encode_message(message: &Message) -> io::Result<Vec<u8>> {
+    match *message {
+        Message::Connection(ref message) => encode(&Frame::Connection(message)),
+        Message::Text(ref message) => encode(&Frame::Text(message))
+    }
+}
+
+/// Whether `reader` is positioned at the start of an archive, without consuming anything.
+pub fn is_archive<R: BufRead>(reader: &mut R) -> io::Result<bool> {
+    Ok(reader.fill_buf()?.starts_with(MAGIC))
+}
+
+/// The `.bin` file of every pid a client wrote as.
+pub struct ArchiveSink<'a> {
+    config: &'a Config,
+    files: HandleCache,
+    /// Files this client has already stored its Connection in.
+    started: HashSet<PathBuf>,
+    connection: Option<Vec<u8>>
+}
+
+impl<'a> ArchiveSink<'a> {
+    pub fn new(config: &'a Config) -> ArchiveSink<'a> {
+        ArchiveSink { config, files: HandleCache::new(), started: HashSet::new(), connection: None }
+    }
+
+    fn path(&self, pid: u64, timestamp: &NaiveDateTime) -> PathBuf {
+        let mut path = output_root(self.config);
+
+        if self.config.date_dir {
+            path.push(timestamp.format("%Y-%m-%d").to_string());
+        }
+
+        path.push(format!("{}.bin", pid));
+        path
+    }
+}
+
+impl<'a> Sink for ArchiveSink<'a> {
+    fn connection(&mut self, message: &ConnectionMessage, _: Option<&str>) -> io::Result<()> {
+        self.connection = Some(encode(&Frame::Connection(message))?);
+        self.started.clear();
+
+        Ok(())
+    }
+
+    fn text(&mut self, pid: u64, _: Option<u64>, timestamp: &NaiveDateTime, _: &NaiveDateTime, message: &TextMessage) -> io::Result<()> {
+        let path = self.path(pid, timestamp);
+        let mut frames = Vec::new();
+
+        if self.started.insert(path.clone()) {
+            if let Some(ref connection) = self.connection {
+                frames.extend_from_slice(connection);
+            }
+        }
+
+        frames.extend(encode(&Frame::Text(message))?);
+
+        // One write per message, so unless --idle-flush buffers them clients appending to the same
+        // file never interleave frames.
+        self.files.write(self.config, path, &header(), &frames)
+    }
+}
+
+/// Reads the messages of an archive back in the order they were written.
+pub struct Frames<R> {
+    reader: R,
+    checked: bool,
+    done: bool
+}
+
+impl<R: Read> Frames<R> {
+    pub fn new(reader: R) -> Frames<R> {
+        Frames { reader, checked: false, done: false }
+    }
+
+    fn check_header(&mut self) -> io::Result<()> {
+        let mut magic = [0; 8];
+        self.reader.read_exact(&mut magic)?;
+
+        if &magic != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not an archive"));
+        }
+
+        match self.reader.read_u32::<LittleEndian>()? {
+            VERSION => Ok(()),
+            e => Err(io::Error::new(io::ErrorKind::InvalidData, format!("Archive format version {} is unknown", e)))
+        }
+    }
+
+    fn read_frame(&mut self) -> io::Result<Option<Message>> {
+        let mut length = [0; 4];
+
+        // A frame boundary is the only place the archive may end.
+        let read = loop {
+            match self.reader.read(&mut length[..1]) {
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                result => break result?
+            }
+        };
+
+        match read {
+            0 => return Ok(None),
+            _ => self.reader.read_exact(&mut length[1..])?
+        }
+
+        let length = u32::from_le_bytes(length);
+
+        if length > MAX_FRAME {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Frame of {} bytes is too large", length)));
+        }
+
+        let mut body = vec![0; length as usize];
+        self.reader.read_exact(&mut body)?;
+
+        bincode::deserialize(&body).map(Some).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+impl<R: Read> Iterator for Frames<R> {
+    type Item = io::Result<Message>;
+
+    fn next(&mut self) -> Option<io::Result<Message>> {
+        if self.done {
+            return None;
+        }
+
+        let result = if self.checked {
+            self.read_frame()
+        } else {
+            self.checked = true;
+            self.check_header().and_then(|_| self.read_frame())
+        };
+
+        match result {
+            Ok(Some(message)) => Some(Ok(message)),
+            Ok(None) => {
+                self.done = true;
+                None
+            },
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use std::io;
+
+use std::collections::HashSet;
+use std::io::Write;
+use std::sync::Arc;
+
+use eve_logger::TextMessage;
+
+use {create_dir, local_time, open_log, output_root, Config};
+
+/// The file `--audit-names` writes to, in the output root next to the pid directories.
+pub const FILE: &str = "name-audit.log";
+
+/// `--audit-names`, recording the module and channel names of a client that won't make sense as
+/// text to `name-audit.log`, a line each: when, the pid, which of the two, the name and the hex of
+/// its bytes, `2026-10-14 12:00:00.000+00:00 pid=1234 module="m\u{1}d" bytes=6d 01 64`.
+///
+/// A name is audited when it holds a control character, or a U+FFFD where the bytes couldn't be
+/// decoded. The bytes are those of the name as decoded to UTF-8, which are the bytes the client
+/// sent for anything but such a U+FFFD, the parser doesn't keep the buffers themselves. A channel
+/// buffer holding a number rather than text is how some protocol versions send it and isn't
+/// audited, see `TextMessage::channel_is_textual`.
+///
+/// Each name is audited once per session, the messages themselves are written as usual.
+pub struct NameAudit {
+    seen: HashSet<(u64, &'static str, Arc<str>)>
+}
+
+impl NameAudit {
+    pub fn new() -> NameAudit {
+        NameAudit { seen: HashSet::new() }
+    }
+
+    /// Audits the names of `message`, as the client sent them.
+    pub fn check(&mut self, config: &Config, pid: u64, message: &TextMessage) -> io::Result<()> {
+        let textual = message.channel_is_textual();
+
+        for &(field, name) in &[("module", &message.module), ("channel", &message.channel)] {
+            if (field == "channel" && !textual) || !unexpected(name) || !self.seen.insert((pid, field, name.clone())) {
+                continue;
+            }
+
+            let bytes: Vec<String> = name.bytes().map(|byte| format!("{:02x}", byte)).collect();
+            let line = format!("{} pid={} {}={:?} bytes={}\n", local_time(), pid, field, name, bytes.join(" "));
+
+            let root = output_root(config);
+
+            if !root.as_os_str().is_empty() {
+                create_dir(config, &root)?;
+            }
+
+            open_log(config, &root.join(FILE))?.write_all(line.as_bytes())?;
+        }
+
+        Ok(())
+    }
+}
+
+fn unexpected(name: &str) -> bool {
+    name.chars().any(|c| c.is_control() || c == char::REPLACEMENT_CHARACTER)
+}
@@ -0,0 +1,51 @@
+#[cfg(test)]
+use std::sync::Mutex;
+
+use chrono::{DateTime, UTC};
+
+#[cfg(test)]
+use chrono::Duration;
+
+/// Where a session gets the time from: when its client connected, for `--timestamp-mode relative`,
+/// and when each message was read, for `--time-source` and the skew.
+///
+/// Timers like `--idle-flush` and `--batch-time` measure how long something took rather than what
+/// time it is, and stay on `Instant`.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<UTC>;
+}
+
+/// The clock of the system, which the server runs on.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<UTC> {
+        UTC::now()
+    }
+}
+
+/// A clock that only moves when told to, for the tests to run sessions at a time of their
+/// choosing.
+#[cfg(test)]
+pub struct FakeClock {
+    now: Mutex<DateTime<UTC>>
+}
+
+#[cfg(test)]
+impl FakeClock {
+    pub fn new(now: DateTime<UTC>) -> FakeClock {
+        FakeClock { now: Mutex::new(now) }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        let mut now = self.now.lock().unwrap();
+        *now = *now + by;
+    }
+}
+
+#[cfg(test)]
+impl Clock for FakeClock {
+    fn now(&self) -> DateTime<UTC> {
+        *self.now.lock().unwrap()
+    }
+}
@@ -0,0 +1,207 @@
+use std::{io, thread};
+
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+
+use chrono::naive::datetime::NaiveDateTime;
+
+use eve_logger::{ConnectionMessage, TextMessage};
+
+use parquet::basic::Compression;
+use parquet::data_type::{ByteArray, ByteArrayType, DataType, Int64Type};
+use parquet::errors::{ParquetError, Result};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::{SerializedFileWriter, SerializedRowGroupWriter};
+use parquet::schema::parser::parse_message_type;
+
+use sink::{Batched, Sink};
+
+const SCHEMA: &str = "
+    message messages {
+        REQUIRED INT64 timestamp (TIMESTAMP(MILLIS, true));
+        REQUIRED INT64 pid (INTEGER(64, false));
+        REQUIRED BYTE_ARRAY severity (UTF8);
+        REQUIRED BYTE_ARRAY module (UTF8);
+        REQUIRED BYTE_ARRAY channel (UTF8);
+        REQUIRED BYTE_ARRAY message (UTF8);
+    }
+";
+
+/// Rows buffered before they are written out as a row group.
+const ROW_GROUP: usize = 65536;
+
+/// How long the first buffered row waits for a row group at most, so a quiet server still gets its
+/// messages on disk.
+const ROW_GROUP_AGE: Duration = Duration::from_secs(60);
+
+struct Row {
+    timestamp: i64,
+    pid: u64,
+    severity: String,
+    module: Arc<str>,
+    channel: Arc<str>,
+    message: String
+}
+
+enum Request {
+    Rows(Vec<Row>),
+    /// Writes what is buffered and the footer, answering once the file is complete.
+    Close(Sender<()>)
+}
+
+/// The `--parquet` file, one row of (timestamp, pid, severity, module, channel, message) for every
+/// text message of every client, written by a thread of its own.
+///
+/// Rows are buffered into row groups of `ROW_GROUP` rows, or whatever has arrived once the first
+/// of them is `ROW_GROUP_AGE` old. A Parquet file is only readable once its footer has been
+/// written, which `close` does when the server exits. A server that is killed leaves a file
+/// without one, so this is for captures replayed with `--replay` rather than a live server. The
+/// file is created anew every run, Parquet files can't be appended to.
+pub struct Writer {
+    sender: Sender<Request>
+}
+
+impl Writer {
+    /// Creates the file at `path`, replacing any that is there.
+    pub fn open(path: &Path) -> Result<Writer> {
+        let schema = Arc::new(parse_message_type(SCHEMA)?);
+        let properties = Arc::new(WriterProperties::builder().set_compression(Compression::SNAPPY).build());
+        let writer = SerializedFileWriter::new(File::create(path)?, schema, properties)?;
+
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move|| write_rows(writer, receiver));
+
+        Ok(Writer { sender })
+    }
+
+    /// A sink for one client that queues its messages for this writer.
+    pub fn sink(&self) -> ParquetSink {
+        ParquetSink { sender: self.sender.clone() }
+    }
+
+    /// Writes the rows queued so far and the footer, after which rows are dropped.
+    pub fn close(&self) {
+        let (done, waiting) = mpsc::channel();
+
+        if self.sender.send(Request::Close(done)).is_ok() {
+            let _ = waiting.recv();
+        }
+    }
+}
+
+fn write_rows(writer: SerializedFileWriter<File>, receiver: Receiver<Request>) {
+    let mut writer = Some(writer);
+    let mut rows = Vec::new();
+    let mut oldest: Option<Instant> = None;
+
+    loop {
+        let request = match oldest {
+            Some(oldest) => receiver.recv_timeout(ROW_GROUP_AGE.saturating_sub(oldest.elapsed())),
+            None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        };
+
+        match request {
+            Ok(Request::Rows(mut more)) => {
+                oldest = oldest.or_else(|| Some(Instant::now()));
+                rows.append(&mut more);
+
+                if rows.len() < ROW_GROUP {
+                    continue;
+                }
+            },
+            Ok(Request::Close(done)) => {
+                if let Some(writer) = writer.take() {
+                    if let Err(e) = write_row_group(writer, &mut rows).and_then(SerializedFileWriter::close) {
+                        warn!("Could not finish the --parquet file: {}", e);
+                    }
+                }
+
+                let _ = done.send(());
+                continue;
+            },
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => return
+        }
+
+        oldest = None;
+
+        if let Some(file) = writer.take() {
+            match write_row_group(file, &mut rows) {
+                Ok(file) => writer = Some(file),
+                Err(e) => warn!("Could not write a row group to the --parquet file, no more will be written: {}", e)
+            }
+        }
+    }
+}
+
+/// Writes `rows` as one row group, leaving `rows` empty. Nothing is written without any.
+fn write_row_group(mut writer: SerializedFileWriter<File>, rows: &mut Vec<Row>) -> Result<SerializedFileWriter<File>> {
+    if rows.is_empty() {
+        return Ok(writer);
+    }
+
+    {
+        let mut group = writer.next_row_group()?;
+
+        write_column::<Int64Type>(&mut group, &rows.iter().map(|row| row.timestamp).collect::<Vec<_>>())?;
+        write_column::<Int64Type>(&mut group, &rows.iter().map(|row| row.pid as i64).collect::<Vec<_>>())?;
+        write_column::<ByteArrayType>(&mut group, &rows.iter().map(|row| ByteArray::from(&row.severity[..])).collect::<Vec<_>>())?;
+        write_column::<ByteArrayType>(&mut group, &rows.iter().map(|row| ByteArray::from(&row.module[..])).collect::<Vec<_>>())?;
+        write_column::<ByteArrayType>(&mut group, &rows.iter().map(|row| ByteArray::from(&row.channel[..])).collect::<Vec<_>>())?;
+        write_column::<ByteArrayType>(&mut group, &rows.iter().map(|row| ByteArray::from(&row.message[..])).collect::<Vec<_>>())?;
+
+        group.close()?;
+    }
+
+    rows.clear();
+    Ok(writer)
+}
+
+fn write_column<T: DataType>(group: &mut SerializedRowGroupWriter<'_, File>, values: &[T::T]) -> Result<()> {
+    let mut column = group.next_column()?.ok_or_else(|| ParquetError::General("The schema has fewer columns than a row".to_string()))?;
+    column.typed::<T>().write_batch(values, None, None)?;
+    column.close()
+}
+
+pub struct ParquetSink {
+    sender: Sender<Request>
+}
+
+impl ParquetSink {
+    fn send(&self, rows: Vec<Row>) -> io::Result<()> {
+        self.sender.send(Request::Rows(rows)).map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "The Parquet writer has stopped"))
+    }
+}
+
+fn row(pid: u64, timestamp: &NaiveDateTime, message: &TextMessage) -> Row {
+    Row {
+        timestamp: timestamp.timestamp() * 1000 + timestamp.timestamp_subsec_millis() as i64,
+        pid,
+        severity: format!("{:?}", message.severity),
+        module: message.module.clone(),
+        channel: message.channel.clone(),
+        message: message.message.clone()
+    }
+}
+
+impl Sink for ParquetSink {
+    /// Connections have no rows of their own, the file only holds messages.
+    fn connection(&mut self, _: &ConnectionMessage, _: Option<&str>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn text(&mut self, pid: u64, _: Option<u64>, timestamp: &NaiveDateTime, _: &NaiveDateTime, message: &TextMessage) -> io::Result<()> {
+        self.send(vec![row(pid, timestamp, message)])
+    }
+
+    fn batches(&self) -> bool {
+        true
+    }
+
+    fn write_batch(&mut self, batch: &[Batched]) -> io::Result<()> {
+        self.send(batch.iter().map(|entry| row(entry.pid, &entry.timestamp, &entry.message)).collect())
+    }
+}
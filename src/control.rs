@@ -0,0 +1,356 @@
+use std::{io, thread};
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::io::Write;
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use serde_json;
+
+use counter::SessionCounter;
+use http::TailEntry;
+use {reload, stats, Context};
+
+/// The connected TCP clients, so the control socket can disconnect them.
+pub struct Clients {
+    next: Mutex<u64>,
+    /// `--session-ids`, where the ids come from instead of `next`.
+    counter: Option<SessionCounter>,
+    streams: Mutex<HashMap<u64, Client>>,
+    /// `--recent-messages`, how many messages each client keeps for `recent`.
+    recent: usize,
+    /// Counts the messages kept, to put those of clients sharing a pid in the order they came.
+    kept: Mutex<u64>
+}
+
+struct Client {
+    pid: u64,
+    stream: TcpStream,
+    /// The modules written to since the client announced `pid`.
+    modules: BTreeSet<String>,
+    /// The last messages since the client announced `pid`, with the count they were kept at.
+    recent: VecDeque<(u64, TailEntry)>
+}
+
+impl Clients {
+    /// `recent` is the `--recent-messages` each client keeps, none for 0.
+    pub fn new(recent: usize) -> Clients {
+        Clients { next: Mutex::new(0), counter: None, streams: Mutex::new(HashMap::new()), recent, kept: Mutex::new(0) }
+    }
+
+    /// Hands out the ids of `counter` rather than counting from 1 every run.
+    pub fn counting(self, counter: SessionCounter) -> Clients {
+        Clients { counter: Some(counter), ..self }
+    }
+
+    /// Registers a clone of `stream`, returning the id its session reports its pid with.
+    pub fn enter(&self, stream: TcpStream) -> u64 {
+        let id = match self.counter {
+            Some(ref counter) => counter.next(),
+            None => {
+                let mut next = self.next.lock().unwrap();
+                *next += 1;
+                *next
+            }
+        };
+
+        self.streams.lock().unwrap().insert(id, Client { pid: 0, stream, modules: BTreeSet::new(), recent: VecDeque::new() });
+        id
+    }
+
+    pub fn announce(&self, id: u64, pid: u64) {
+        if let Some(client) = self.streams.lock().unwrap().get_mut(&id) {
+            client.pid = pid;
+            client.modules.clear();
+            client.recent.clear();
+        }
+    }
+
+    /// Records that the client wrote to `module`, its session only reports each module once.
+    pub fn module(&self, id: u64, module: &str) {
+        if let Some(client) = self.streams.lock().unwrap().get_mut(&id) {
+            client.modules.insert(module.to_string());
+        }
+    }
+
+    /// Keeps `entry` as the newest message of the client, forgetting its oldest past
+    /// `--recent-messages`.
+    pub fn remember(&self, id: u64, entry: TailEntry) {
+        if self.recent == 0 {
+            return;
+        }
+
+        let mut kept = self.kept.lock().unwrap();
+        *kept += 1;
+
+        if let Some(client) = self.streams.lock().unwrap().get_mut(&id) {
+            if client.recent.len() == self.recent {
+                client.recent.pop_front();
+            }
+
+            client.recent.push_back((*kept, entry));
+        }
+    }
+
+    /// The last `count` messages of the connected clients of `pid`, oldest first. A pid that isn't
+    /// connected has none, its messages are forgotten with the client.
+    pub fn recent(&self, pid: u64, count: usize) -> Vec<TailEntry> {
+        let mut recent: Vec<(u64, TailEntry)> = self.streams.lock().unwrap().values()
+            .filter(|client| client.pid == pid)
+            .flat_map(|client| client.recent.iter().cloned())
+            .collect();
+
+        recent.sort_by_key(|&(kept, _)| kept);
+
+        let skipped = recent.len().saturating_sub(count);
+        recent.into_iter().skip(skipped).map(|(_, entry)| entry).collect()
+    }
+
+    /// The modules of every connected pid, for clients sharing a pid those of all of them.
+    pub fn modules(&self) -> BTreeMap<u64, BTreeSet<String>> {
+        let mut modules: BTreeMap<u64, BTreeSet<String>> = BTreeMap::new();
+
+        for client in self.streams.lock().unwrap().values() {
+            modules.entry(client.pid).or_default().extend(client.modules.iter().cloned());
+        }
+
+        modules
+    }
+
+    /// The address the client connected from.
+    pub fn peer(&self, id: u64) -> Option<SocketAddr> {
+        self.streams.lock().unwrap().get(&id).and_then(|client| client.stream.peer_addr().ok())
+    }
+
+    pub fn leave(&self, id: u64) {
+        self.streams.lock().unwrap().remove(&id);
+    }
+
+    /// Shuts down the connection of every client with `pid`, or of all of them for `None`. Their
+    /// sessions then end as if the client had disconnected. Returns how many there were.
+    pub fn disconnect(&self, pid: Option<u64>) -> usize {
+        let streams = self.streams.lock().unwrap();
+        let mut count = 0;
+
+        for client in streams.values() {
+            if pid.is_none_or(|pid| pid == client.pid) {
+                let _ = client.stream.shutdown(Shutdown::Both);
+                count += 1;
+            }
+        }
+
+        count
+    }
+
+    /// Waits up to `timeout` for every client to leave, returning how many are still connected.
+    pub fn drain(&self, timeout: Duration) -> usize {
+        let started = Instant::now();
+
+        loop {
+            let connected = self.streams.lock().unwrap().len();
+
+            if connected == 0 || started.elapsed() >= timeout {
+                return connected;
+            }
+
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+}
+
+/// Set once shutting down has started, from then on new clients are turned away.
+pub static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+/// Shuts the server down on `shutdown` or SIGTERM, returning what happened.
+///
+/// New clients are refused and the connected ones disconnected, which ends their sessions like a
+/// disconnect of their own, writing out what they still had buffered. Sessions get up to
+/// `--drain-timeout` to finish that, after which the background sinks are flushed regardless, the
+/// `--sink-plugin` is shut down and the caller may exit. Clients that didn't finish in time lose
+/// whatever they hadn't written yet.
+pub fn shutdown(context: &Context) -> String {
+    SHUTTING_DOWN.store(true, Ordering::SeqCst);
+
+    let count = context.clients.disconnect(None);
+    let stuck = context.clients.drain(context.config.drain_timeout);
+
+    // The files before the database, as for each client in `sink::in_order`.
+    flush_idle_files();
+    context.close();
+
+    if stuck == 0 {
+        format!("shutting down after disconnecting {} clients", count)
+    } else {
+        warn!("{} of {} clients didn't finish within the drain timeout of {}s", stuck, count, context.config.drain_timeout.as_secs());
+        format!("shutting down after disconnecting {} clients, {} didn't drain in time", count, stuck)
+    }
+}
+
+/// A line sent to the `--control` socket.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// Re-reads the `--config` file like SIGHUP.
+    Reload,
+    /// The counters dumped on SIGUSR1.
+    Stats,
+    /// The modules each connected pid has written to so far, a line per pid.
+    Modules,
+    /// Disconnects every client that announced that pid.
+    DropPid(u64),
+    /// The last messages of that pid, at most the count given, as a JSON array.
+    Recent(u64, usize),
+    /// Disconnects every client, waits up to `--drain-timeout` for their sessions to finish and
+    /// exits.
+    Shutdown
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Command, String> {
+        let mut words = line.split_whitespace();
+
+        let command = match (words.next(), words.next()) {
+            (Some("reload"), None) => Command::Reload,
+            (Some("stats"), None) => Command::Stats,
+            (Some("modules"), None) => Command::Modules,
+            (Some("drop-pid"), Some(pid)) => Command::DropPid(pid.parse().map_err(|_| format!("drop-pid expects a pid, got {}", pid))?),
+            (Some("drop-pid"), None) => return Err("drop-pid expects a pid".to_string()),
+            (Some("recent"), Some(pid)) => {
+                let pid = pid.parse().map_err(|_| format!("recent expects a pid, got {}", pid))?;
+
+                match words.next() {
+                    Some(count) => Command::Recent(pid, count.parse().map_err(|_| format!("recent expects a count, got {}", count))?),
+                    None => return Err("recent expects a pid and a count".to_string())
+                }
+            },
+            (Some("recent"), None) => return Err("recent expects a pid and a count".to_string()),
+            (Some("shutdown"), None) => Command::Shutdown,
+            (Some(command @ ("reload" | "stats" | "modules" | "shutdown")), Some(_)) => return Err(format!("{} takes no arguments", command)),
+            (Some(command), _) => return Err(format!("unknown command {}, expected reload, stats, modules, drop-pid <pid>, recent <pid> <n> or shutdown", command)),
+            (None, _) => return Err("empty command".to_string())
+        };
+
+        match (words.next(), &command) {
+            (Some(_), &Command::Recent(..)) => Err("recent takes a pid and a count".to_string()),
+            (Some(_), _) => Err("drop-pid takes a single pid".to_string()),
+            (None, _) => Ok(command)
+        }
+    }
+}
+
+/// Carries out `command`, returning the response. Shutdown doesn't exit by itself, that is left
+/// to the caller once the response has been sent.
+pub fn execute(context: &Context, command: &Command) -> Result<String, String> {
+    match *command {
+        Command::Reload => reload(&context.filters, &context.args).map(|_| "reloaded".to_string()),
+        Command::Stats => Ok(stats::STATS.snapshot().to_string().trim_end().to_string()),
+        Command::Modules => Ok(list_modules(&context.clients.modules())),
+        Command::DropPid(pid) => Ok(format!("dropped {} connections", context.clients.disconnect(Some(pid)))),
+        Command::Recent(_, _) if context.config.recent_messages == 0 => Err("recent needs the server to keep messages with --recent-messages".to_string()),
+        Command::Recent(pid, count) => Ok(serde_json::to_string(&context.clients.recent(pid, count)).unwrap()),
+        Command::Shutdown => Ok(shutdown(context))
+    }
+}
+
+pub fn list_modules(modules: &BTreeMap<u64, BTreeSet<String>>) -> String {
+    let mut lines = vec![format!("{} pids connected", modules.len())];
+
+    for (pid, modules) in modules {
+        lines.push(format!("pid {}: {}", pid, modules.iter().cloned().collect::<Vec<_>>().join(", ")));
+    }
+
+    lines.join("\n")
+}
+
+/// `--idle-flush` holds back writes, which have to reach the files before exiting.
+pub fn flush_idle_files() {
+    for handle in ::HANDLES.lock().unwrap().iter().filter_map(|handle| handle.upgrade()) {
+        if let Some(ref mut file) = handle.lock().unwrap().file {
+            if let Err(e) = file.flush() {
+                warn!("Could not flush a log file: {}", e);
+            }
+        }
+    }
+}
+
+/// Serves `--control <path>`, a Unix socket taking one command a line, each answered with a line
+/// of text (a few lines for `stats` and `modules`).
+///
+/// The commands are `reload`, `stats`, `modules`, `drop-pid <pid>`, `recent <pid> <n>` and
+/// `shutdown`. A socket left over at `path` from an earlier run is replaced, one that a running
+/// server still answers on isn't.
+///
+/// The socket is made readable and writable by the owner alone right after binding, as `shutdown`
+/// and `drop-pid` are no business of other local users.
+#[cfg(unix)]
+pub fn serve(path: &Path, context: Arc<Context>) -> io::Result<()> {
+    use std::{fs, process};
+
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    let listener = match UnixListener::bind(path) {
+        Err(ref e) if e.kind() == io::ErrorKind::AddrInUse && UnixStream::connect(path).is_err() => {
+            fs::remove_file(path)?;
+            UnixListener::bind(path)?
+        },
+        result => result?
+    };
+
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+
+    let path = path.to_path_buf();
+
+    thread::spawn(move|| {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("Control connection failed: {}", e);
+                    continue;
+                }
+            };
+
+            let context = context.clone();
+            let path = path.clone();
+
+            thread::spawn(move|| -> io::Result<()> {
+                let mut writer = stream.try_clone()?;
+
+                for line in BufReader::new(stream).lines() {
+                    let line = line?;
+
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+
+                    let command = Command::parse(&line);
+                    let result = command.as_ref().map_err(|e| e.clone()).and_then(|command| execute(&context, command));
+
+                    match result {
+                        Ok(ref response) => writeln!(writer, "ok, {}", response)?,
+                        Err(ref e) => writeln!(writer, "error: {}", e)?
+                    }
+
+                    if command == Ok(Command::Shutdown) {
+                        info!("Shutting down as asked on the control socket");
+                        let _ = fs::remove_file(&path);
+                        process::exit(0);
+                    }
+                }
+
+                Ok(())
+            });
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn serve(_: &Path, _: Arc<Context>) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "--control is only supported on Unix"))
+}
@@ -0,0 +1,125 @@
+use std::{fs, io};
+
+use std::io::{BufWriter, Write};
+
+use chrono::naive::datetime::NaiveDateTime;
+
+use eve_logger::{write_packet, Message, Messages};
+
+use {archive, option_value, sink};
+
+/// What `convert` reads and writes.
+///
+/// Only formats that keep whole messages can be read back: `bin` is the `--format bincode`
+/// archive, `capture` the raw wire format as given to `--replay`. Text and JSON output lose the
+/// Connection message and the layout of continuations, so they can only be written.
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Bin, Capture, Json
+}
+
+impl Kind {
+    fn from_name(name: &str, value: &str) -> Result<Kind, String> {
+        match value {
+            "bin" => Ok(Kind::Bin),
+            "capture" => Ok(Kind::Capture),
+            "json" => Ok(Kind::Json),
+            "text" => Err(format!("Option {} doesn't support text, convert to json instead", name)),
+            e => Err(format!("Option {} expects bin, capture or json, got {}", name, e))
+        }
+    }
+}
+
+/// `eve-logger convert --from <bin|capture> --to <bin|capture|json> <in> <out>`, re-encodes a
+/// whole file of messages in another format.
+///
+/// JSON output is one object per text message, like `--format json` but all pids in one file.
+/// Converting to `capture` writes little endian UTF-8 packets, whatever the original client sent.
+/// Returns the exit code.
+pub fn run<I: Iterator<Item = String>>(args: I) -> i32 {
+    match convert(args) {
+        Ok(count) => {
+            println!("Converted {} messages", count);
+            0
+        },
+        Err(e) => {
+            eprintln!("Convert failed: {}", e);
+            1
+        }
+    }
+}
+
+fn convert<I: Iterator<Item = String>>(args: I) -> Result<u64, String> {
+    let mut args = args;
+    let mut from = None;
+    let mut to = None;
+    let mut paths = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_ref() {
+            "--from" => from = Some(Kind::from_name(&arg, &option_value(&arg, &mut args)?)?),
+            "--to" => to = Some(Kind::from_name(&arg, &option_value(&arg, &mut args)?)?),
+            e if e.starts_with("--") => return Err(format!("Unknown option {}", e)),
+            _ => paths.push(arg)
+        }
+    }
+
+    let (from, to) = match (from, to) {
+        (Some(Kind::Json), _) => return Err("converting from json is not supported, it doesn't keep whole messages".to_string()),
+        (Some(from), Some(to)) => (from, to),
+        _ => return Err("both --from and --to are required".to_string())
+    };
+
+    let (input, output) = match paths.as_slice() {
+        [input, output] => (input, output),
+        _ => return Err("expected an input and an output file".to_string())
+    };
+
+    let reader = io::BufReader::new(fs::File::open(input).map_err(|e| format!("could not open {}: {}", input, e))?);
+    let messages: Box<dyn Iterator<Item = io::Result<Message>>> = match from {
+        Kind::Bin => Box::new(archive::Frames::new(reader)),
+        _ => Box::new(Messages::new(reader))
+    };
+
+    let file = fs::OpenOptions::new().write(true).create_new(true).open(output).map_err(|e| format!("could not create {}: {}", output, e))?;
+    let mut writer = BufWriter::new(file);
+
+    write_all(messages, to, &mut writer).map_err(|e| e.to_string())
+}
+
+fn write_all<I: Iterator<Item = io::Result<Message>>, W: Write>(messages: I, to: Kind, writer: &mut W) -> io::Result<u64> {
+    // The JSON of a server run without options.
+    let config = ::Config::default();
+    let mut pid = 0;
+    let mut count = 0;
+
+    if to == Kind::Bin {
+        writer.write_all(&archive::header())?;
+    }
+
+    for message in messages {
+        let message = message?;
+
+        match (to, &message) {
+            (Kind::Bin, _) => writer.write_all(&archive::encode_message(&message)?)?,
+            (Kind::Capture, _) => write_packet(writer, &message)?,
+            (Kind::Json, Message::Connection(connection)) => pid = connection.pid,
+            (Kind::Json, Message::Text(text)) => {
+                let timestamp = match sink::TimestampMode::Absolute.decode(text.timestamp, NaiveDateTime::from_timestamp_opt(0, 0).unwrap()) {
+                    Some(timestamp) => timestamp,
+                    None => {
+                        warn!("Skipped a message of pid {} with timestamp {}, which is out of range", pid, text.timestamp);
+                        continue;
+                    }
+                };
+
+                writer.write_all(sink::json_line(&config, pid, None, None, &timestamp, None, text)?.as_bytes())?;
+            }
+        }
+
+        count += 1;
+    }
+
+    writer.flush()?;
+    Ok(count)
+}
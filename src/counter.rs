@@ -0,0 +1,50 @@
+use std::{fs, io, process};
+
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// The file `--session-ids` keeps its counter in, in the output root next to the pid directories.
+pub const FILE: &str = "session-counter";
+
+/// `--session-ids`, session ids that keep counting up across restarts of the server, so the
+/// `--save-raw` captures, the Connection lines and the close records of one session can be found
+/// by its id without an earlier run having used it too.
+///
+/// The last id handed out is kept as a decimal number in `session-counter`, rewritten through a
+/// temporary file and a rename every time, so a server that is killed leaves either the old
+/// number or the new one. A counter that can't be written is a warning, the ids still count up for
+/// the rest of the run.
+pub struct SessionCounter {
+    path: PathBuf,
+    last: Mutex<u64>
+}
+
+impl SessionCounter {
+    /// Carries on from the counter at `path`, from the start if there is none.
+    pub fn open(path: &Path) -> Result<SessionCounter, String> {
+        let last = match fs::read_to_string(path) {
+            Ok(contents) => contents.trim().parse().map_err(|_| format!("The session counter {} doesn't hold a number", path.display()))?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(format!("Could not read the session counter {}: {}", path.display(), e))
+        };
+
+        Ok(SessionCounter { path: path.to_path_buf(), last: Mutex::new(last) })
+    }
+
+    /// The next id, which no session had before.
+    pub fn next(&self) -> u64 {
+        let mut last = self.last.lock().unwrap();
+        *last += 1;
+
+        let mut temporary = self.path.as_os_str().to_owned();
+        temporary.push(format!(".{}.tmp", process::id()));
+        let temporary = PathBuf::from(temporary);
+
+        if let Err(e) = fs::write(&temporary, format!("{}\n", *last)).and_then(|_| fs::rename(&temporary, &self.path)) {
+            warn!("Could not write the session counter {}: {}", self.path.display(), e);
+            let _ = fs::remove_file(&temporary);
+        }
+
+        *last
+    }
+}
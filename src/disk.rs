@@ -0,0 +1,106 @@
+use std::{io, thread};
+
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use Config;
+
+/// Set while the output filesystem has less than `--min-free-bytes` available.
+static LOW: AtomicBool = AtomicBool::new(false);
+
+/// What a client does with its messages while space is low, chosen with `--low-disk`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LowDisk {
+    /// Stop reading from the client until space recovers, its own buffers fill up instead.
+    Wait,
+    /// Keep reading and throw the messages away, so the client never blocks.
+    Discard
+}
+
+impl LowDisk {
+    pub fn from_name(name: &str, value: &str) -> Result<LowDisk, String> {
+        match value {
+            "wait" => Ok(LowDisk::Wait),
+            "discard" => Ok(LowDisk::Discard),
+            e => Err(format!("Option {} expects wait or discard, got {}", name, e))
+        }
+    }
+}
+
+/// Checks the free space of the output directory once a second for the lifetime of the server.
+pub fn watch(min_free: u64) {
+    loop {
+        match free_bytes(Path::new(".")) {
+            Ok(free) => {
+                let low = free < min_free;
+
+                if low != LOW.swap(low, Ordering::SeqCst) {
+                    if low {
+                        warn!("Only {} bytes free, below --min-free-bytes {}, not writing until space is freed", free, min_free);
+                    } else {
+                        warn!("{} bytes free again, writing resumes", free);
+                    }
+                }
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::Unsupported => {
+                warn!("{}", e);
+                return;
+            },
+            Err(e) => warn!("Could not check free space: {}", e)
+        }
+
+        thread::sleep(Duration::from_secs(1));
+    }
+}
+
+/// Sets what `watch` would, without a filesystem that is actually running out of space.
+#[cfg(test)]
+pub fn set_low(low: bool) {
+    LOW.store(low, Ordering::SeqCst);
+}
+
+/// Whether a message may be written now. With `--low-disk wait` this only returns once space has
+/// recovered, with `--low-disk discard` it returns false while space is low.
+pub fn may_write(config: &Config) -> bool {
+    if config.min_free_bytes.is_none() {
+        return true;
+    }
+
+    while LOW.load(Ordering::SeqCst) {
+        if config.low_disk == LowDisk::Discard {
+            return false;
+        }
+
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    true
+}
+
+#[cfg(unix)]
+fn free_bytes(path: &Path) -> io::Result<u64> {
+    use std::ffi::CString;
+    use std::mem;
+    use std::os::unix::ffi::OsStrExt;
+
+    use libc;
+
+    let path = CString::new(path.as_os_str().as_bytes()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    unsafe {
+        let mut stat: libc::statvfs = mem::zeroed();
+
+        if libc::statvfs(path.as_ptr(), &mut stat) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // Available to unprivileged users, which is what the logger usually runs as.
+        Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+    }
+}
+
+#[cfg(not(unix))]
+fn free_bytes(_: &Path) -> io::Result<u64> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "--min-free-bytes is only supported on Unix"))
+}
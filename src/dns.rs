@@ -0,0 +1,131 @@
+use std::thread;
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+#[cfg(test)]
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+/// How long a client waits for the name of its address before it is logged with the address alone.
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long a name, or the lack of one, is remembered for.
+const CACHE: Duration = Duration::from_secs(300);
+
+/// Where `--reverse-dns` gets the name of an address from.
+pub trait Resolver: Send + Sync {
+    /// The name of `ip`, `None` when it has none.
+    fn reverse(&self, ip: IpAddr) -> Option<String>;
+}
+
+/// Resolves with `getnameinfo`, so with whatever the system is set up to use: `/etc/hosts`, DNS
+/// and the like.
+pub struct SystemResolver;
+
+#[cfg(unix)]
+impl Resolver for SystemResolver {
+    fn reverse(&self, ip: IpAddr) -> Option<String> {
+        use std::ffi::CStr;
+        use std::net::SocketAddr;
+        use std::ptr;
+
+        use libc;
+        use socket2::SockAddr;
+
+        let address = SockAddr::from(SocketAddr::new(ip, 0));
+        let mut host = [0; 1025];
+
+        unsafe {
+            // NI_NAMEREQD fails rather than writing the address back as the name.
+            if libc::getnameinfo(address.as_ptr(), address.len(), host.as_mut_ptr(), host.len() as libc::socklen_t, ptr::null_mut(), 0, libc::NI_NAMEREQD) != 0 {
+                return None;
+            }
+
+            CStr::from_ptr(host.as_ptr()).to_str().ok().map(str::to_string)
+        }
+    }
+}
+
+#[cfg(not(unix))]
+impl Resolver for SystemResolver {
+    fn reverse(&self, _: IpAddr) -> Option<String> {
+        None
+    }
+}
+
+/// A resolver that knows the names it was given, taking `delay` for every lookup, for the
+/// tests to stand in for a real one.
+#[cfg(test)]
+pub struct FakeResolver {
+    names: HashMap<IpAddr, String>,
+    delay: Duration,
+    lookups: AtomicUsize
+}
+
+#[cfg(test)]
+impl FakeResolver {
+    pub fn new(names: HashMap<IpAddr, String>, delay: Duration) -> FakeResolver {
+        FakeResolver { names, delay, lookups: AtomicUsize::new(0) }
+    }
+
+    /// How many lookups reached this resolver.
+    pub fn lookups(&self) -> usize {
+        self.lookups.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+impl Resolver for FakeResolver {
+    fn reverse(&self, ip: IpAddr) -> Option<String> {
+        self.lookups.fetch_add(1, Ordering::SeqCst);
+        thread::sleep(self.delay);
+
+        self.names.get(&ip).cloned()
+    }
+}
+
+/// `--reverse-dns`, the names of the addresses clients connect from.
+///
+/// Each client looks its own address up in its thread, so a slow resolver never holds up
+/// accepting. A lookup that takes longer than `timeout` is given up on, the client carrying on
+/// with its address alone, though the lookup itself is left to finish in a thread of its own as
+/// a resolver can't be interrupted. Names and failures alike are kept for a while, so neither a
+/// client that reconnects nor one whose address has no name waits every time.
+pub struct ReverseDns {
+    resolver: Arc<dyn Resolver>,
+    timeout: Duration,
+    cache: Mutex<HashMap<IpAddr, (Option<String>, Instant)>>
+}
+
+impl ReverseDns {
+    pub fn new(resolver: Arc<dyn Resolver>) -> ReverseDns {
+        ReverseDns::with_timeout(resolver, TIMEOUT)
+    }
+
+    pub fn with_timeout(resolver: Arc<dyn Resolver>, timeout: Duration) -> ReverseDns {
+        ReverseDns { resolver, timeout, cache: Mutex::new(HashMap::new()) }
+    }
+
+    /// The name of `ip`, `None` if it has none or the resolver didn't answer in time.
+    pub fn lookup(&self, ip: IpAddr) -> Option<String> {
+        if let Some((name, resolved)) = self.cache.lock().unwrap().get(&ip) {
+            if resolved.elapsed() < CACHE {
+                return name.clone();
+            }
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        let resolver = self.resolver.clone();
+        thread::spawn(move|| { let _ = sender.send(resolver.reverse(ip)); });
+
+        let name = receiver.recv_timeout(self.timeout).unwrap_or(None);
+
+        let mut cache = self.cache.lock().unwrap();
+        cache.retain(|_, &mut (_, resolved)| resolved.elapsed() < CACHE);
+        cache.insert(ip, (name.clone(), Instant::now()));
+
+        name
+    }
+}
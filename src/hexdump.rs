@@ -0,0 +1,67 @@
+use std::io;
+
+use std::io::Read;
+
+use eve_logger::{hexdump, Message, MessagesWithBytes};
+
+use {output_root, Config, HandleCache};
+
+/// `--hexdump`, the messages of a client that also dumps every packet it reads to
+/// `{pid}/_raw.hex`, whether it parsed or not.
+///
+/// Each dump starts with a line giving the time, offset into the stream and size, and the error
+/// for packets that didn't parse. Bytes are written as they arrived, before `--redact` or
+/// `--anonymize-connection` get to them. Packets before the first Connection go to `0/_raw.hex`.
+pub struct Hexdump<'a, R> {
+    config: &'a Config,
+    messages: MessagesWithBytes<R>,
+    files: HandleCache,
+    pid: u64
+}
+
+impl<'a, R: Read> Hexdump<'a, R> {
+    pub fn new(config: &'a Config, messages: MessagesWithBytes<R>) -> Hexdump<'a, R> {
+        Hexdump { config, messages, files: HandleCache::new(), pid: 0 }
+    }
+
+    fn dump(&mut self, bytes: &[u8], error: Option<&io::Error>) -> io::Result<()> {
+        let offset = self.messages.position() - bytes.len() as u64;
+        let mut dump = format!("# {} {} bytes at offset {}", ::local_time(), bytes.len(), offset);
+
+        if let Some(error) = error {
+            dump.push_str(&format!(": {}", error));
+        }
+
+        dump.push('\n');
+        dump.push_str(&hexdump(offset, bytes));
+
+        let path = output_root(self.config).join(self.pid.to_string()).join("_raw.hex");
+        self.files.write(self.config, path, &[], dump.as_bytes())
+    }
+}
+
+impl<'a, R: Read> Iterator for Hexdump<'a, R> {
+    type Item = io::Result<Message>;
+
+    fn next(&mut self) -> Option<io::Result<Message>> {
+        match self.messages.next() {
+            Some(Ok((message, bytes))) => {
+                if let Message::Connection(ref connection) = message {
+                    self.pid = connection.pid;
+                }
+
+                Some(self.dump(&bytes, None).map(|_| message))
+            },
+            Some(Err(e)) => {
+                let bytes = self.messages.rejected().to_vec();
+
+                if let Err(e) = self.dump(&bytes, Some(&e)) {
+                    return Some(Err(e));
+                }
+
+                Some(Err(e))
+            },
+            None => None
+        }
+    }
+}
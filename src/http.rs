@@ -0,0 +1,122 @@
+use std::{io, thread};
+
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+
+use serde_json;
+
+#[derive(Clone, Serialize)]
+pub struct TailEntry {
+    pub pid: u64,
+    pub timestamp: String,
+    pub severity: String,
+    pub module: String,
+    pub channel: String,
+    pub message: String
+}
+
+/// The newest messages across all clients, bounded by `--tail-buffer`.
+pub struct TailBuffer {
+    capacity: usize,
+    entries: Mutex<VecDeque<TailEntry>>
+}
+
+impl TailBuffer {
+    pub fn new(capacity: usize) -> TailBuffer {
+        TailBuffer { capacity, entries: Mutex::new(VecDeque::with_capacity(capacity)) }
+    }
+
+    pub fn push(&self, entry: TailEntry) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+
+        entries.push_back(entry);
+    }
+
+    /// The last `count` entries, oldest first, optionally only those of one pid.
+    pub fn last(&self, count: usize, pid: Option<u64>) -> Vec<TailEntry> {
+        let entries = self.entries.lock().unwrap();
+
+        let mut result: Vec<TailEntry> = entries.iter().rev()
+            .filter(|entry| pid.is_none_or(|pid| entry.pid == pid))
+            .take(count).cloned().collect();
+
+        result.reverse();
+        result
+    }
+}
+
+/// Serves the status endpoints given by `--http`.
+///
+/// `GET /tail` returns the buffered messages as a JSON array, `?pid=<pid>` restricts it to one
+/// client and `?n=<count>` to the newest few.
+pub fn serve(listener: TcpListener, tail: Arc<TailBuffer>) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let tail = tail.clone();
+
+                thread::spawn(move|| {
+                    if let Err(e) = handle_request(stream, &tail) {
+                        info!("HTTP request failed {:?}", e)
+                    }
+                });
+            }
+            Err(e) => { info!("HTTP connection failed {:?}", e) }
+        }
+    }
+}
+
+fn handle_request(stream: TcpStream, tail: &TailBuffer) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    // Drain the headers, nothing in them matters to us.
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+
+    let target = request_line.split_whitespace().nth(1).unwrap_or("/");
+    let (path, query) = match target.find('?') {
+        Some(split) => (&target[..split], &target[split + 1..]),
+        None => (target, "")
+    };
+
+    let mut stream = stream;
+
+    match path {
+        "/tail" => {
+            let mut count = usize::MAX;
+            let mut pid = None;
+
+            for pair in query.split('&') {
+                let mut parts = pair.splitn(2, '=');
+
+                match (parts.next(), parts.next().map(|value| value.parse::<u64>())) {
+                    (Some("n"), Some(Ok(value))) => count = value as usize,
+                    (Some("pid"), Some(Ok(value))) => pid = Some(value),
+                    _ => ()
+                }
+            }
+
+            let body = serde_json::to_string(&tail.last(count, pid)).unwrap();
+            respond(&mut stream, "200 OK", "application/json", &body)
+        },
+        _ => respond(&mut stream, "404 Not Found", "text/plain", "Not found\n")
+    }
+}
+
+fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &str) -> io::Result<()> {
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, content_type, body.len(), body)
+}
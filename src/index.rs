@@ -0,0 +1,93 @@
+use std::{fs, io, process};
+
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use serde_json;
+
+use {output_root, Config, GroupBy};
+
+const NAME: &str = "index.json";
+
+#[derive(Serialize)]
+struct Index {
+    updated: String,
+    files: Vec<Entry>
+}
+
+#[derive(Serialize)]
+struct Entry {
+    /// Relative to the directory of the index, with `/` between components.
+    path: String,
+    size: u64
+}
+
+/// The pid directory that `path` was written under, as in `{pid}` or `{YYYY-MM-DD}/{pid}` below
+/// the output root, or `{exe}/{pid}` with `--group-by exe-pid`. `None` for files outside of one,
+/// like the `--format bincode` archives.
+pub fn pid_directory(config: &Config, path: &Path) -> Option<PathBuf> {
+    let root = output_root(config);
+    let relative = path.strip_prefix(&root).ok()?;
+    let depth = if config.date_dir { 2 } else { 1 } + if config.group_by == GroupBy::ExePid { 1 } else { 0 };
+
+    let mut directory = root;
+    let mut components = relative.components();
+
+    for _ in 0..depth {
+        match components.next() {
+            Some(Component::Normal(name)) => directory.push(name),
+            _ => return None
+        }
+    }
+
+    // The pid directory itself rather than a file in it.
+    components.next()?;
+    Some(directory)
+}
+
+/// `--index`, rewrites `index.json` in `directory` listing every file in it and its
+/// subdirectories with their sizes.
+///
+/// It is written when a client creates a file there and again when it disconnects, so sizes are
+/// only up to date as of the last disconnect. The index is replaced in one go, readers never see
+/// it half written.
+pub fn update(directory: &Path) -> io::Result<()> {
+    static TEMPORARY: AtomicUsize = AtomicUsize::new(0);
+
+    let mut files = Vec::new();
+    list(directory, "", &mut files)?;
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let index = Index { updated: ::local_time(), files };
+    let json = serde_json::to_string_pretty(&index).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    let temporary = directory.join(format!("{}.{}-{}.tmp", NAME, process::id(), TEMPORARY.fetch_add(1, Ordering::SeqCst)));
+    let result = fs::write(&temporary, json + "\n").and_then(|_| fs::rename(&temporary, directory.join(NAME)));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+
+    result
+}
+
+fn list(directory: &Path, prefix: &str, files: &mut Vec<Entry>) -> io::Result<()> {
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let metadata = entry.metadata()?;
+
+        // Half-written files of create_atomic and of this index.
+        if name.ends_with(".tmp") || (prefix.is_empty() && name == NAME) {
+            continue;
+        }
+
+        if metadata.is_dir() {
+            list(&entry.path(), &format!("{}{}/", prefix, name), files)?;
+        } else {
+            files.push(Entry { path: format!("{}{}", prefix, name), size: metadata.len() });
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,97 @@
+use std::io;
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use eve_logger::{Message, TextMessage};
+
+/// `--join-partial <ms>`, joins text messages that a client flushed halfway through a line.
+///
+/// A text message that doesn't end in a newline is held back. If the next message on the same
+/// (module, channel) arrives within the window of the last part, it is appended to the held one
+/// as is, and the result is held again unless it now ends in a newline. The joined message keeps
+/// the timestamp and severity of its first part.
+///
+/// Without a timer a held message is only let go once something else arrives after its window
+/// has passed, or when the client announces a new pid, disconnects or fails. Messages on other
+/// channels aren't held up by it, so the messages of a channel can come out after later ones of
+/// another.
+pub struct JoinPartial<I> {
+    messages: I,
+    window: Duration,
+    held: HashMap<(Arc<str>, Arc<str>), (TextMessage, Instant)>,
+    ready: VecDeque<io::Result<Message>>,
+    done: bool
+}
+
+impl<I: Iterator<Item = io::Result<Message>>> JoinPartial<I> {
+    pub fn new(messages: I, window: Duration) -> JoinPartial<I> {
+        JoinPartial { messages, window, held: HashMap::new(), ready: VecDeque::new(), done: false }
+    }
+
+    /// Lets go of every held message whose window has passed, or all of them with `all`.
+    fn release(&mut self, all: bool) {
+        let window = self.window;
+        let expired: Vec<(Arc<str>, Arc<str>)> = self.held.iter()
+            .filter(|&(_, &(_, last))| all || last.elapsed() >= window)
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in expired {
+            if let Some((message, _)) = self.held.remove(&key) {
+                self.ready.push_back(Ok(Message::Text(message)));
+            }
+        }
+    }
+
+    fn text(&mut self, message: TextMessage) {
+        let now = Instant::now();
+        let key = (message.module.clone(), message.channel.clone());
+
+        let message = match self.held.remove(&key) {
+            Some((mut held, last)) if now.duration_since(last) < self.window => {
+                held.message.push_str(&message.message);
+                held.truncated |= message.truncated;
+                held
+            },
+            Some((held, _)) => {
+                self.ready.push_back(Ok(Message::Text(held)));
+                message
+            },
+            None => message
+        };
+
+        if message.message.ends_with('\n') {
+            self.ready.push_back(Ok(Message::Text(message)));
+        } else {
+            self.held.insert(key, (message, now));
+        }
+    }
+}
+
+impl<I: Iterator<Item = io::Result<Message>>> Iterator for JoinPartial<I> {
+    type Item = io::Result<Message>;
+
+    fn next(&mut self) -> Option<io::Result<Message>> {
+        while self.ready.is_empty() && !self.done {
+            match self.messages.next() {
+                Some(Ok(Message::Text(message))) => {
+                    self.release(false);
+                    self.text(message);
+                },
+                Some(other) => {
+                    // A new pid or an error, either way nothing held belongs after it.
+                    self.release(true);
+                    self.ready.push_back(other);
+                },
+                None => {
+                    self.release(true);
+                    self.done = true;
+                }
+            }
+        }
+
+        self.ready.pop_front()
+    }
+}
@@ -0,0 +1,201 @@
+use std::{io, thread};
+
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender, TrySendError};
+use std::time::Duration;
+
+use chrono::naive::datetime::NaiveDateTime;
+
+use eve_logger::{ConnectionMessage, TextMessage};
+
+use rdkafka::ClientConfig;
+use rdkafka::client::ClientContext;
+use rdkafka::error::{KafkaError, KafkaResult, RDKafkaErrorCode};
+use rdkafka::producer::{BaseProducer, BaseRecord, DeliveryResult, Producer as _, ProducerContext};
+
+use serde_json;
+
+use sink::{self, SessionClose, Sink, TimeSource};
+use {stats, Config, KafkaKey};
+
+/// Records waiting for the producer thread at most, the ones past that are dropped.
+const QUEUE: usize = 10000;
+
+/// What a sink hands the producer thread.
+pub enum Queued {
+    Record { key: String, value: String },
+    Flush(Sender<()>)
+}
+
+/// Counts the records librdkafka gave up on, and passes its errors on as warnings.
+struct Context;
+
+impl ClientContext for Context {
+    fn error(&self, error: KafkaError, reason: &str) {
+        warn!("Kafka: {}: {}", error, reason);
+    }
+}
+
+impl ProducerContext for Context {
+    type DeliveryOpaque = ();
+
+    fn delivery(&self, result: &DeliveryResult<'_>, _: ()) {
+        if result.is_err() {
+            stats::STATS.dropped_record();
+        }
+    }
+}
+
+/// The `--kafka-brokers` producer, fed by a thread of its own so a slow or unreachable broker
+/// never holds up the clients or their files.
+///
+/// Clients queue their records without waiting, up to `QUEUE` of them. While the brokers are down
+/// librdkafka keeps what it was given and retries until its `message.timeout.ms`, once its own
+/// queue is full the producer thread waits for room and the client queue fills up. Records that
+/// don't fit, and those librdkafka gives up on, are counted as dropped records in the stats.
+pub struct Writer {
+    sender: SyncSender<Queued>
+}
+
+impl Writer {
+    pub fn open(brokers: &str, topic: &str) -> KafkaResult<Writer> {
+        let producer: BaseProducer<Context> = ClientConfig::new().set("bootstrap.servers", brokers).create_with_context(Context)?;
+
+        let (sender, receiver) = mpsc::sync_channel(QUEUE);
+        let topic = topic.to_string();
+        thread::spawn(move|| produce(producer, &topic, receiver));
+
+        Ok(Writer { sender })
+    }
+
+    /// A sink for one client that queues its messages for this producer.
+    pub fn sink<'a>(&self, config: &'a Config) -> KafkaSink<'a> {
+        KafkaSink { config, sender: self.sender.clone(), builds: HashMap::new() }
+    }
+
+    /// Waits up to 10s for the brokers to take everything queued so far.
+    pub fn flush(&self) {
+        let (done, waiting) = mpsc::channel();
+
+        if self.sender.send(Queued::Flush(done)).is_ok() {
+            let _ = waiting.recv();
+        }
+    }
+}
+
+fn produce(producer: BaseProducer<Context>, topic: &str, receiver: Receiver<Queued>) {
+    loop {
+        match receiver.recv_timeout(Duration::from_millis(100)) {
+            Ok(Queued::Record { key, value }) => {
+                let mut record = BaseRecord::to(topic).key(&key).payload(&value);
+
+                loop {
+                    match producer.send(record) {
+                        Ok(()) => break,
+                        Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), returned)) => {
+                            producer.poll(Duration::from_millis(100));
+                            record = returned;
+                        },
+                        Err((e, _)) => {
+                            warn!("Could not produce a Kafka record: {}", e);
+                            stats::STATS.dropped_record();
+                            break;
+                        }
+                    }
+                }
+            },
+            Ok(Queued::Flush(done)) => {
+                if let Err(e) = producer.flush(Duration::from_secs(10)) {
+                    warn!("Could not flush the Kafka producer: {}", e);
+                }
+
+                let _ = done.send(());
+            },
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => return
+        }
+
+        // Delivery reports and errors are only handed out while polling.
+        producer.poll(Duration::ZERO);
+    }
+}
+
+/// Each text message as a record keyed by the pid or, with `--kafka-key module`, the module, with
+/// the message as `--format json` writes it as its value.
+pub struct KafkaSink<'a> {
+    config: &'a Config,
+    sender: SyncSender<Queued>,
+    builds: HashMap<u64, String>
+}
+
+impl<'a> KafkaSink<'a> {
+    /// A sink queueing into `sender` rather than into a producer, to see what it queues.
+    #[cfg(test)]
+    pub fn with_sender(config: &'a Config, sender: SyncSender<Queued>) -> KafkaSink<'a> {
+        KafkaSink { config, sender, builds: HashMap::new() }
+    }
+}
+
+/// The key and value of the record for a message.
+pub fn record(config: &Config, pid: u64, build: Option<&str>, sequence: Option<u64>, timestamp: &NaiveDateTime, received: &NaiveDateTime, message: &TextMessage) -> io::Result<(String, String)> {
+    let key = match config.kafka_key {
+        KafkaKey::Pid => pid.to_string(),
+        KafkaKey::Module => message.module.to_string()
+    };
+
+    let received = Some(received).filter(|_| config.time_source == TimeSource::Both);
+    let value = sink::json_line(config, pid, build, sequence, timestamp, received, message)?;
+
+    Ok((key, value.trim_end_matches('\n').to_string()))
+}
+
+/// The record of a `--close-records` close, keyed by the pid whatever `--kafka-key` is as it has no
+/// module, with the close as JSON marked by `"event": "close"` as its value, or in a `close`
+/// envelope with `--json-envelope`.
+pub fn close_record(config: &Config, close: &SessionClose) -> io::Result<(String, String)> {
+    if config.json_envelope {
+        return Ok((close.pid.to_string(), sink::json_close(config, close)?.trim_end_matches('\n').to_string()));
+    }
+
+    #[derive(Serialize)]
+    struct Close<'a> {
+        event: &'static str,
+        #[serde(flatten)]
+        close: &'a SessionClose
+    }
+
+    let value = serde_json::to_string(&Close { event: "close", close })?;
+    Ok((close.pid.to_string(), value))
+}
+
+impl<'a> KafkaSink<'a> {
+    fn queue(&self, key: String, value: String) -> io::Result<()> {
+        match self.sender.try_send(Queued::Record { key, value }) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                stats::STATS.dropped_record();
+                Ok(())
+            },
+            Err(TrySendError::Disconnected(_)) => Err(io::Error::new(io::ErrorKind::BrokenPipe, "The Kafka producer has stopped"))
+        }
+    }
+}
+
+impl<'a> Sink for KafkaSink<'a> {
+    fn connection(&mut self, message: &ConnectionMessage, build: Option<&str>) -> io::Result<()> {
+        sink::remember_build(&mut self.builds, message.pid, build);
+        Ok(())
+    }
+
+    fn text(&mut self, pid: u64, sequence: Option<u64>, timestamp: &NaiveDateTime, received: &NaiveDateTime, message: &TextMessage) -> io::Result<()> {
+        let build = self.builds.get(&pid).map(|build| &build[..]);
+        let (key, value) = record(self.config, pid, build, sequence, timestamp, received, message)?;
+
+        self.queue(key, value)
+    }
+
+    fn close_session(&mut self, close: &SessionClose) -> io::Result<()> {
+        let (key, value) = close_record(self.config, close)?;
+        self.queue(key, value)
+    }
+}
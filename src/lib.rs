@@ -0,0 +1,1569 @@
+//! Parser and encoder for the wire format of the EVE Online log server.
+//!
+//! A client sends a stream of packets, each an 8 byte header (message type and padding) followed
+//! by the payload of that type. The first packet is a Connection message announcing the client,
+//! text messages follow, with long ones split over continuation packets.
+
+extern crate byteorder;
+#[cfg(feature = "tokio")]
+extern crate futures_core;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[cfg(feature = "tokio")]
+extern crate tokio;
+
+use std::{error, fmt, io, mem};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use std::io::{Read, Write};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt, LittleEndian};
+
+#[repr(C)]
+#[derive(Debug)]
+pub enum MessageType {
+    Connection = 0,
+    Simple = 1,
+    Large = 2,
+    Continuation = 3,
+    ContinuationEnd = 4
+}
+
+impl MessageType {
+    pub fn from_u32(value: u32) -> Option<MessageType> {
+        match value {
+            0 => Some(MessageType::Connection),
+            1 => Some(MessageType::Simple),
+            2 => Some(MessageType::Large),
+            3 => Some(MessageType::Continuation),
+            4 => Some(MessageType::ContinuationEnd),
+            _ => None
+        }
+    }
+}
+
+/// A packet that was read in full but makes no sense, like one of an unknown type or a
+/// continuation outside of a Large message.
+///
+/// The stream is still in sync after one, so `Messages` returns it wrapped in an `InvalidData`
+/// error and carries on with the next packet. Check for it with `is_malformed`.
+#[derive(Debug)]
+pub struct MalformedPacket(pub String);
+
+impl fmt::Display for MalformedPacket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Malformed packet: {}", self.0)
+    }
+}
+
+impl error::Error for MalformedPacket {}
+
+fn malformed(reason: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, MalformedPacket(reason))
+}
+
+/// Whether `error` is a `MalformedPacket`, after which reading may go on, also when a
+/// `ParseError` wraps it.
+pub fn is_malformed(error: &io::Error) -> bool {
+    match ParseError::of(error) {
+        Some(parse_error) => is_malformed(&parse_error.error),
+        None => error.get_ref().is_some_and(|inner| inner.is::<MalformedPacket>())
+    }
+}
+
+/// A packet the stream ended partway through, its header included, returned in an
+/// `UnexpectedEof` error. Check for it with `truncated_packet`.
+///
+/// `expected` is the size of the whole packet once its header has been read, and the size of the
+/// header before that.
+///
+/// ```
+/// use eve_logger::{truncated_packet, write_packet, Message, Messages, Severity, TextMessage};
+///
+/// let message = Message::Text(TextMessage {
+///     timestamp: 1500000000, severity: Severity::Info, module: "module".into(),
+///     channel: "channel".into(), message: "hello".to_string(), truncated: false
+/// });
+///
+/// let mut bytes = Vec::new();
+/// write_packet(&mut bytes, &message).unwrap();
+/// let size = bytes.len();
+/// write_packet(&mut bytes, &message).unwrap();
+///
+/// let mut messages = Messages::new(&bytes[..size + 20]);
+/// assert!(messages.next().unwrap().is_ok());
+///
+/// let error = messages.next().unwrap().unwrap_err();
+/// let truncated = truncated_packet(&error).unwrap();
+///
+/// assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+/// assert_eq!((truncated.got, truncated.expected), (20, size));
+/// assert!(messages.next().is_none());
+/// ```
+#[derive(Debug)]
+pub struct TruncatedPacket {
+    pub got: usize,
+    pub expected: usize
+}
+
+impl fmt::Display for TruncatedPacket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The stream ended after {} of the {} bytes of the packet", self.got, self.expected)
+    }
+}
+
+impl error::Error for TruncatedPacket {}
+
+/// The `TruncatedPacket` `error` is, also when a `ParseError` wraps it.
+pub fn truncated_packet(error: &io::Error) -> Option<&TruncatedPacket> {
+    match ParseError::of(error) {
+        Some(parse_error) => truncated_packet(&parse_error.error),
+        None => error.get_ref().and_then(|inner| inner.downcast_ref::<TruncatedPacket>())
+    }
+}
+
+/// Where in the stream a message failed to parse, wrapped around every error `Messages` and
+/// `MessageStream` return.
+///
+/// The wrapping `io::Error` keeps the kind of the original, so `is_malformed` and checks for
+/// `UnexpectedEof` work as they would on the original.
+///
+/// ```
+/// use eve_logger::{write_packet, Message, Messages, ParseError, Severity, TextMessage};
+///
+/// let message = Message::Text(TextMessage {
+///     timestamp: 1500000000, severity: Severity::Info, module: "module".into(),
+///     channel: "channel".into(), message: "hello".to_string(), truncated: false
+/// });
+///
+/// let mut bytes = Vec::new();
+/// write_packet(&mut bytes, &message).unwrap();
+/// let second = bytes.len();
+/// write_packet(&mut bytes, &message).unwrap();
+///
+/// // The type of the second packet.
+/// bytes[second] = 9;
+///
+/// let mut messages = Messages::new(&bytes[..]);
+/// assert!(messages.next().unwrap().is_ok());
+///
+/// let error = messages.next().unwrap().unwrap_err();
+/// let parse_error = ParseError::of(&error).unwrap();
+///
+/// assert_eq!((parse_error.offset, parse_error.chunks), (second as u64, 0));
+/// assert_eq!(error.to_string(), format!("Malformed packet: Unknown message type 9 at byte {}", second));
+/// ```
+#[derive(Debug)]
+pub struct ParseError {
+    /// The offset of the Connection, Simple or Large packet the message starts with, counted from
+    /// the start of the stream.
+    pub offset: u64,
+    /// How many chunks of a Large message had been assembled when it failed, 0 for every other
+    /// message.
+    pub chunks: usize,
+    pub error: io::Error
+}
+
+impl ParseError {
+    /// The `ParseError` `error` wraps, if any.
+    pub fn of(error: &io::Error) -> Option<&ParseError> {
+        error.get_ref().and_then(|inner| inner.downcast_ref::<ParseError>())
+    }
+
+    fn wrap(error: io::Error, offset: u64, chunks: usize) -> io::Error {
+        io::Error::new(error.kind(), ParseError { offset, chunks, error })
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.chunks {
+            0 => write!(f, "{} at byte {}", self.error, self.offset),
+            chunks => write!(f, "{} at byte {}, {} chunks into a Large message", self.error, self.offset, chunks)
+        }
+    }
+}
+
+impl error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Severity {
+    Info, Notice, Warn, Error, Unknown(u32)
+}
+
+impl Severity {
+    pub fn from_u32(value: u32) -> Severity {
+        match value {
+            0 => Severity::Info,
+            1 => Severity::Notice,
+            2 => Severity::Warn,
+            3 => Severity::Error,
+            e => Severity::Unknown(e)
+        }
+    }
+
+    pub fn to_u32(&self) -> u32 {
+        match *self {
+            Severity::Info => 0,
+            Severity::Notice => 1,
+            Severity::Warn => 2,
+            Severity::Error => 3,
+            Severity::Unknown(e) => e
+        }
+    }
+}
+
+#[repr(C)]
+struct RawConnectionMessage {
+    version: u32,
+    pid: u64,
+    machine_name: [u8; 32],
+    executable_path: [u8; 260]
+}
+
+#[repr(C)]
+struct RawTextMessage {
+    timestamp: u64,
+    severity: u32,
+    module: [u8; 32],
+    channel: [u8; 32],
+    message: [u8; 256]
+}
+
+impl RawTextMessage {
+    /// Whether both packets carry the fields of the same message, as the continuations of a Large
+    /// message do.
+    fn same_message(&self, other: &RawTextMessage) -> bool {
+        self.timestamp == other.timestamp && self.severity == other.severity && self.module == other.module && self.channel == other.channel
+    }
+}
+
+/// The port clients connect to unless told otherwise.
+pub const DEFAULT_PORT: u16 = 3273;
+
+/// The bytes in front of every payload: the message type and padding, both u32.
+pub const HEADER_SIZE: usize = 2 * mem::size_of::<u32>();
+
+/// The payload of a Connection packet, trailing alignment padding included.
+pub const CONNECTION_PAYLOAD_SIZE: usize = mem::size_of::<RawConnectionMessage>();
+
+/// The payload of every text packet, continuations included, trailing alignment padding included.
+pub const TEXT_PAYLOAD_SIZE: usize = mem::size_of::<RawTextMessage>();
+
+/// Where one field of a packet payload is in this build, see `payload_layouts`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FieldLayout {
+    pub name: &'static str,
+    pub offset: usize,
+    pub size: usize
+}
+
+/// How this build lays out a packet payload, which is what the parser reads it as.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PayloadLayout {
+    pub name: &'static str,
+    pub fields: Vec<FieldLayout>,
+    /// Trailing alignment padding included.
+    pub size: usize
+}
+
+impl fmt::Display for PayloadLayout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}: {} bytes", self.name, self.size)?;
+
+        for field in &self.fields {
+            writeln!(f, "  {:<16} offset {:>3} size {:>3}", field.name, field.offset, field.size)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn size_of_field<T, F>(_: fn(&T) -> &F) -> usize {
+    mem::size_of::<F>()
+}
+
+macro_rules! field_layout {
+    ($payload:ident, $field:ident) => {
+        FieldLayout { name: stringify!($field), offset: mem::offset_of!($payload, $field), size: size_of_field(|payload: &$payload| &payload.$field) }
+    }
+}
+
+/// The offset and size of every field of the Connection and text payloads, as the compiler laid
+/// them out for this target. The parser reads packets as these structs, so they have to match the
+/// wire format, padding included.
+///
+/// ```
+/// use eve_logger::{payload_layouts, CONNECTION_PAYLOAD_SIZE, TEXT_PAYLOAD_SIZE};
+///
+/// let layouts = payload_layouts();
+/// assert_eq!(layouts[0].size, CONNECTION_PAYLOAD_SIZE);
+/// assert_eq!(layouts[1].size, TEXT_PAYLOAD_SIZE);
+///
+/// let pid = layouts[0].fields.iter().find(|field| field.name == "pid").unwrap();
+/// assert_eq!((pid.offset, pid.size), (8, 8));
+/// ```
+pub fn payload_layouts() -> Vec<PayloadLayout> {
+    vec![
+        PayloadLayout {
+            name: "RawConnectionMessage",
+            fields: vec![
+                field_layout!(RawConnectionMessage, version),
+                field_layout!(RawConnectionMessage, pid),
+                field_layout!(RawConnectionMessage, machine_name),
+                field_layout!(RawConnectionMessage, executable_path)
+            ],
+            size: CONNECTION_PAYLOAD_SIZE
+        },
+        PayloadLayout {
+            name: "RawTextMessage",
+            fields: vec![
+                field_layout!(RawTextMessage, timestamp),
+                field_layout!(RawTextMessage, severity),
+                field_layout!(RawTextMessage, module),
+                field_layout!(RawTextMessage, channel),
+                field_layout!(RawTextMessage, message)
+            ],
+            size: TEXT_PAYLOAD_SIZE
+        }
+    ]
+}
+
+/// What the body of a Large message starts with when its continuations can't all be its own, so a
+/// message that lost packets on the way doesn't pass for complete.
+///
+/// Packets carry no sequence number, the padding in the header is always zero, but every
+/// continuation repeats the timestamp, severity, module and channel of its Large packet, and only
+/// the last buffer of a message is ever shorter than the buffer. A continuation that differs from
+/// its Large packet in any of those, or a buffer other than the last that isn't full, marks the
+/// message. What was assembled is kept after the marker, as far as the limits allow. A continuation
+/// of the same message that went missing, or two that swapped places, go unnoticed.
+///
+/// ```
+/// use eve_logger::{read_packet, write_packet, Limits, Message, Severity, TextMessage};
+/// use eve_logger::{HEADER_SIZE, REASSEMBLY_GAP, TEXT_PAYLOAD_SIZE};
+///
+/// let text = |module: &str| Message::Text(TextMessage {
+///     timestamp: 1500000000, severity: Severity::Info, module: module.into(),
+///     channel: "channel".into(), message: "x".repeat(300), truncated: false
+/// });
+///
+/// let (mut first, mut second) = (Vec::new(), Vec::new());
+/// write_packet(&mut first, &text("first")).unwrap();
+/// write_packet(&mut second, &text("second")).unwrap();
+///
+/// // The end of the first message and the start of the second were lost.
+/// let packet = HEADER_SIZE + TEXT_PAYLOAD_SIZE;
+/// let bytes = [&first[..packet], &second[packet..]].concat();
+///
+/// match read_packet(&mut &bytes[..], 1, &Limits::default()).unwrap() {
+///     Message::Text(message) => assert_eq!(message.message, format!("{}{}", REASSEMBLY_GAP, "x".repeat(300))),
+///     _ => unreachable!()
+/// }
+/// ```
+pub const REASSEMBLY_GAP: &str = "[reassembly-gap] ";
+
+enum RawMessage {
+    RawConnection(RawConnectionMessage), RawText(MessageType, RawTextMessage)
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ConnectionMessage {
+    pub version: u32, pub pid: u64, pub machine_name: String, pub executable_path: String
+}
+
+/// One line for logs, `pid=1234 machine=HOST exe=exefile.exe version=1`, `exe` being the file name
+/// of `executable_path` with either kind of separator. A value that is empty, or has whitespace or
+/// a quote in it, is quoted so the line still splits on spaces.
+///
+/// ```
+/// use eve_logger::ConnectionMessage;
+///
+/// let mut message = ConnectionMessage {
+///     version: 1, pid: 1234, machine_name: "HOST".to_string(),
+///     executable_path: "C:\\EVE\\bin\\exefile.exe".to_string()
+/// };
+///
+/// assert_eq!(message.to_string(), "pid=1234 machine=HOST exe=exefile.exe version=1");
+///
+/// message.machine_name.clear();
+/// message.executable_path = "/opt/eve/My Game.exe".to_string();
+///
+/// assert_eq!(message.to_string(), r#"pid=1234 machine="" exe="My Game.exe" version=1"#);
+/// ```
+impl fmt::Display for ConnectionMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let exe = self.executable_path.rsplit(['\\', '/']).next().unwrap_or("");
+
+        write!(f, "pid={} machine={} exe={} version={}", self.pid, LogValue(&self.machine_name), LogValue(exe), self.version)
+    }
+}
+
+/// A value of a `key=value` log line, quoted when it wouldn't survive splitting on spaces.
+struct LogValue<'a>(&'a str);
+
+impl<'a> fmt::Display for LogValue<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.is_empty() || self.0.contains(|c: char| c.is_whitespace() || c == '"') {
+            write!(f, "{:?}", self.0)
+        } else {
+            f.write_str(self.0)
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TextMessage {
+    pub timestamp: u64, pub severity: Severity, pub module: Arc<str>, pub channel: Arc<str>, pub message: String,
+    /// Set when the assembled message hit one of the reassembly limits and was cut short.
+    pub truncated: bool
+}
+
+impl TextMessage {
+    /// Whether `channel` is text, rather than the number some protocol versions put in the
+    /// channel buffer. A channel holding a control character (below U+0020, or U+007F) isn't.
+    ///
+    /// For such a channel the parser keeps the bytes of the buffer up to its last non-zero one,
+    /// each as the character of the same value, so `channel_id` can read the number back.
+    pub fn channel_is_textual(&self) -> bool {
+        !self.channel.chars().any(|c| c < ' ' || c == '\u{7f}')
+    }
+
+    /// The number in the channel buffer, read as a little endian integer, when it isn't text.
+    /// `None` for a textual channel or one too long to be a u64.
+    ///
+    /// ```
+    /// use eve_logger::{parse_packet, write_packet, Limits, Message, Severity, TextMessage};
+    ///
+    /// let message = Message::Text(TextMessage {
+    ///     timestamp: 1500000000, severity: Severity::Info, module: "module".into(),
+    ///     channel: "\u{5}\u{0}\u{1}".into(), message: "hello".to_string(), truncated: false
+    /// });
+    ///
+    /// let mut bytes = Vec::new();
+    /// write_packet(&mut bytes, &message).unwrap();
+    ///
+    /// match parse_packet(&bytes, 1, &Limits::default()).unwrap().0 {
+    ///     Message::Text(text) => {
+    ///         assert!(!text.channel_is_textual());
+    ///         assert_eq!(text.channel_id(), Some(0x010005));
+    ///     },
+    ///     _ => unreachable!()
+    /// }
+    /// ```
+    pub fn channel_id(&self) -> Option<u64> {
+        if self.channel_is_textual() || self.channel.chars().count() > 8 || self.channel.chars().any(|c| c > '\u{ff}') {
+            return None;
+        }
+
+        Some(self.channel.chars().rev().fold(0, |id, c| id << 8 | c as u64))
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub enum Message {
+    Connection(ConnectionMessage), Text(TextMessage)
+}
+
+/// How the string buffers of a packet are encoded.
+///
+/// The same encoding applies to every string field of every message type, Connection fields
+/// included, since it is a property of the client rather than of a field.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    /// Little endian UTF-16, terminated by a NUL code unit rather than a NUL byte.
+    Utf16Le,
+    /// UTF-16LE or UTF-8, guessed for each buffer on its own by `Encoding::detect`.
+    Auto
+}
+
+impl Encoding {
+    /// The encoding `buffer` is read with, for `Auto` a guess from its contents.
+    ///
+    /// A buffer is taken for UTF-16LE when more than half of the code units before its UTF-16
+    /// terminator have a zero high byte and a non-zero low one, as ASCII and Latin-1 text has in
+    /// UTF-16LE and UTF-8 text only has where a NUL is followed by stale bytes. Anything else is
+    /// read as UTF-8, including an empty buffer.
+    ///
+    /// The guess goes wrong for UTF-16LE text that is mostly outside of Latin-1, such as Chinese
+    /// or Cyrillic, which has few zero high bytes and is read as UTF-8, and for a short UTF-8
+    /// string followed by stale bytes that happen to alternate with NULs, which is read as
+    /// UTF-16LE. A one-character ASCII string reads the same either way. The body of a Large
+    /// message is guessed from its first buffer and its continuations read the same way.
+    ///
+    /// ```
+    /// use eve_logger::Encoding;
+    ///
+    /// assert_eq!(Encoding::Auto.detect(b"HOST\0\0\0\0"), Encoding::Utf8);
+    /// assert_eq!(Encoding::Auto.detect(b"H\0O\0S\0T\0\0\0"), Encoding::Utf16Le);
+    /// assert_eq!(Encoding::Utf8.detect(b"H\0O\0S\0T\0\0\0"), Encoding::Utf8);
+    /// ```
+    pub fn detect(self, buffer: &[u8]) -> Encoding {
+        if self != Encoding::Auto {
+            return self;
+        }
+
+        let units = &buffer[..Encoding::Utf16Le.terminated_len(buffer)];
+        let narrow = units.chunks(2).filter(|unit| unit[0] != 0 && unit[1] == 0).count();
+
+        if narrow * 2 > units.len() / 2 { Encoding::Utf16Le } else { Encoding::Utf8 }
+    }
+
+    /// The length of the contents of `buffer`, up to its terminator.
+    fn terminated_len(self, buffer: &[u8]) -> usize {
+        match self {
+            Encoding::Utf8 => buffer.iter().position(|x| *x == 0).unwrap_or(buffer.len()),
+            Encoding::Utf16Le => buffer.chunks(2).position(|unit| unit == [0, 0]).map_or(buffer.len() & !1, |unit| unit * 2),
+            Encoding::Auto => self.detect(buffer).terminated_len(buffer)
+        }
+    }
+
+    /// The length of `bytes` without a trailing, incomplete character.
+    fn complete_len(self, bytes: &[u8]) -> usize {
+        match self {
+            Encoding::Utf8 => complete_utf8_len(bytes),
+            Encoding::Utf16Le => {
+                let even = bytes.len() & !1;
+
+                // A high surrogate waits for the low surrogate that was cut off.
+                match even.checked_sub(2).map(|last| u16::from_le_bytes([bytes[last], bytes[last + 1]])) {
+                    Some(0xd800..=0xdbff) => even - 2,
+                    _ => even
+                }
+            },
+            Encoding::Auto => self.detect(bytes).complete_len(bytes)
+        }
+    }
+
+    /// Decodes straight into the returned string, without collecting the bytes or code units first.
+    /// UTF-16 is decoded twice, once to size the string exactly so it is never grown.
+    fn decode(self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            Encoding::Utf16Le => {
+                let chars = || {
+                    let units = bytes.chunks(2).map(|unit| u16::from_le_bytes([unit[0], *unit.get(1).unwrap_or(&0)]));
+                    char::decode_utf16(units).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                };
+
+                let mut decoded = String::with_capacity(chars().map(char::len_utf8).sum());
+                decoded.extend(chars());
+                decoded
+            },
+            Encoding::Auto => self.detect(bytes).decode(bytes)
+        }
+    }
+
+    /// Like `decode`, but valid UTF-8 keeps the allocation of `bytes`.
+    fn decode_owned(self, bytes: Vec<u8>) -> String {
+        match self {
+            Encoding::Utf8 => String::from_utf8(bytes).unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned()),
+            Encoding::Utf16Le => self.decode(&bytes),
+            Encoding::Auto => self.detect(&bytes).decode_owned(bytes)
+        }
+    }
+}
+
+/// The byte order of the header words and the integer fields of a packet.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Endianness {
+    #[default]
+    Little,
+    Big
+}
+
+impl Endianness {
+    fn read_u32(self, reader: &mut dyn Read) -> io::Result<u32> {
+        match self {
+            Endianness::Little => reader.read_u32::<LittleEndian>(),
+            Endianness::Big => reader.read_u32::<BigEndian>()
+        }
+    }
+
+    fn read_u64(self, reader: &mut dyn Read) -> io::Result<u64> {
+        match self {
+            Endianness::Little => reader.read_u64::<LittleEndian>(),
+            Endianness::Big => reader.read_u64::<BigEndian>()
+        }
+    }
+}
+
+/// Where the message type is in the 8 byte header of a packet.
+///
+/// Every client seen so far sends the type as a u32 followed by a u32 of padding, the other
+/// layouts are for builds that may not: one that writes the type as a u64, where padding that
+/// isn't zero makes the type unknown, and one with the two words the other way around. Either
+/// way the words are read in the `Endianness` of the packet.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum HeaderLayout {
+    #[default]
+    TypeU32,
+    TypeU64,
+    Swapped
+}
+
+impl HeaderLayout {
+    fn message_type(self, header: &[u8], endianness: Endianness) -> io::Result<u64> {
+        let mut words = header;
+
+        match self {
+            HeaderLayout::TypeU32 => endianness.read_u32(&mut words).map(u64::from),
+            HeaderLayout::TypeU64 => endianness.read_u64(&mut words),
+            HeaderLayout::Swapped => endianness.read_u32(&mut &header[4..]).map(u64::from)
+        }
+    }
+}
+
+/// Which types of message are read as messages, the others being read off the stream and skipped.
+///
+/// A Large message that isn't wanted still has all of its continuations read, so the stream stays
+/// in sync. Connection messages are always read as they say which pid the messages after them
+/// are from, `connection` only says whether the reader wants to hear about them beyond that.
+///
+/// ```
+/// use eve_logger::{write_packet, Limits, Message, MessageTypes, Messages, Severity, TextMessage};
+///
+/// let text = |message: &str| Message::Text(TextMessage {
+///     timestamp: 1500000000, severity: Severity::Info, module: "module".into(),
+///     channel: "channel".into(), message: message.to_string(), truncated: false
+/// });
+///
+/// let mut bytes = Vec::new();
+/// write_packet(&mut bytes, &text(&"x".repeat(600))).unwrap();
+/// write_packet(&mut bytes, &text("simple")).unwrap();
+///
+/// let limits = Limits { message_types: MessageTypes { large: false, ..MessageTypes::default() }, ..Limits::default() };
+/// let messages: Vec<Message> = Messages::with_limits(&bytes[..], limits).collect::<Result<_, _>>().unwrap();
+///
+/// assert_eq!(messages, [text("simple")]);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MessageTypes {
+    pub connection: bool,
+    pub simple: bool,
+    pub large: bool
+}
+
+impl Default for MessageTypes {
+    fn default() -> MessageTypes {
+        MessageTypes { connection: true, simple: true, large: true }
+    }
+}
+
+/// Caps on how large a message assembled from continuations may grow, and how its bytes are
+/// decoded.
+///
+/// Whichever limit trips first truncates the message, the remaining continuation packets are still
+/// read so the stream stays in sync.
+#[derive(Clone, Debug, Default)]
+pub struct Limits {
+    pub max_message_bytes: Option<usize>,
+    pub max_continuation_chunks: Option<usize>,
+    pub encoding: Encoding,
+    pub endianness: Endianness,
+    pub header_layout: HeaderLayout,
+    pub message_types: MessageTypes
+}
+
+/// Passes through reads while counting the bytes consumed so far.
+pub struct CountingReader<R> {
+    inner: R,
+    position: u64
+}
+
+impl<R: Read> CountingReader<R> {
+    pub fn new(inner: R) -> CountingReader<R> {
+        CountingReader { inner, position: 0 }
+    }
+
+    /// How many bytes have been read from the underlying reader.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.position += read as u64;
+
+        Ok(read)
+    }
+}
+
+/// Keeps a copy of every byte read through it until `take` is called.
+pub struct RecordingReader<R> {
+    inner: R,
+    recorded: Vec<u8>
+}
+
+impl<R: Read> RecordingReader<R> {
+    pub fn new(inner: R) -> RecordingReader<R> {
+        RecordingReader { inner, recorded: Vec::new() }
+    }
+
+    /// The bytes read since the last call.
+    pub fn take(&mut self) -> Vec<u8> {
+        mem::take(&mut self.recorded)
+    }
+}
+
+impl<R: Read> Read for RecordingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.recorded.extend_from_slice(&buf[..read]);
+
+        Ok(read)
+    }
+}
+
+/// Iterates over the messages of a stream, following the protocol version announced by its
+/// Connection messages.
+///
+/// Ends when the stream ends on a packet boundary. A `MalformedPacket` is returned and skipped
+/// over. Any other error, including the `UnexpectedEof` of a stream that ends partway through a
+/// packet, is returned once and ends the iteration, as the stream can't be trusted to be in sync
+/// after it. Reads that a signal interrupts (`ErrorKind::Interrupted`) are retried, they are never
+/// returned. Every error comes wrapped in a `ParseError` saying where its message started.
+///
+/// The modules and channels of the stream are interned, messages with the same one share its
+/// `Arc<str>`.
+///
+/// ```
+/// use std::sync::Arc;
+///
+/// use eve_logger::{write_packet, Message, Messages, Severity, TextMessage};
+///
+/// let mut bytes = Vec::new();
+///
+/// for text in &["first", "second"] {
+///     let message = Message::Text(TextMessage {
+///         timestamp: 1500000000, severity: Severity::Info, module: "module".into(),
+///         channel: "channel".into(), message: text.to_string(), truncated: false
+///     });
+///
+///     write_packet(&mut bytes, &message).unwrap();
+/// }
+///
+/// let texts: Vec<TextMessage> = Messages::new(&bytes[..]).map(|message| match message.unwrap() {
+///     Message::Text(text) => text,
+///     _ => unreachable!()
+/// }).collect();
+///
+/// assert!(Arc::ptr_eq(&texts[0].module, &texts[1].module));
+/// assert!(Arc::ptr_eq(&texts[0].channel, &texts[1].channel));
+/// ```
+pub struct Messages<R> {
+    reader: CountingReader<R>,
+    version: u32,
+    limits: Limits,
+    interner: Interner,
+    done: bool
+}
+
+impl<R: Read> Messages<R> {
+    pub fn new(reader: R) -> Messages<R> {
+        Messages::with_limits(reader, Limits::default())
+    }
+
+    pub fn with_limits(reader: R, limits: Limits) -> Messages<R> {
+        Messages { reader: CountingReader::new(reader), version: 1, limits, interner: Interner::new(), done: false }
+    }
+
+    /// How many bytes of the stream have been consumed so far.
+    pub fn position(&self) -> u64 {
+        self.reader.position()
+    }
+}
+
+impl<R: Read> Iterator for Messages<R> {
+    type Item = io::Result<Message>;
+
+    fn next(&mut self) -> Option<io::Result<Message>> {
+        if self.done {
+            return None;
+        }
+
+        let start = self.reader.position();
+        let mut chunks = 0;
+
+        match read_packet_interned(&mut self.reader, self.version, &self.limits, &mut self.interner, &mut |chunk| chunks = chunk.index + 1) {
+            Ok(message) => {
+                if let Message::Connection(ref connection) = message {
+                    self.version = connection.version;
+                }
+
+                Some(Ok(message))
+            },
+            Err(e) => {
+                self.done = !is_malformed(&e);
+
+                if e.kind() == io::ErrorKind::UnexpectedEof && self.reader.position() == start {
+                    None
+                } else {
+                    Some(Err(ParseError::wrap(e, start, chunks)))
+                }
+            }
+        }
+    }
+}
+
+/// Like `Messages`, but also yields the exact bytes each message was parsed from, continuation
+/// packets included.
+pub struct MessagesWithBytes<R> {
+    messages: Messages<RecordingReader<R>>,
+    rejected: Vec<u8>
+}
+
+impl<R: Read> MessagesWithBytes<R> {
+    pub fn new(reader: R) -> MessagesWithBytes<R> {
+        MessagesWithBytes::with_limits(reader, Limits::default())
+    }
+
+    pub fn with_limits(reader: R, limits: Limits) -> MessagesWithBytes<R> {
+        MessagesWithBytes { messages: Messages::with_limits(RecordingReader::new(reader), limits), rejected: Vec::new() }
+    }
+
+    pub fn position(&self) -> u64 {
+        self.messages.position()
+    }
+
+    /// The bytes behind the error `next` returned last, which aren't part of the error itself.
+    /// Empty after a message, or when the stream ended on a packet boundary.
+    pub fn rejected(&self) -> &[u8] {
+        &self.rejected
+    }
+}
+
+impl<R: Read> Iterator for MessagesWithBytes<R> {
+    type Item = io::Result<(Message, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<io::Result<(Message, Vec<u8>)>> {
+        let next = self.messages.next();
+        let bytes = self.messages.reader.get_mut().take();
+
+        match next {
+            Some(Ok(message)) => {
+                self.rejected.clear();
+                Some(Ok((message, bytes)))
+            },
+            next => {
+                self.rejected = bytes;
+                next.map(|result| result.map(|message| (message, Vec::new())))
+            }
+        }
+    }
+}
+
+/// The async counterpart of `Messages`, a `Stream` of the messages of an `AsyncRead`.
+///
+/// Bytes are buffered until a whole message has arrived, continuations included, and then parsed
+/// like `Messages` does, so it ends and fails in the same places: at the end of the stream on a
+/// packet boundary, once with the error otherwise, and skipping over a `MalformedPacket`.
+///
+/// ```edition2018
+/// use eve_logger::{write_packet, Message, MessageStream, Severity, TextMessage};
+///
+/// let mut bytes = Vec::new();
+///
+/// for text in &["first", "second"] {
+///     let message = Message::Text(TextMessage {
+///         timestamp: 1500000000, severity: Severity::Info, module: "module".into(),
+///         channel: "channel".into(), message: text.to_string(), truncated: false
+///     });
+///
+///     write_packet(&mut bytes, &message).unwrap();
+/// }
+///
+/// let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+///
+/// let seen = runtime.block_on(async {
+///     let mut stream = MessageStream::new(&bytes[..]);
+///     let mut seen = Vec::new();
+///
+///     while let Some(message) = stream.next().await {
+///         if let Message::Text(text) = message.unwrap() {
+///             seen.push(text.message);
+///         }
+///     }
+///
+///     seen
+/// });
+///
+/// assert_eq!(seen, ["first", "second"]);
+/// ```
+#[cfg(feature = "tokio")]
+pub struct MessageStream<R> {
+    reader: R,
+    buffer: Vec<u8>,
+    /// The offset in the stream of the start of `buffer`.
+    position: u64,
+    version: u32,
+    limits: Limits,
+    interner: Interner,
+    eof: bool,
+    done: bool
+}
+
+#[cfg(feature = "tokio")]
+impl<R: tokio::io::AsyncRead + Unpin> MessageStream<R> {
+    pub fn new(reader: R) -> MessageStream<R> {
+        MessageStream::with_limits(reader, Limits::default())
+    }
+
+    pub fn with_limits(reader: R, limits: Limits) -> MessageStream<R> {
+        MessageStream { reader, buffer: Vec::new(), position: 0, version: 1, limits, interner: Interner::new(), eof: false, done: false }
+    }
+
+    /// The next message, for `while let Some(message) = stream.next().await` without importing
+    /// a `StreamExt`.
+    pub fn next<'a>(&'a mut self) -> impl std::future::Future<Output = Option<io::Result<Message>>> + 'a {
+        std::future::poll_fn(move |cx| self.poll_next_message(cx))
+    }
+
+    fn poll_next_message(&mut self, cx: &mut std::task::Context) -> std::task::Poll<Option<io::Result<Message>>> {
+        use std::task::Poll;
+
+        use tokio::io::ReadBuf;
+
+        loop {
+            if self.done {
+                return Poll::Ready(None);
+            }
+
+            if !self.buffer.is_empty() {
+                let mut unread = &self.buffer[..];
+                let mut chunks = 0;
+
+                match read_packet_interned(&mut unread, self.version, &self.limits, &mut self.interner, &mut |chunk| chunks = chunk.index + 1) {
+                    Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof && !self.eof => (),
+                    result => {
+                        let start = self.position;
+                        let consumed = self.buffer.len() - unread.len();
+                        self.buffer.drain(..consumed);
+                        self.position += consumed as u64;
+
+                        match result {
+                            Ok(Message::Connection(ref connection)) => self.version = connection.version,
+                            Err(ref e) if !is_malformed(e) => self.done = true,
+                            _ => ()
+                        }
+
+                        return Poll::Ready(Some(result.map_err(|e| ParseError::wrap(e, start, chunks))));
+                    }
+                }
+            } else if self.eof {
+                return Poll::Ready(None);
+            }
+
+            let mut chunk = [0; 4096];
+            let mut read = ReadBuf::new(&mut chunk);
+
+            match std::pin::Pin::new(&mut self.reader).poll_read(cx, &mut read) {
+                Poll::Ready(Ok(())) if read.filled().is_empty() => self.eof = true,
+                Poll::Ready(Ok(())) => self.buffer.extend_from_slice(read.filled()),
+                // A signal cut the read short, the stream itself is fine.
+                Poll::Ready(Err(ref e)) if e.kind() == io::ErrorKind::Interrupted => (),
+                Poll::Ready(Err(e)) => {
+                    self.done = true;
+                    return Poll::Ready(Some(Err(e)));
+                },
+                Poll::Pending => return Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<R: tokio::io::AsyncRead + Unpin> futures_core::Stream for MessageStream<R> {
+    type Item = io::Result<Message>;
+
+    fn poll_next(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context) -> std::task::Poll<Option<io::Result<Message>>> {
+        self.get_mut().poll_next_message(cx)
+    }
+}
+
+/// Formats `bytes` as a hex dump of 16 bytes a line, each line starting with its offset counted
+/// from `offset` and ending with the printable ASCII of those bytes.
+///
+/// ```
+/// assert_eq!(eve_logger::hexdump(16, b"EVE\0"), "00000010  45 56 45 00                                       |EVE.|\n");
+/// ```
+pub fn hexdump(offset: u64, bytes: &[u8]) -> String {
+    let mut dump = String::with_capacity(bytes.len().div_ceil(16) * 78);
+
+    for (i, line) in bytes.chunks(16).enumerate() {
+        dump.push_str(&format!("{:08x} ", offset + 16 * i as u64));
+
+        for column in 0..16 {
+            match line.get(column) {
+                Some(byte) => dump.push_str(&format!(" {:02x}", byte)),
+                None => dump.push_str("   ")
+            }
+
+            if column == 7 {
+                dump.push(' ');
+            }
+        }
+
+        dump.push_str("  |");
+        dump.extend(line.iter().map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' }));
+        dump.push_str("|\n");
+    }
+
+    dump
+}
+
+/// Parses messages from `reader` until it ends, calling `f` with each of them.
+///
+/// The protocol version announced by Connection messages is picked up along the way. Returns
+/// `Ok(())` when the stream ends on a packet boundary and the error otherwise, including the
+/// `UnexpectedEof` of a stream that ends partway through a packet.
+///
+/// ```
+/// use eve_logger::{run_stream, write_packet, Message, Severity, TextMessage};
+///
+/// let mut bytes = Vec::new();
+///
+/// for text in &["first", "second"] {
+///     let message = Message::Text(TextMessage {
+///         timestamp: 1500000000, severity: Severity::Info, module: "module".into(),
+///         channel: "channel".into(), message: text.to_string(), truncated: false
+///     });
+///
+///     write_packet(&mut bytes, &message).unwrap();
+/// }
+///
+/// let mut seen = Vec::new();
+///
+/// run_stream(&bytes[..], |message| {
+///     if let Message::Text(ref text) = *message {
+///         seen.push(text.message.clone());
+///     }
+/// }).unwrap();
+///
+/// assert_eq!(seen, ["first", "second"]);
+/// ```
+pub fn run_stream<R: Read, F: FnMut(&Message)>(reader: R, mut f: F) -> io::Result<()> {
+    for message in Messages::new(reader) {
+        f(&message?);
+    }
+
+    Ok(())
+}
+
+/// Reads one message using the layout of the negotiated protocol `version`.
+///
+/// Clients start out on version 1 until their Connection message says otherwise. The Connection
+/// message itself has to be readable before the version is known, so later versions must keep its
+/// layout and only change the text messages.
+pub fn read_packet(reader: &mut dyn Read, version: u32, limits: &Limits) -> io::Result<Message> {
+    read_packet_with_chunks(reader, version, limits, &mut |_| ())
+}
+
+/// Parses the message at the start of `bytes`, returning it along with how many bytes it took, the
+/// continuations of a Large message included.
+///
+/// For callers that already hold the bytes, like a WASM module handed a `Uint8Array`. Only the
+/// parser is involved, the library doesn't use networking, files or threads, so it builds with
+/// `--no-default-features` for `wasm32-unknown-unknown`. A message that isn't complete yet fails
+/// with `UnexpectedEof`, call again once more bytes have arrived.
+///
+/// ```
+/// use eve_logger::{parse_packet, write_packet, Limits, Message, Severity, TextMessage};
+///
+/// let message = Message::Text(TextMessage {
+///     timestamp: 1500000000, severity: Severity::Info, module: "module".into(),
+///     channel: "channel".into(), message: "hello".to_string(), truncated: false
+/// });
+///
+/// let mut bytes = Vec::new();
+/// write_packet(&mut bytes, &message).unwrap();
+/// write_packet(&mut bytes, &message).unwrap();
+///
+/// let (first, used) = parse_packet(&bytes, 1, &Limits::default()).unwrap();
+/// assert_eq!(first, message);
+/// assert_eq!(used, bytes.len() / 2);
+///
+/// let error = parse_packet(&bytes[used..bytes.len() - 1], 1, &Limits::default()).unwrap_err();
+/// assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+/// ```
+pub fn parse_packet(bytes: &[u8], version: u32, limits: &Limits) -> io::Result<(Message, usize)> {
+    let mut unread = bytes;
+    let message = read_packet(&mut unread, version, limits)?;
+
+    Ok((message, bytes.len() - unread.len()))
+}
+
+/// One buffer of a text message body, as `read_packet_with_chunks` reads it.
+#[derive(Debug)]
+pub struct Chunk<'a> {
+    /// The bytes of the buffer up to its terminator. They aren't decoded, a character may continue
+    /// in the next chunk.
+    pub bytes: &'a [u8],
+    /// 0 for the buffer of the Simple or Large packet itself, then counting its continuations.
+    pub index: usize,
+    /// Whether the message is complete with this chunk.
+    pub last: bool
+}
+
+/// Like `read_packet`, also calling `on_chunk` with every buffer of a text message as it is read,
+/// for showing the progress of a Large message before it has been reassembled.
+///
+/// Chunks are passed on whole, also those that `limits` cut short or leave out of the message, and
+/// those of messages of a type `limits` skips. A Large message that fails halfway has had its
+/// first chunks passed on already, none of which were `last`.
+///
+/// ```
+/// use eve_logger::{read_packet_with_chunks, write_packet, Limits, Message, Severity, TextMessage};
+///
+/// let message = Message::Text(TextMessage {
+///     timestamp: 1500000000, severity: Severity::Info, module: "module".into(),
+///     channel: "channel".into(), message: "x".repeat(600), truncated: false
+/// });
+///
+/// let mut bytes = Vec::new();
+/// write_packet(&mut bytes, &message).unwrap();
+///
+/// let mut chunks = Vec::new();
+/// let decoded = read_packet_with_chunks(&mut &bytes[..], 1, &Limits::default(), &mut |chunk| {
+///     chunks.push((chunk.index, chunk.bytes.len(), chunk.last));
+/// }).unwrap();
+///
+/// assert_eq!(decoded, message);
+/// assert_eq!(chunks, [(0, 256, false), (1, 256, false), (2, 88, true)]);
+/// ```
+pub fn read_packet_with_chunks(reader: &mut dyn Read, version: u32, limits: &Limits, on_chunk: &mut dyn FnMut(&Chunk)) -> io::Result<Message> {
+    read_packet_interned(reader, version, limits, &mut Interner::new(), on_chunk)
+}
+
+/// Like `read_packet_with_chunks`, sharing the module and channel strings of earlier messages
+/// through `interner`.
+fn read_packet_interned(reader: &mut dyn Read, version: u32, limits: &Limits, interner: &mut Interner, on_chunk: &mut dyn FnMut(&Chunk)) -> io::Result<Message> {
+    match version {
+        1 => read_packet_v1(reader, limits, interner, on_chunk),
+        2 => read_packet_v2(reader),
+        e => Err(io::Error::new(io::ErrorKind::InvalidData, format!("Protocol version {} is unknown", e)))
+    }
+}
+
+/// Placeholder for the next protocol revision, which is expected to change the size of
+/// `RawTextMessage`. Its layout isn't known yet.
+fn read_packet_v2(_: &mut dyn Read) -> io::Result<Message> {
+    Err(io::Error::new(io::ErrorKind::InvalidData, "Protocol version 2 is not supported yet"))
+}
+
+fn read_packet_v1(reader: &mut dyn Read, limits: &Limits, interner: &mut Interner, on_chunk: &mut dyn FnMut(&Chunk)) -> io::Result<Message> {
+    loop {
+        let raw_packet = read_raw_packet(reader, limits)?;
+
+        match raw_packet {
+            RawMessage::RawConnection(raw_message) => {
+                let message = ConnectionMessage {
+                    version: raw_message.version,
+                    pid: raw_message.pid,
+                    machine_name: convert_string(&raw_message.machine_name, limits.encoding),
+                    executable_path: convert_string(&raw_message.executable_path, limits.encoding)
+                };
+
+                return Ok(Message::Connection(message));
+            },
+            RawMessage::RawText(t, raw_message) => {
+                let mut message = TextMessage {
+                    timestamp: raw_message.timestamp,
+                    severity: Severity::from_u32(raw_message.severity),
+                    module: interner.module(&raw_message.module, limits.encoding),
+                    channel: interner.channel(&raw_message.channel, limits.encoding),
+                    message: String::new(),
+                    truncated: false
+                };
+
+                let mut body = Reassembly::new(limits, on_chunk);
+                body.push(&raw_message.message, !matches!(t, MessageType::Large));
+
+                let wanted = match t {
+                    MessageType::Simple => limits.message_types.simple,
+                    MessageType::Large => {
+                        read_continuation(&mut body, reader, &raw_message)?;
+                        limits.message_types.large
+                    },
+                    e => return Err(malformed(format!("{:?} packet outside of a Large message", e)))
+                };
+
+                if !wanted {
+                    continue;
+                }
+
+                let (body, truncated) = body.finish();
+                message.message = body;
+                message.truncated = truncated;
+
+                return Ok(Message::Text(message));
+            }
+        }
+    }
+}
+
+/// Decodes a fixed-size, NUL-terminated wire buffer.
+///
+/// Part of the parsing contract: only the bytes before the first NUL (a NUL code unit with
+/// `Encoding::Utf16Le`) are decoded. Clients reuse
+/// their buffers, so whatever follows the terminator (zeroes or stale garbage like
+/// `b"HOST\0XXXXGARBAGE"`) is ignored. Every string field goes through here before anything else
+/// sees it, so directory and file names built from `module`, and the reported `machine_name` and
+/// `executable_path`, never contain trailing garbage either.
+fn convert_string(buffer: &[u8], encoding: Encoding) -> String {
+    let encoding = encoding.detect(buffer);
+    encoding.decode(&buffer[..encoding.terminated_len(buffer)])
+}
+
+/// Like `convert_string`, except that a buffer that doesn't hold text is kept byte for byte as
+/// `TextMessage::channel_is_textual` describes. An empty string with non-zero bytes after its
+/// terminator is a number whose lowest byte is zero.
+fn convert_channel(buffer: &[u8], encoding: Encoding) -> String {
+    let channel = convert_string(buffer, encoding);
+    let used = buffer.iter().rposition(|x| *x != 0).map_or(0, |last| last + 1);
+
+    if channel.chars().any(|c| c < ' ' || c == '\u{7f}') || (channel.is_empty() && used > 0) {
+        buffer[..used].iter().map(|x| *x as char).collect()
+    } else {
+        channel
+    }
+}
+
+/// The module and channel strings a stream has used so far, keyed by the buffer they were decoded
+/// from, so the messages of a connection share one allocation of each instead of decoding them
+/// again for every message.
+///
+/// The buffers are compared whole, garbage after the terminator included, as that is cheaper than
+/// finding the terminator. A client that keeps changing that garbage only costs a lookup. Either
+/// table is emptied once it holds `INTERNED` strings, so it can't grow without bound.
+struct Interner {
+    modules: HashMap<[u8; 32], Arc<str>>,
+    channels: HashMap<[u8; 32], Arc<str>>
+}
+
+const INTERNED: usize = 1024;
+
+impl Interner {
+    fn new() -> Interner {
+        Interner { modules: HashMap::new(), channels: HashMap::new() }
+    }
+
+    fn module(&mut self, buffer: &[u8; 32], encoding: Encoding) -> Arc<str> {
+        Interner::intern(&mut self.modules, buffer, || convert_string(buffer, encoding))
+    }
+
+    fn channel(&mut self, buffer: &[u8; 32], encoding: Encoding) -> Arc<str> {
+        Interner::intern(&mut self.channels, buffer, || convert_channel(buffer, encoding))
+    }
+
+    fn intern<F: FnOnce() -> String>(table: &mut HashMap<[u8; 32], Arc<str>>, buffer: &[u8; 32], convert: F) -> Arc<str> {
+        if let Some(string) = table.get(buffer) {
+            return string.clone();
+        }
+
+        if table.len() >= INTERNED {
+            table.clear();
+        }
+
+        let string: Arc<str> = convert().into();
+        table.insert(*buffer, string.clone());
+        string
+    }
+}
+
+/// The raw bytes of a message body as its chunks arrive.
+///
+/// Chunks are only decoded once all of them are in, so a multi-byte character split across two
+/// packets still decodes as that character.
+struct Reassembly<'a> {
+    limits: &'a Limits,
+    on_chunk: &'a mut dyn FnMut(&Chunk),
+    /// `limits.encoding`, with `Encoding::Auto` settled by the first buffer.
+    encoding: Encoding,
+    bytes: Vec<u8>,
+    chunks: usize,
+    truncated: bool,
+    /// Set when a chunk can't belong to the message, see `REASSEMBLY_GAP`.
+    gap: bool
+}
+
+impl<'a> Reassembly<'a> {
+    fn new(limits: &'a Limits, on_chunk: &'a mut dyn FnMut(&Chunk)) -> Reassembly<'a> {
+        Reassembly { limits, on_chunk, encoding: limits.encoding, bytes: Vec::new(), chunks: 0, truncated: false, gap: false }
+    }
+
+    /// Adds the NUL-terminated contents of a message buffer, keeping only what fits the limits.
+    fn push(&mut self, buffer: &[u8], last: bool) {
+        if self.chunks == 0 {
+            self.encoding = self.encoding.detect(buffer);
+        }
+
+        let chunk = &buffer[..self.encoding.terminated_len(buffer)];
+        (self.on_chunk)(&Chunk { bytes: chunk, index: self.chunks, last });
+
+        // Clients fill every buffer before starting the next.
+        if !last && chunk.len() < buffer.len() {
+            self.gap = true;
+        }
+
+        // The first buffer is part of the Large packet itself, only the ones after it count.
+        if self.limits.max_continuation_chunks.is_some_and(|max| self.chunks > max) {
+            self.truncated = true;
+        }
+
+        self.chunks += 1;
+
+        if self.truncated {
+            return;
+        }
+
+        let room = self.limits.max_message_bytes.map_or(usize::MAX, |max| max.saturating_sub(self.bytes.len()));
+
+        if chunk.len() <= room {
+            self.bytes.extend_from_slice(chunk);
+        } else {
+            self.bytes.extend_from_slice(&chunk[..room]);
+            self.truncated = true;
+        }
+    }
+
+    /// Decodes the body, dropping a character that `--max-message-bytes` cut in half and marking
+    /// one with a gap.
+    fn finish(self) -> (String, bool) {
+        let mut bytes = self.bytes;
+
+        if self.truncated {
+            bytes.truncate(self.encoding.complete_len(&bytes));
+        }
+
+        let body = self.encoding.decode_owned(bytes);
+
+        if self.gap {
+            (format!("{}{}", REASSEMBLY_GAP, body), self.truncated)
+        } else {
+            (body, self.truncated)
+        }
+    }
+}
+
+/// The length of `bytes` without a trailing, incomplete UTF-8 sequence.
+fn complete_utf8_len(bytes: &[u8]) -> usize {
+    match bytes.iter().rposition(|x| *x & 0xc0 != 0x80) {
+        Some(start) => {
+            let expected = match bytes[start] {
+                x if x >= 0xf0 => 4,
+                x if x >= 0xe0 => 3,
+                x if x >= 0xc0 => 2,
+                _ => 1
+            };
+
+            if bytes.len() - start < expected { start } else { bytes.len() }
+        },
+        None => bytes.len()
+    }
+}
+
+fn read_continuation(body: &mut Reassembly, reader: &mut dyn Read, large: &RawTextMessage) -> io::Result<()> {
+    loop {
+        let (last, raw_message) = match read_raw_packet(reader, body.limits)? {
+            RawMessage::RawText(MessageType::Continuation, raw_message) => (false, raw_message),
+            RawMessage::RawText(MessageType::ContinuationEnd, raw_message) => (true, raw_message),
+            RawMessage::RawText(e, _) => return Err(malformed(format!("{:?} packet inside a Large message", e))),
+            RawMessage::RawConnection(_) => return Err(malformed("Connection packet inside a Large message".to_string()))
+        };
+
+        if !raw_message.same_message(large) {
+            body.gap = true;
+        }
+
+        body.push(&raw_message.message, last);
+
+        if last {
+            return Ok(());
+        }
+    }
+}
+
+/// Fills `buffer` like `read_exact`, except that running out of bytes is a `TruncatedPacket` of
+/// a packet of `expected` bytes, of which `got` were read before `buffer`.
+fn read_packet_part(reader: &mut dyn Read, buffer: &mut [u8], got: usize, expected: usize) -> io::Result<()> {
+    let mut filled = 0;
+
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => {
+                let truncated = TruncatedPacket { got: got + filled, expected };
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, truncated));
+            },
+            Ok(read) => filled += read,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e)
+        }
+    }
+
+    Ok(())
+}
+
+fn read_raw_packet(reader: &mut dyn Read, limits: &Limits) -> io::Result<RawMessage> {
+    let endianness = limits.endianness;
+    let mut header = [0; HEADER_SIZE];
+    read_packet_part(reader, &mut header, 0, HEADER_SIZE)?;
+
+    // The padding is ignored.
+    let raw_type = limits.header_layout.message_type(&header, endianness)?;
+
+    let known = if raw_type <= u64::from(u32::MAX) { MessageType::from_u32(raw_type as u32) } else { None };
+
+    // Nothing says how long the payload of an unknown type is, clients only ever send text sized
+    // packets besides the Connection so that is the best guess for getting back in sync.
+    let message_type = match known {
+        Some(message_type) => message_type,
+        None => {
+            read_packet_part(reader, &mut [0; TEXT_PAYLOAD_SIZE], HEADER_SIZE, HEADER_SIZE + TEXT_PAYLOAD_SIZE)?;
+            return Err(malformed(format!("Unknown message type {}", raw_type)));
+        }
+    };
+
+    // Each packet carries exactly its own payload, reading any more would eat into the next packet.
+    let payload_size = match message_type {
+        MessageType::Connection => CONNECTION_PAYLOAD_SIZE,
+        _ => TEXT_PAYLOAD_SIZE
+    };
+
+    let mut payload = vec![0; payload_size];
+    read_packet_part(reader, &mut payload, HEADER_SIZE, HEADER_SIZE + payload_size)?;
+
+    // Fields are read one by one in the layout of the repr(C) structs, trailing padding is ignored.
+    let mut fields = &payload[..];
+
+    match message_type {
+        MessageType::Connection => {
+            let version = endianness.read_u32(&mut fields)?;
+            let _ = endianness.read_u32(&mut fields)?; // Aligns pid
+            let pid = endianness.read_u64(&mut fields)?;
+
+            let mut message = RawConnectionMessage { version, pid, machine_name: [0; 32], executable_path: [0; 260] };
+            fields.read_exact(&mut message.machine_name)?;
+            fields.read_exact(&mut message.executable_path)?;
+
+            Ok(RawMessage::RawConnection(message))
+        },
+        t => {
+            let timestamp = endianness.read_u64(&mut fields)?;
+            let severity = endianness.read_u32(&mut fields)?;
+
+            let mut message = RawTextMessage { timestamp, severity, module: [0; 32], channel: [0; 32], message: [0; 256] };
+            fields.read_exact(&mut message.module)?;
+            fields.read_exact(&mut message.channel)?;
+            fields.read_exact(&mut message.message)?;
+
+            Ok(RawMessage::RawText(t, message))
+        }
+    }
+}
+
+/// Writes `message` in the wire format `read_packet` expects, so that reading it back yields the
+/// same message.
+///
+/// Messages that don't fit a single packet are split into `Large`, `Continuation` and
+/// `ContinuationEnd` packets. Like a real client this splits on byte boundaries, even inside a
+/// character. Other string fields longer than their buffer are cut at the last character that fits.
+pub fn write_packet(writer: &mut dyn Write, message: &Message) -> io::Result<()> {
+    match *message {
+        Message::Connection(ref message) => {
+            let mut payload = Vec::with_capacity(CONNECTION_PAYLOAD_SIZE);
+
+            payload.write_u32::<LittleEndian>(message.version)?;
+            payload.write_u32::<LittleEndian>(0)?; // Padding
+            payload.write_u64::<LittleEndian>(message.pid)?;
+            write_string(&mut payload, &message.machine_name, 32);
+            write_string(&mut payload, &message.executable_path, 260);
+
+            write_raw_packet(writer, MessageType::Connection, payload, CONNECTION_PAYLOAD_SIZE)
+        },
+        Message::Text(ref message) => {
+            let chunks = split_chunks(message.message.as_bytes(), 256);
+
+            for (i, chunk) in chunks.iter().enumerate() {
+                let message_type = match (i, chunks.len()) {
+                    (0, 1) => MessageType::Simple,
+                    (0, _) => MessageType::Large,
+                    (i, n) if i == n - 1 => MessageType::ContinuationEnd,
+                    _ => MessageType::Continuation
+                };
+
+                let mut payload = Vec::with_capacity(TEXT_PAYLOAD_SIZE);
+
+                payload.write_u64::<LittleEndian>(message.timestamp)?;
+                payload.write_u32::<LittleEndian>(message.severity.to_u32())?;
+                write_string(&mut payload, &message.module, 32);
+                write_string(&mut payload, &message.channel, 32);
+                write_bytes(&mut payload, chunk, 256);
+
+                write_raw_packet(writer, message_type, payload, TEXT_PAYLOAD_SIZE)?;
+            }
+
+            Ok(())
+        }
+    }
+}
+
+fn write_raw_packet(writer: &mut dyn Write, message_type: MessageType, payload: Vec<u8>, size: usize) -> io::Result<()> {
+    let mut payload = payload;
+    payload.resize(size, 0);
+
+    writer.write_u32::<LittleEndian>(message_type as u32)?;
+    writer.write_u32::<LittleEndian>(0)?; // Padding
+    writer.write_all(&payload)
+}
+
+/// Appends `value` as a NUL-padded buffer of `size` bytes.
+fn write_string(payload: &mut Vec<u8>, value: &str, size: usize) {
+    write_bytes(payload, &value.as_bytes()[..floor_char_boundary(value, size)], size);
+}
+
+fn write_bytes(payload: &mut Vec<u8>, value: &[u8], size: usize) {
+    payload.extend_from_slice(value);
+    payload.resize(payload.len() + size - value.len(), 0);
+}
+
+/// Splits `value` into pieces of `size` bytes, always yielding at least one (possibly empty) piece.
+fn split_chunks(value: &[u8], size: usize) -> Vec<&[u8]> {
+    if value.is_empty() {
+        return vec![value];
+    }
+
+    value.chunks(size).collect()
+}
+
+fn floor_char_boundary(value: &str, index: usize) -> usize {
+    if index >= value.len() {
+        return value.len();
+    }
+
+    let mut index = index;
+
+    while !value.is_char_boundary(index) {
+        index -= 1;
+    }
+
+    index
+}
@@ -1,218 +1,3390 @@
-extern crate chrono;
+extern crate bincode;
 extern crate byteorder;
+extern crate chrono;
+extern crate flate2;
+extern crate eve_logger;
+extern crate libc;
+extern crate regex;
+#[cfg(feature = "notify")]
+extern crate notify_rust;
+#[cfg(feature = "plugin")]
+extern crate libloading;
+#[cfg(feature = "otlp")]
+extern crate opentelemetry;
+#[cfg(feature = "otlp")]
+extern crate opentelemetry_otlp;
+#[cfg(feature = "otlp")]
+extern crate opentelemetry_sdk;
+#[cfg(feature = "parquet")]
+extern crate parquet;
+#[cfg(feature = "kafka")]
+extern crate rdkafka;
+#[cfg(feature = "sqlite")]
+extern crate rusqlite;
+#[cfg(feature = "tls")]
+extern crate rustls;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate socket2;
+extern crate tar;
+#[cfg(all(test, feature = "tokio"))]
+extern crate tokio;
+
+/// Prints an operational message to stdout, prefixed with the local time. Silenced while `--top`
+/// owns the terminal.
+macro_rules! info {
+    ($($arg:tt)*) => (if !::QUIET.load(::std::sync::atomic::Ordering::Relaxed) {
+        println!("{} {}", ::local_time(), format_args!($($arg)*))
+    })
+}
+
+/// Like `info!`, but only with `--verbose`, for what is only of interest when looking into what the
+/// server does.
+macro_rules! debug {
+    ($($arg:tt)*) => (if ::VERBOSE.load(::std::sync::atomic::Ordering::Relaxed) {
+        info!($($arg)*)
+    })
+}
+
+/// Like `info!`, but for warnings and errors on stderr.
+macro_rules! warn {
+    ($($arg:tt)*) => (eprintln!("{} {}", ::local_time(), format_args!($($arg)*)))
+}
 
-use std::{cmp, fs, io, mem, ptr, thread};
+mod archive;
+mod audit;
+mod clock;
+#[cfg(feature = "parquet")]
+mod columnar;
+mod control;
+mod convert;
+mod counter;
+mod disk;
+mod dns;
+mod hexdump;
+mod index;
+mod join;
+mod notify;
+#[cfg(feature = "otlp")]
+mod otlp;
+#[cfg(feature = "kafka")]
+mod kafka;
+mod http;
+mod merge;
+#[cfg(feature = "plugin")]
+mod plugin;
+mod probe;
+mod raw;
+mod realtime;
+mod reconnect;
+mod retention;
+mod route;
+mod selftest;
+mod signal;
+mod sink;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod stats;
+mod strict;
+mod summary;
+mod tarball;
+#[cfg(test)]
+mod tests;
+#[cfg(feature = "tls")]
+mod tls;
+mod top;
+mod udp;
+mod verify;
 
-use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream};
+use std::{cmp, env, fmt, fs, io, iter, mem, process, thread};
 
-use byteorder::{ReadBytesExt, LittleEndian};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
+use std::io::{IsTerminal, Read, Seek, Write};
+use std::net::{Ipv6Addr, SocketAddr, SocketAddrV6, TcpListener, TcpStream, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
 use chrono::naive::datetime::NaiveDateTime;
 
-#[repr(C)]
-#[derive(Debug)]
-enum MessageType {
-    Connection = 0,
-    Simple = 1,
-    Large = 2,
-    Continuation = 3,
-    ContinuationEnd = 4
+use eve_logger::{is_malformed, payload_layouts, truncated_packet, Encoding, Endianness, HeaderLayout, Limits, Message, MessageTypes, Messages, MessagesWithBytes, Severity, TextMessage, DEFAULT_PORT, REASSEMBLY_GAP};
+
+use sink::Sink;
+
+use regex::Regex;
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+/// Which characters can't appear in a file name on the filesystem logs are written to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ReservedChars {
+    Unix, Windows
 }
 
-impl MessageType {
-    pub fn from_u32(value: u32) -> MessageType {
+impl ReservedChars {
+    /// The set of the platform this was compiled for, override it with `--reserved-chars` when
+    /// writing to a share that follows the other platform's rules.
+    fn native() -> ReservedChars {
+        if cfg!(windows) { ReservedChars::Windows } else { ReservedChars::Unix }
+    }
+
+    fn from_name(name: &str, value: &str) -> Result<ReservedChars, String> {
         match value {
-            0 => MessageType::Connection,
-            1 => MessageType::Simple,
-            2 => MessageType::Large,
-            3 => MessageType::Continuation,
-            4 => MessageType::ContinuationEnd,
-            e => panic!("Unknown message type! {}", e)
+            "native" => Ok(ReservedChars::native()),
+            "unix" => Ok(ReservedChars::Unix),
+            "windows" => Ok(ReservedChars::Windows),
+            e => Err(format!("Option {} expects native, unix or windows, got {}", name, e))
+        }
+    }
+
+    fn is_reserved(self, c: char) -> bool {
+        match self {
+            ReservedChars::Unix => c == '/' || c == '\0',
+            ReservedChars::Windows => "<>:\"/\\|?*".contains(c) || c.is_control()
         }
     }
 }
 
-#[derive(Debug)]
-enum Severity {
-    Info, Notice, Warn, Error, Unknown(u32)
+/// What the records of `--kafka-brokers` are keyed by, chosen with `--kafka-key`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum KafkaKey {
+    Pid, Module
 }
 
-impl Severity {
-    pub fn from_u32(value: u32) -> Severity {
+impl KafkaKey {
+    fn from_name(name: &str, value: &str) -> Result<KafkaKey, String> {
         match value {
-            0 => Severity::Info,
-            1 => Severity::Notice,
-            2 => Severity::Warn,
-            3 => Severity::Error,
-            e => Severity::Unknown(e)
+            "pid" => Ok(KafkaKey::Pid),
+            "module" => Ok(KafkaKey::Module),
+            e => Err(format!("Option {} expects pid or module, got {}", name, e))
         }
     }
 }
 
-#[repr(C)]
-struct RawConnectionMessage {
-    version: u32,
-    pid: u64,
-    machine_name: [u8; 32],
-    executable_path: [u8; 260]
+/// How `--shards` picks the file of a message, chosen with `--shard-by`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ShardBy {
+    /// Each message of a module goes to the shard after that of the one before it.
+    RoundRobin,
+    /// The shard follows from the channel, every message of a channel is in the same file.
+    Hash
+}
+
+impl ShardBy {
+    fn from_name(name: &str, value: &str) -> Result<ShardBy, String> {
+        match value {
+            "round-robin" => Ok(ShardBy::RoundRobin),
+            "hash" => Ok(ShardBy::Hash),
+            e => Err(format!("Option {} expects round-robin or hash, got {}", name, e))
+        }
+    }
 }
 
-#[repr(C)]
-struct RawTextMessage {
-    timestamp: u64,
-    severity: u32,
-    module: [u8; 32],
-    channel: [u8; 32],
-    message: [u8; 256]
+/// The directory below the output root, and the date with `--date-dir`, that a client's files go
+/// in, chosen with `--group-by`.
+///
+/// Pids are reused and mean nothing from one run to the next, grouping by the executable puts
+/// every run of the same program in one tree. Clients of the same executable then write to the
+/// same files: each line stays whole, but lines of different clients interleave in the order their
+/// buffers were written out, and `--sequence-numbers` counts per pid. A `{pid}` in `--line-format`
+/// or the `pid` field of JSON tells them apart.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum GroupBy {
+    /// `{pid}/`
+    Pid,
+    /// `{exe}/`, the file name of `executable_path` without its extension.
+    Exe,
+    /// `{exe}/{pid}/`
+    ExePid
 }
 
-enum RawMessage {
-    RawConnection(RawConnectionMessage), RawText(MessageType, RawTextMessage)
+impl GroupBy {
+    fn from_name(name: &str, value: &str) -> Result<GroupBy, String> {
+        match value {
+            "pid" => Ok(GroupBy::Pid),
+            "exe" => Ok(GroupBy::Exe),
+            "exe-pid" => Ok(GroupBy::ExePid),
+            e => Err(format!("Option {} expects pid, exe or exe-pid, got {}", name, e))
+        }
+    }
 }
 
-#[derive(Debug)]
-struct ConnectionMessage {
-    version: u32, pid: u64, machine_name: String, executable_path: String
+/// How writes get to the end of a log file, chosen with `--append-mode`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum AppendMode {
+    /// Opening with `O_APPEND`, which has the filesystem put every write at the end.
+    OAppend,
+    /// Seeking to the end before every write, holding an exclusive lock on the file for the seek and
+    /// the write. For shared storage that doesn't honor `O_APPEND`, where writers appending at once
+    /// overwrite each other.
+    ///
+    /// The lock is advisory, so only writers taking it too are kept out: other eve-logger servers
+    /// with `--append-mode seek-end`, not an editor or a plain `>>`. On NFS, Linux takes it as a
+    /// lock on the server.
+    SeekEnd
 }
 
-#[derive(Debug)]
-struct TextMessage {
-    timestamp: u64, severity: Severity, module: String, channel: String, message: String
+impl AppendMode {
+    fn from_name(name: &str, value: &str) -> Result<AppendMode, String> {
+        match value {
+            "oappend" => Ok(AppendMode::OAppend),
+            "seek-end" => Ok(AppendMode::SeekEnd),
+            e => Err(format!("Option {} expects oappend or seek-end, got {}", name, e))
+        }
+    }
+}
+
+/// What the per-client output is written as, chosen with `--format`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Text,
+    /// One JSON object per line in `{module}.jsonl`, or indented objects with `--json-pretty`.
+    Json,
+    Bincode
+}
+
+impl Format {
+    fn from_name(name: &str, value: &str) -> Result<Format, String> {
+        match value {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "bincode" => Ok(Format::Bincode),
+            e => Err(format!("Option {} expects text, json or bincode, got {}", name, e))
+        }
+    }
+
+    /// The extension of the files of a format that writes lines.
+    fn extension(self) -> &'static str {
+        if self == Format::Json { "jsonl" } else { "txt" }
+    }
+}
+
+/// The options a running server picks up again from `--config` on SIGHUP: `--min-severity`,
+/// `--channel-severity`, `--unknown-severity-as`, `--grep`, `--grep-v` and `--redact`. Everything
+/// else only changes with a restart.
+///
+/// Clients see the new values from their next message on, a message is always handled with one
+/// set of them.
+#[derive(Clone, Debug, Default)]
+struct Filters {
+    min_severity: Option<Severity>,
+    /// Severities given to every message of a channel by `--channel-severity`, whatever the client
+    /// said, for modules that log everything as Info.
+    channel_severities: HashMap<String, Severity>,
+    unknown_severity: UnknownSeverity,
+    /// `--grep`, a message has to match one of these to be kept, if there are any.
+    grep: Vec<Regex>,
+    /// `--grep-v`, a message matching any of these is left out.
+    grep_v: Vec<Regex>,
+    redactions: Vec<(Regex, String)>
+}
+
+/// `--unknown-severity-as`, what happens to a message whose severity code isn't one of the four
+/// known ones.
+#[derive(Clone, Copy, Debug, Default)]
+enum UnknownSeverity {
+    /// Keep `Unknown(n)`, written as such and passing every `--min-severity`.
+    #[default]
+    Keep,
+    /// Treat it as this severity, for `--min-severity` and in the output alike.
+    As(Severity),
+    /// Leave the message out.
+    Drop
+}
+
+impl UnknownSeverity {
+    fn from_name(name: &str, value: &str) -> Result<UnknownSeverity, String> {
+        match value {
+            "keep" => Ok(UnknownSeverity::Keep),
+            "drop" => Ok(UnknownSeverity::Drop),
+            "info" | "notice" | "warn" | "error" => Ok(UnknownSeverity::As(parse_severity(name, value)?)),
+            e => Err(format!("Option {} expects info, notice, warn, error, keep or drop, got {}", name, e))
+        }
+    }
+}
+
+/// An option value `--print-config` doesn't show, like the `--anon-salt` that would undo
+/// `--anonymize-connection` in any log the dump is pasted into. Only whether it is set is printed.
+#[derive(Default)]
+struct Secret(String);
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(if self.0.is_empty() { "(not set)" } else { "(redacted)" })
+    }
 }
 
 #[derive(Debug)]
-enum Message {
-    Connection(ConnectionMessage), Text(TextMessage)
+struct Config {
+    bind: Vec<SocketAddr>,
+    format: Format,
+    /// `--also-format`, the formats the files are written in as well, each to files of their own
+    /// extension next to those of `format`.
+    also_format: Vec<Format>,
+    /// Indent `--format json` output for reading by eye. The result is no longer JSON lines, so
+    /// tools that ingest one object per line can't read it.
+    json_pretty: bool,
+    /// `--json-envelope`, wrapping every JSON record in a versioned envelope, see `sink::json_line`.
+    json_envelope: bool,
+    line_format: Option<sink::LineFormat>,
+    drop_fields: Vec<sink::Field>,
+    /// `--align`, the width the channel is padded to in the default text layout, see
+    /// `sink::format_line`.
+    align: Option<usize>,
+    /// `--wrap-width`, the characters of a body the default text layout writes on a line, the rest
+    /// going on continuation lines lined up under the body and marked with `| ` instead of `> `.
+    /// JSON and bincode keep every message whole.
+    wrap_width: Option<usize>,
+    /// `--label`, the key=value pairs every written message gets, in the order given.
+    labels: Vec<(String, String)>,
+    time_source: sink::TimeSource,
+    timestamp_mode: sink::TimestampMode,
+    time_precision: Option<sink::TimePrecision>,
+    /// `--index`, keeps an `index.json` of the files in each pid directory.
+    index: bool,
+    line_terminator: sink::LineTerminator,
+    /// `--max-line-bytes`, how much of each message a written line holds.
+    max_line_bytes: Option<usize>,
+    /// `--max-open-files`, how many files each client may have open at once.
+    max_open_files: Option<usize>,
+    /// How often opening a log file is tried again, see `open_with_retries`.
+    open_retries: u32,
+    open_retry_delay: Duration,
+    /// `--batch-size` and `--batch-time`, how the sinks that take batches get them, see
+    /// `sink::Batch`.
+    batch_size: usize,
+    batch_time: Duration,
+    /// `--buffer`, the strategy of each sink given one, see `sink::Buffer`.
+    buffers: Vec<(String, sink::Buffer)>,
+    /// `--route`, where text messages go when there are any, see `route::Routed`.
+    routes: Vec<route::Route>,
+    route_match: route::RouteMatch,
+    /// `--sample`, keeping one in this many messages of each (pid, module).
+    sample: Option<u64>,
+    /// `--monotonic-timestamps`, never letting the timestamp of a (pid, module) go backwards.
+    monotonic_timestamps: bool,
+    /// `--reverse-dns`, logging the name of the address each client connected from, see
+    /// `dns::ReverseDns`.
+    reverse_dns: bool,
+    /// `--verbose`, also logging what `debug!` does.
+    verbose: bool,
+    /// `--session-ids`, numbering the sessions of TCP clients on from the last run, see
+    /// `counter::SessionCounter`.
+    session_ids: bool,
+    /// `--audit-names`, recording names that aren't text to `name-audit.log`, see `audit::NameAudit`.
+    audit_names: bool,
+    /// `--since` and `--until`, the window of message timestamps that are kept, the start included
+    /// and the end not.
+    since: Option<NaiveDateTime>,
+    until: Option<NaiveDateTime>,
+    severity_style: sink::SeverityStyle,
+    listen_backlog: Option<i32>,
+    follow_symlinks: bool,
+    max_connections: Option<usize>,
+    /// Accept errors in a row that are waited out before giving up, see `accept_with_backoff`.
+    accept_retries: u32,
+    date_dir: bool,
+    /// `--no-create-empty`, the pid directory waits for the first message that gets written.
+    no_create_empty: bool,
+    group_by: GroupBy,
+    append_mode: AppendMode,
+    /// `--prefix-hostname`, the name of this server as a directory above everything written, so
+    /// servers sharing storage don't write into each other's pid directories.
+    hostname: Option<String>,
+    reserved_chars: ReservedChars,
+    replacement_char: char,
+    /// `--max-name-len`, the most bytes a module, channel or other client-supplied name may take
+    /// as a path component, its extension included. 255 is what most filesystems allow.
+    max_name_len: usize,
+    limits: Limits,
+    /// Prefix lines with a per (pid, module) counter. Counters live with the session, so a
+    /// client that reconnects starts again from 1 unless it is back within `--reconnect-grace`.
+    sequence_numbers: bool,
+    reconnect_grace: Option<Duration>,
+    /// `--dedup-on-reconnect`, skipping the lines a client sends again after it connected, see
+    /// `reconnect::Written`.
+    dedup_on_reconnect: bool,
+    min_pid: u64,
+    /// `--pid`, the only pids written when any are given, see `check_pid`.
+    pids: Vec<u64>,
+    anonymize_connection: bool,
+    anon_salt: Secret,
+    /// `--version-regex`, finds the game build in the executable path of each client.
+    version_regex: Option<Regex>,
+    http: Option<SocketAddr>,
+    /// `--udp`, also reads packets sent as datagrams to this address.
+    udp: Option<SocketAddr>,
+    tail_buffer: usize,
+    replay: Vec<PathBuf>,
+    /// `--realtime`, `--speed` and `--realtime-max-gap`, pacing a replay by its timestamps, see
+    /// `realtime::Realtime`.
+    realtime: bool,
+    speed: f64,
+    realtime_max_gap: Duration,
+    /// `--merge-all`, the one file a replay writes every pid to, see `merge::MergeSink`.
+    merge_all: Option<PathBuf>,
+    /// `--sort-by-time`, how long it holds messages back to put them in order.
+    sort_by_time: Option<Duration>,
+    /// `--stdin`, reads one client's stream from stdin instead of listening, and exits at its end.
+    stdin: bool,
+    sqlite: Option<PathBuf>,
+    /// `--parquet`, the file every text message is written to as a row, see `columnar::Writer`.
+    parquet: Option<PathBuf>,
+    /// `--mirror-dir`, a second root every log file is also written under, see `Handle::mirror`.
+    mirror_dir: Option<PathBuf>,
+    /// `--save-raw`, where the bytes of every client are saved to replay later, see `raw::Capture`.
+    save_raw: Option<PathBuf>,
+    /// `--checkpoint-interval`, how many milliseconds apart a `--save-raw` capture gets an entry in
+    /// its index, 0 for one after every message. See `raw::Capture`.
+    checkpoint_interval: Option<Duration>,
+    kafka_brokers: Option<String>,
+    kafka_topic: Option<String>,
+    kafka_key: KafkaKey,
+    /// `--otlp`, the OTLP/HTTP logs endpoint every text message is exported to, see `otlp::Writer`.
+    otlp: Option<String>,
+    sink_plugin: Option<PathBuf>,
+    /// Passed to the `init` of the `--sink-plugin`, empty unless given.
+    sink_plugin_config: String,
+    top: bool,
+    truncate_existing: bool,
+    split_by_channel: bool,
+    /// `--shards`, the files each module is spread over, see `sink::FileSink`.
+    shards: Option<u64>,
+    shard_by: ShardBy,
+    idle_flush: Option<Duration>,
+    idle_close: bool,
+    heartbeat_lines: Option<Duration>,
+    archive_on_disconnect: bool,
+    archive_remove: bool,
+    default_module: String,
+    min_free_bytes: Option<u64>,
+    low_disk: disk::LowDisk,
+    summary: bool,
+    /// The channels of each module `--summary` counts on their own, see `summary::ChannelCounts`.
+    summary_channels: usize,
+    /// `--notify-on`, alerting with `notify` on messages of at least this severity.
+    notify_on: Option<Severity>,
+    notify: notify::Alert,
+    notify_debounce: Duration,
+    /// `--max-errors`, the malformed packets a client may send in a row before it is disconnected.
+    /// 0 by default, so the first malformed packet ends the connection like any other error.
+    max_errors: usize,
+    join_partial: Option<Duration>,
+    hexdump: bool,
+    /// `--strict-parse`, the first parse error of any client ends the server, see `strict::Strict`.
+    strict_parse: bool,
+    errors_to_stderr: Option<Severity>,
+    /// As given at startup, running clients read `Context::filters` instead, which SIGHUP replaces.
+    filters: Filters,
+    /// `--config`, options read before the command line and again on SIGHUP.
+    config_file: Option<PathBuf>,
+    /// `--control`, the Unix socket `control::serve` takes commands on. Only the user running the
+    /// server may connect, the socket is set to 0600 whatever the umask.
+    control: Option<PathBuf>,
+    /// `--close-records`, hands the sinks a `sink::SessionClose` when a client disconnects.
+    close_records: bool,
+    /// `--recent-messages`, how many of its last messages each connected client keeps for the
+    /// `recent` control command, none for 0.
+    ///
+    /// Each one kept holds its module, channel, body and formatted timestamp along with about 150
+    /// bytes of its own, so keeping n messages of around 200 bytes costs roughly n × 400 bytes for
+    /// every connected client. They are forgotten when the client disconnects or announces a new
+    /// pid.
+    recent_messages: usize,
+    /// How long clients get to finish writing when shutting down.
+    drain_timeout: Duration,
+    /// `--max-runtime`, how long the server runs before shutting down on its own.
+    max_runtime: Option<Duration>,
+    /// `--retention-days`, how old the pid directories removed at startup are, see `retention::sweep`.
+    retention_days: Option<u64>,
+    /// `--retention-interval`, how often they are swept again after startup.
+    retention_interval: Option<Duration>,
+    tls_cert: Option<PathBuf>,
+    tls_key: Option<PathBuf>,
+    /// `--print-config`, prints the options as resolved from the `--config` file and the command
+    /// line and exits instead of starting.
+    print_config: bool
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            bind: vec![SocketAddr::from(([127, 0, 0, 1], DEFAULT_PORT))],
+            format: Format::Text,
+            also_format: Vec::new(),
+            json_pretty: false,
+            json_envelope: false,
+            line_format: None,
+            drop_fields: Vec::new(),
+            align: None,
+            wrap_width: None,
+            labels: Vec::new(),
+            time_source: sink::TimeSource::Message,
+            timestamp_mode: sink::TimestampMode::Absolute,
+            time_precision: None,
+            index: false,
+            line_terminator: sink::LineTerminator::Lf,
+            max_line_bytes: None,
+            max_open_files: None,
+            open_retries: 0,
+            open_retry_delay: Duration::from_millis(100),
+            batch_size: 1,
+            batch_time: Duration::from_secs(1),
+            buffers: Vec::new(),
+            routes: Vec::new(),
+            route_match: route::RouteMatch::First,
+            sample: None,
+            monotonic_timestamps: false,
+            reverse_dns: false,
+            verbose: false,
+            session_ids: false,
+            audit_names: false,
+            since: None,
+            until: None,
+            severity_style: sink::SeverityStyle::default(),
+            listen_backlog: None,
+            follow_symlinks: true,
+            max_connections: None,
+            accept_retries: 60,
+            date_dir: false,
+            no_create_empty: false,
+            group_by: GroupBy::Pid,
+            append_mode: AppendMode::OAppend,
+            hostname: None,
+            reserved_chars: ReservedChars::native(),
+            replacement_char: '_',
+            max_name_len: 255,
+            limits: Limits::default(),
+            sequence_numbers: false,
+            reconnect_grace: None,
+            dedup_on_reconnect: false,
+            min_pid: 1,
+            pids: Vec::new(),
+            anonymize_connection: false,
+            anon_salt: Secret::default(),
+            version_regex: None,
+            http: None,
+            udp: None,
+            tail_buffer: 100,
+            replay: Vec::new(),
+            realtime: false,
+            speed: 1.0,
+            realtime_max_gap: Duration::from_secs(10),
+            merge_all: None,
+            sort_by_time: None,
+            stdin: false,
+            sqlite: None,
+            parquet: None,
+            mirror_dir: None,
+            save_raw: None,
+            checkpoint_interval: None,
+            kafka_brokers: None,
+            kafka_topic: None,
+            kafka_key: KafkaKey::Pid,
+            otlp: None,
+            sink_plugin: None,
+            sink_plugin_config: String::new(),
+            top: false,
+            truncate_existing: false,
+            split_by_channel: false,
+            shards: None,
+            shard_by: ShardBy::RoundRobin,
+            idle_flush: None,
+            idle_close: false,
+            heartbeat_lines: None,
+            archive_on_disconnect: false,
+            archive_remove: false,
+            default_module: "default".to_string(),
+            min_free_bytes: None,
+            low_disk: disk::LowDisk::Wait,
+            summary: false,
+            summary_channels: 32,
+            notify_on: None,
+            notify: notify::Alert::Bell,
+            notify_debounce: Duration::from_secs(5),
+            max_errors: 0,
+            join_partial: None,
+            hexdump: false,
+            strict_parse: false,
+            errors_to_stderr: None,
+            filters: Filters::default(),
+            config_file: None,
+            control: None,
+            recent_messages: 0,
+            close_records: false,
+            drain_timeout: Duration::from_secs(10),
+            max_runtime: None,
+            retention_days: None,
+            retention_interval: None,
+            tls_cert: None,
+            tls_key: None,
+            print_config: false
+        }
+    }
 }
 
-fn handle_client(stream: TcpStream) {
-    let mut stream = stream;
-    let mut version = 1u32;
-    let mut pid = 0u64;
+impl Config {
+    fn from_args<I: Iterator<Item = String>>(args: I) -> Result<Config, String> {
+        let mut config = Config::default();
+        let mut binds = Vec::new();
+        let mut prefix_hostname = false;
+        let mut args = args;
 
-    loop {
-        match read_packet(&mut stream).unwrap() {
-            Message::Connection(msg) => {
-                version = msg.version;
-                pid = msg.pid;
+        while let Some(arg) = args.next() {
+            match arg.as_ref() {
+                "--anonymize-connection" => config.anonymize_connection = true,
+                "--anon-salt" => config.anon_salt = Secret(option_value(&arg, &mut args)?),
+                "--archive-on-disconnect" => config.archive_on_disconnect = true,
+                "--archive-remove" => config.archive_remove = true,
+                "--bind" => binds.push(parse_address(&arg, &option_value(&arg, &mut args)?)?),
+                "--format" => config.format = Format::from_name(&arg, &option_value(&arg, &mut args)?)?,
+                "--also-format" => match Format::from_name(&arg, &option_value(&arg, &mut args)?)? {
+                    Format::Bincode => return Err("Option --also-format expects text or json, bincode has no files of lines".to_string()),
+                    format if !config.also_format.contains(&format) => config.also_format.push(format),
+                    _ => ()
+                },
+                "--join-partial" => config.join_partial = Some(Duration::from_millis(parse_number(&arg, &option_value(&arg, &mut args)?)?)),
+                "--json-envelope" => config.json_envelope = true,
+                "--json-pretty" => config.json_pretty = true,
+                "--encoding" => config.limits.encoding = parse_encoding(&arg, &option_value(&arg, &mut args)?)?,
+                "--errors-to-stderr" => config.errors_to_stderr = Some(parse_severity(&arg, &option_value(&arg, &mut args)?)?),
+                "--endianness" => config.limits.endianness = parse_endianness(&arg, &option_value(&arg, &mut args)?)?,
+                "--message-types" => config.limits.message_types = parse_message_types(&arg, &option_value(&arg, &mut args)?)?,
+                "--header-layout" => config.limits.header_layout = parse_header_layout(&arg, &option_value(&arg, &mut args)?)?,
+                "--default-module" => config.default_module = option_value(&arg, &mut args)?,
+                "--config" => config.config_file = Some(PathBuf::from(option_value(&arg, &mut args)?)),
+                "--control" => config.control = Some(PathBuf::from(option_value(&arg, &mut args)?)),
+                "--close-records" => config.close_records = true,
+                "--recent-messages" => config.recent_messages = parse_number(&arg, &option_value(&arg, &mut args)?)?,
+                "--drain-timeout" => config.drain_timeout = Duration::from_secs(parse_number(&arg, &option_value(&arg, &mut args)?)?),
+                "--max-runtime" => config.max_runtime = Some(Duration::from_secs(parse_number(&arg, &option_value(&arg, &mut args)?)?)),
+                "--retention-days" => config.retention_days = Some(parse_number(&arg, &option_value(&arg, &mut args)?)?),
+                "--retention-interval" => config.retention_interval = Some(Duration::from_secs(parse_number(&arg, &option_value(&arg, &mut args)?)?)),
+                "--severity-label" => config.severity_style.set_label(&arg, &option_value(&arg, &mut args)?)?,
+                "--severity-color" => config.severity_style.set_color(&arg, &option_value(&arg, &mut args)?)?,
+                "--line-terminator" => config.line_terminator = sink::LineTerminator::from_name(&arg, &option_value(&arg, &mut args)?)?,
+                "--time-source" => config.time_source = sink::TimeSource::from_name(&arg, &option_value(&arg, &mut args)?)?,
+                "--timestamp-mode" => config.timestamp_mode = sink::TimestampMode::from_name(&arg, &option_value(&arg, &mut args)?)?,
+                "--time-precision" => config.time_precision = Some(sink::TimePrecision::from_name(&arg, &option_value(&arg, &mut args)?)?),
+                "--drop-fields" => config.drop_fields = sink::parse_drop_fields(&arg, &option_value(&arg, &mut args)?)?,
+                "--align" => config.align = Some(parse_number(&arg, &option_value(&arg, &mut args)?)?),
+                "--wrap-width" => match parse_number(&arg, &option_value(&arg, &mut args)?)? {
+                    0 => return Err("Option --wrap-width expects a width of at least 1".to_string()),
+                    width => config.wrap_width = Some(width)
+                },
+                "--buffer" => {
+                    let buffer = sink::parse_buffer(&arg, &option_value(&arg, &mut args)?)?;
 
-                fs::create_dir_all(format!("{}", pid)).unwrap();
+                    if config.buffers.iter().any(|(sink, _)| *sink == buffer.0) {
+                        return Err(format!("Option {} was given {} twice", arg, buffer.0));
+                    }
 
-                println!("Connection: {:?}", msg)
-            },
-            Message::Text(msg) => {
-                let timestamp = match version {
-                    1 => NaiveDateTime::from_timestamp(msg.timestamp as i64, 0),
-                    e => panic!("Version {} is unknown", e)
-                };
+                    config.buffers.push(buffer);
+                },
+                "--route" => config.routes.push(route::parse_route(&arg, &option_value(&arg, &mut args)?)?),
+                "--route-match" => config.route_match = route::RouteMatch::from_name(&arg, &option_value(&arg, &mut args)?)?,
+                "--label" => {
+                    let label = sink::parse_label(&arg, &option_value(&arg, &mut args)?)?;
+
+                    if config.labels.iter().any(|(key, _)| *key == label.0) {
+                        return Err(format!("Option {} was given {} twice", arg, label.0));
+                    }
+
+                    config.labels.push(label);
+                },
+                "--date-dir" => config.date_dir = true,
+                "--no-create-empty" => config.no_create_empty = true,
+                "--group-by" => config.group_by = GroupBy::from_name(&arg, &option_value(&arg, &mut args)?)?,
+                "--append-mode" => config.append_mode = AppendMode::from_name(&arg, &option_value(&arg, &mut args)?)?,
+                "--prefix-hostname" => prefix_hostname = true,
+                "--idle-flush" => config.idle_flush = Some(Duration::from_secs(parse_number(&arg, &option_value(&arg, &mut args)?)?)),
+                "--index" => config.index = true,
+                "--idle-close" => config.idle_close = true,
+                "--heartbeat-lines" => config.heartbeat_lines = Some(Duration::from_secs(parse_number(&arg, &option_value(&arg, &mut args)?)?)),
+                "--hexdump" => config.hexdump = true,
+                "--strict-parse" => config.strict_parse = true,
+                "--udp" => config.udp = Some(parse_address(&arg, &option_value(&arg, &mut args)?)?),
+                "--http" => config.http = Some(parse_address(&arg, &option_value(&arg, &mut args)?)?),
+                "--follow-symlinks" => config.follow_symlinks = parse_bool(&arg, &option_value(&arg, &mut args)?)?,
+                "--max-continuation-chunks" => config.limits.max_continuation_chunks = Some(parse_number(&arg, &option_value(&arg, &mut args)?)?),
+                "--min-severity" => config.filters.min_severity = Some(parse_severity(&arg, &option_value(&arg, &mut args)?)?),
+                "--channel-severity" => {
+                    let (channel, severity) = parse_channel_severity(&arg, &option_value(&arg, &mut args)?)?;
+                    config.filters.channel_severities.insert(channel, severity);
+                },
+                "--unknown-severity-as" => config.filters.unknown_severity = UnknownSeverity::from_name(&arg, &option_value(&arg, &mut args)?)?,
+                "--max-open-files" => config.max_open_files = Some(parse_number(&arg, &option_value(&arg, &mut args)?)?),
+                "--open-retries" => config.open_retries = parse_number(&arg, &option_value(&arg, &mut args)?)?,
+                "--open-retry-delay" => config.open_retry_delay = Duration::from_millis(parse_number(&arg, &option_value(&arg, &mut args)?)?),
+                "--batch-size" => config.batch_size = parse_number(&arg, &option_value(&arg, &mut args)?)?,
+                "--batch-time" => config.batch_time = Duration::from_millis(parse_number(&arg, &option_value(&arg, &mut args)?)?),
+                "--sample" => config.sample = Some(parse_sample(&arg, &option_value(&arg, &mut args)?)?),
+                "--monotonic-timestamps" => config.monotonic_timestamps = true,
+                "--reverse-dns" => config.reverse_dns = true,
+                "--verbose" => config.verbose = true,
+                "--session-ids" => config.session_ids = true,
+                "--audit-names" => config.audit_names = true,
+                "--since" => config.since = Some(parse_datetime(&arg, &option_value(&arg, &mut args)?)?),
+                "--until" => config.until = Some(parse_datetime(&arg, &option_value(&arg, &mut args)?)?),
+                "--max-errors" => config.max_errors = parse_number(&arg, &option_value(&arg, &mut args)?)?,
+                "--max-line-bytes" => config.max_line_bytes = Some(parse_memory_size(&arg, &option_value(&arg, &mut args)?)?),
+                "--max-message-bytes" => config.limits.max_message_bytes = Some(parse_memory_size(&arg, &option_value(&arg, &mut args)?)?),
+                "--line-format" => config.line_format = Some(sink::LineFormat::parse(&option_value(&arg, &mut args)?).map_err(|e| format!("Option {} {}", arg, e))?),
+                "--listen-backlog" => config.listen_backlog = Some(parse_number(&arg, &option_value(&arg, &mut args)?)?),
+                "--min-free-bytes" => config.min_free_bytes = Some(parse_byte_size(&arg, &option_value(&arg, &mut args)?)?),
+                "--low-disk" => config.low_disk = disk::LowDisk::from_name(&arg, &option_value(&arg, &mut args)?)?,
+                "--max-connections" => config.max_connections = Some(parse_number(&arg, &option_value(&arg, &mut args)?)?),
+                "--accept-retries" => config.accept_retries = parse_number(&arg, &option_value(&arg, &mut args)?)?,
+                "--sequence-numbers" => config.sequence_numbers = true,
+                "--dedup-on-reconnect" => config.dedup_on_reconnect = true,
+                "--reconnect-grace" => config.reconnect_grace = Some(Duration::from_secs(parse_number(&arg, &option_value(&arg, &mut args)?)?)),
+                "--truncate-existing" => config.truncate_existing = true,
+                "--top" => config.top = true,
+                "--tls-cert" => config.tls_cert = Some(PathBuf::from(option_value(&arg, &mut args)?)),
+                "--tls-key" => config.tls_key = Some(PathBuf::from(option_value(&arg, &mut args)?)),
+                "--tail-buffer" => config.tail_buffer = parse_number(&arg, &option_value(&arg, &mut args)?)?,
+                "--replay" => config.replay.extend(expand_glob(&option_value(&arg, &mut args)?)?),
+                "--realtime" => config.realtime = true,
+                "--speed" => match parse_number::<f64>(&arg, &option_value(&arg, &mut args)?)? {
+                    speed if speed > 0.0 && speed.is_finite() => config.speed = speed,
+                    speed => return Err(format!("Option --speed expects a factor above 0, got {}", speed))
+                },
+                "--realtime-max-gap" => config.realtime_max_gap = Duration::from_millis(parse_number(&arg, &option_value(&arg, &mut args)?)?),
+                "--merge-all" => config.merge_all = Some(PathBuf::from(option_value(&arg, &mut args)?)),
+                "--sort-by-time" => config.sort_by_time = Some(Duration::from_secs(parse_number(&arg, &option_value(&arg, &mut args)?)?)),
+                "--reserved-chars" => config.reserved_chars = ReservedChars::from_name(&arg, &option_value(&arg, &mut args)?)?,
+                "--replacement-char" => config.replacement_char = parse_char(&arg, &option_value(&arg, &mut args)?)?,
+                "--max-name-len" => match parse_number(&arg, &option_value(&arg, &mut args)?)? {
+                    // Room for the hash of a cut name and an extension like `.12.jsonl`.
+                    length if length < 32 => return Err("Option --max-name-len expects at least 32 bytes".to_string()),
+                    length => config.max_name_len = length
+                },
+                "--summary" => config.summary = true,
+                "--summary-channels" => config.summary_channels = parse_number(&arg, &option_value(&arg, &mut args)?)?,
+                "--notify-on" => config.notify_on = Some(parse_severity(&arg, &option_value(&arg, &mut args)?)?),
+                "--notify" => config.notify = notify::Alert::from_name(&arg, &option_value(&arg, &mut args)?)?,
+                "--notify-debounce" => config.notify_debounce = Duration::from_secs(parse_number(&arg, &option_value(&arg, &mut args)?)?),
+                "--stdin" => config.stdin = true,
+                "--split-by-channel" => config.split_by_channel = true,
+                "--shards" => match parse_number(&arg, &option_value(&arg, &mut args)?)? {
+                    0 => return Err("Option --shards expects at least 1 shard".to_string()),
+                    shards => config.shards = Some(shards)
+                },
+                "--shard-by" => config.shard_by = ShardBy::from_name(&arg, &option_value(&arg, &mut args)?)?,
+                "--kafka-brokers" => config.kafka_brokers = Some(option_value(&arg, &mut args)?),
+                "--kafka-topic" => config.kafka_topic = Some(option_value(&arg, &mut args)?),
+                "--kafka-key" => config.kafka_key = KafkaKey::from_name(&arg, &option_value(&arg, &mut args)?)?,
+                "--otlp" => config.otlp = Some(option_value(&arg, &mut args)?),
+                "--sink-plugin" => config.sink_plugin = Some(PathBuf::from(option_value(&arg, &mut args)?)),
+                "--sink-plugin-config" => config.sink_plugin_config = option_value(&arg, &mut args)?,
+                "--sqlite" => config.sqlite = Some(PathBuf::from(option_value(&arg, &mut args)?)),
+                "--parquet" => config.parquet = Some(PathBuf::from(option_value(&arg, &mut args)?)),
+                "--mirror-dir" => config.mirror_dir = Some(PathBuf::from(option_value(&arg, &mut args)?)),
+                "--save-raw" => config.save_raw = Some(PathBuf::from(option_value(&arg, &mut args)?)),
+                "--checkpoint-interval" => config.checkpoint_interval = Some(Duration::from_millis(parse_number(&arg, &option_value(&arg, &mut args)?)?)),
+                "--min-pid" => config.min_pid = parse_number(&arg, &option_value(&arg, &mut args)?)?,
+                "--pid" => config.pids.push(parse_number(&arg, &option_value(&arg, &mut args)?)?),
+                "--grep" => config.filters.grep.push(parse_regex(&arg, &option_value(&arg, &mut args)?)?),
+                "--grep-v" => config.filters.grep_v.push(parse_regex(&arg, &option_value(&arg, &mut args)?)?),
+                "--version-regex" => config.version_regex = Some(parse_regex(&arg, &option_value(&arg, &mut args)?)?),
+                "--print-config" => config.print_config = true,
+                "--redact" => config.filters.redactions.push(parse_redaction(&arg, &option_value(&arg, &mut args)?)?),
+                e => return Err(format!("Unknown option {}", e))
+            }
+        }
+
+        if !binds.is_empty() {
+            check_binds(&binds)?;
+            config.bind = binds;
+        }
+
+        if config.also_format.contains(&config.format) {
+            return Err("Option --also-format expects a format other than the --format".to_string());
+        }
+
+        if !config.also_format.is_empty() && config.format == Format::Bincode {
+            return Err("Option --also-format writes files next to those of --format text or json, it can't be combined with --format bincode".to_string());
+        }
+
+        if config.shards.is_some() && config.merge_all.is_some() {
+            return Err("Option --shards can't be combined with --merge-all, which writes one file".to_string());
+        }
+
+        if !config.also_format.is_empty() && config.merge_all.is_some() {
+            return Err("Option --also-format can't be combined with --merge-all, which writes one file".to_string());
+        }
+
+        if config.json_envelope && config.format != Format::Json && !config.also_format.contains(&Format::Json) && config.kafka_brokers.is_none() {
+            return Err("Option --json-envelope wraps JSON records, it requires --format json, --also-format json or --kafka-brokers".to_string());
+        }
+
+        if config.json_pretty && config.format != Format::Json {
+            return Err("Option --json-pretty requires --format json".to_string());
+        }
+
+        if config.group_by != GroupBy::Pid && config.archive_on_disconnect {
+            return Err("Option --archive-on-disconnect packs pid directories, it can't be combined with --group-by exe or exe-pid".to_string());
+        }
+
+        if config.group_by != GroupBy::Pid && config.format == Format::Bincode {
+            return Err("Option --group-by only applies to --format text and json".to_string());
+        }
+
+        if config.archive_remove && !config.archive_on_disconnect {
+            return Err("Option --archive-remove requires --archive-on-disconnect".to_string());
+        }
+
+        if config.line_format.is_some() && !config.drop_fields.is_empty() {
+            return Err("Option --drop-fields can't be combined with --line-format, leave the placeholders out instead".to_string());
+        }
+
+        if config.line_format.is_some() && config.format != Format::Text {
+            return Err("Option --line-format requires --format text".to_string());
+        }
+
+        if config.align.is_some() && (config.line_format.is_some() || config.format != Format::Text) {
+            return Err("Option --align only lines up the default text layout, it can't be combined with --line-format or another --format".to_string());
+        }
+
+        if config.wrap_width.is_some() && config.line_format.is_some() {
+            return Err("Option --wrap-width only wraps the default text layout, it can't be combined with --line-format".to_string());
+        }
+
+        if config.tls_cert.is_some() != config.tls_key.is_some() {
+            return Err("Options --tls-cert and --tls-key have to be given together".to_string());
+        }
+
+        if config.stdin && !config.replay.is_empty() {
+            return Err("Options --stdin and --replay can't be combined".to_string());
+        }
+
+        if config.realtime && config.replay.is_empty() {
+            return Err("Option --realtime requires --replay".to_string());
+        }
+
+        if config.speed != 1.0 && !config.realtime {
+            return Err("Option --speed requires --realtime".to_string());
+        }
+
+        if config.checkpoint_interval.is_some() && config.save_raw.is_none() {
+            return Err("Option --checkpoint-interval requires --save-raw".to_string());
+        }
+
+        if config.merge_all.is_some() && config.replay.is_empty() {
+            return Err("Option --merge-all requires --replay".to_string());
+        }
+
+        if config.merge_all.is_some() && config.format == Format::Bincode {
+            return Err("Option --merge-all writes lines, it can't be combined with --format bincode".to_string());
+        }
+
+        if config.merge_all.is_some() && (config.index || config.archive_on_disconnect) {
+            return Err("Option --merge-all writes no pid directories, it can't be combined with --index or --archive-on-disconnect".to_string());
+        }
+
+        if config.sort_by_time.is_some() && config.merge_all.is_none() {
+            return Err("Option --sort-by-time requires --merge-all".to_string());
+        }
+
+        if config.kafka_brokers.is_some() != config.kafka_topic.is_some() {
+            return Err("Options --kafka-brokers and --kafka-topic have to be given together".to_string());
+        }
+
+        if config.max_line_bytes == Some(0) {
+            return Err("Option --max-line-bytes expects at least 1".to_string());
+        }
+
+        if config.max_open_files == Some(0) {
+            return Err("Option --max-open-files expects at least 1".to_string());
+        }
+
+        if config.batch_size == 0 {
+            return Err("Option --batch-size expects at least 1".to_string());
+        }
+
+        if config.idle_close && config.idle_flush.is_none() {
+            return Err("Option --idle-close requires --idle-flush".to_string());
+        }
+
+        if config.heartbeat_lines == Some(Duration::ZERO) {
+            return Err("Option --heartbeat-lines expects at least 1".to_string());
+        }
+
+        if config.heartbeat_lines.is_some() && config.format != Format::Text {
+            return Err("Option --heartbeat-lines only works with --format text, the other formats have no comment lines".to_string());
+        }
 
-                let mut file = fs::OpenOptions::new().write(true).create(true).append(true).open(format!("{}/{}.txt", pid, msg.module)).unwrap();
+        if config.retention_days == Some(0) {
+            return Err("Option --retention-days expects at least 1".to_string());
+        }
+
+        if config.retention_interval.is_some() && config.retention_days.is_none() {
+            return Err("Option --retention-interval requires --retention-days".to_string());
+        }
+
+        if config.retention_interval == Some(Duration::ZERO) {
+            return Err("Option --retention-interval expects at least 1".to_string());
+        }
+
+        if config.group_by != GroupBy::Pid && config.retention_days.is_some() {
+            return Err("Option --retention-days removes pid directories, it can't be combined with --group-by exe or exe-pid".to_string());
+        }
 
-                let message = format!("{} {:?} [{}] > {}\n", timestamp.format("%F %T%.f").to_string(), msg.severity, msg.channel, msg.message);
+        if config.strict_parse && config.hexdump {
+            return Err("Option --strict-parse can't be combined with --hexdump, which goes on after a parse error".to_string());
+        }
 
-                file.write_all(message.as_bytes()).unwrap();
+        if let (Some(since), Some(until)) = (config.since, config.until) {
+            if since >= until {
+                return Err("Option --until expects a time after --since".to_string());
             }
         }
+
+        // After the loop, so the name is sanitized with the final --reserved-chars.
+        if prefix_hostname {
+            let hostname = hostname().map_err(|e| format!("Option --prefix-hostname could not get the hostname: {}", e))?;
+            config.hostname = Some(sanitize_component(&config, &hostname));
+        }
+
+        Ok(config)
     }
 }
 
-fn read_packet(reader: &mut Read) -> io::Result<Message> {
-    let raw_packet = try!(read_raw_packet(reader));
+impl Config {
+    /// Parses `args`, after the options of the `--config` file among them if there is one, so the
+    /// command line wins where an option can only be given once.
+    fn load(args: &[String]) -> Result<Config, String> {
+        let path = args.iter().position(|arg| arg == "--config").and_then(|i| args.get(i + 1));
 
-    return match raw_packet {
-        RawMessage::RawConnection(raw_message) => {
-            let message = ConnectionMessage {
-                version: raw_message.version,
-                pid: raw_message.pid,
-                machine_name: convert_string(raw_message.machine_name.to_vec()),
-                executable_path: convert_string(raw_message.executable_path.to_vec())
-            };
+        let mut options = match path {
+            Some(path) => read_config_file(Path::new(path))?,
+            None => Vec::new()
+        };
 
-            Ok(Message::Connection(message))
-        },
-        RawMessage::RawText(t, raw_message) => {
-            let mut message = TextMessage {
-                timestamp: raw_message.timestamp,
-                severity: Severity::from_u32(raw_message.severity),
-                module: convert_string(raw_message.module.to_vec()),
-                channel: convert_string(raw_message.channel.to_vec()),
-                message: convert_string(raw_message.message.to_vec())
-            };
+        options.extend(args.iter().cloned());
+        Config::from_args(options.into_iter())
+    }
+}
 
-            match t {
-                MessageType::Simple => (),
-                MessageType::Large => try!(read_continuation(&mut message, reader)),
-                e => panic!("Message type was {:?} but not in continuation mode /o\\", e)
-            }
+/// Reads a `--config` file into command line arguments.
+///
+/// Every line holds one option without its leading `--` and, separated by whitespace, its value
+/// if it takes one, like `min-severity warn` or `redact password=\S+=***`. The value runs to the
+/// end of the line and isn't quoted. Empty lines and lines starting with `#` are skipped.
+fn read_config_file(path: &Path) -> Result<Vec<String>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    let mut args = Vec::new();
 
-            Ok(Message::Text(message))
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
         }
+
+        let (name, value) = match line.find(char::is_whitespace) {
+            Some(split) => (&line[..split], Some(line[split..].trim_start())),
+            None => (line, None)
+        };
+
+        args.push(format!("--{}", name));
+        args.extend(value.map(str::to_string));
+    }
+
+    Ok(args)
+}
+
+/// Re-reads the `--config` file for SIGHUP, replacing `filters` if all its options parse. Clients
+/// keep the old values until they handle their next message.
+fn reload(filters: &RwLock<Arc<Filters>>, args: &[String]) -> Result<(), String> {
+    let config = Config::load(args)?;
+    *filters.write().unwrap() = Arc::new(config.filters);
+    RELOADS.fetch_add(1, Ordering::SeqCst);
+
+    Ok(())
+}
+
+/// How many times `reload` has run. Each client flushes its sinks once it sees this change, which
+/// happens before its next message so an idle client only flushes once it writes again.
+static RELOADS: AtomicUsize = AtomicUsize::new(0);
+
+/// Set by `--top`, whose table would be garbled by `info!` output.
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Set by `--verbose`, for `debug!` output.
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+/// The local wall-clock time used to prefix operational output.
+///
+/// The UTC offset is always printed, so the repeated hour at the end of daylight saving time can
+/// still be told apart and the times stay correct when read from another timezone.
+fn local_time() -> String {
+    Local::now().format("%F %T%.3f%:z").to_string()
+}
+
+fn option_value<I: Iterator<Item = String>>(name: &str, args: &mut I) -> Result<String, String> {
+    args.next().ok_or_else(|| format!("Option {} requires a value", name))
+}
+
+fn parse_bool(name: &str, value: &str) -> Result<bool, String> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        e => Err(format!("Option {} expects true or false, got {}", name, e))
+    }
+}
+
+fn parse_number<T: FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("Option {} expects a number, got {}", name, value))
+}
+
+/// Parses a number of bytes, with an optional suffix: `K`, `M`, `G` and `T` or their `KiB`
+/// spelling count in powers of 1024, `KB` and the like in powers of 1000, and `B` is just bytes.
+/// Suffixes ignore case and may follow a space, `500K`, `1 MiB` and `2gb` all parse.
+fn parse_byte_size(name: &str, value: &str) -> Result<u64, String> {
+    let digits = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (number, suffix) = value.split_at(digits);
+
+    let unit: u64 = match &suffix.trim_start().to_ascii_lowercase()[..] {
+        "" | "b" => 1,
+        "k" | "kib" => 1 << 10,
+        "m" | "mib" => 1 << 20,
+        "g" | "gib" => 1 << 30,
+        "t" | "tib" => 1 << 40,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        "tb" => 1_000_000_000_000,
+        _ => return Err(format!("Option {} expects a size like 4096, 500K or 2GiB, got {}", name, value))
     };
+
+    let number: u64 = number.parse().map_err(|_| format!("Option {} expects a size like 4096, 500K or 2GiB, got {}", name, value))?;
+    number.checked_mul(unit).ok_or_else(|| format!("Option {} was given a size too large, {}", name, value))
 }
 
-fn convert_string(vector: Vec<u8>) -> String {
-    let string: Vec<u8> = vector.iter().map(|x| *x).take_while(|x| *x != 0).collect();
+/// `parse_byte_size` for a size held in memory.
+fn parse_memory_size(name: &str, value: &str) -> Result<usize, String> {
+    let bytes = parse_byte_size(name, value)?;
 
-    return String::from_utf8_lossy(&string).to_string();
+    if bytes > usize::MAX as u64 {
+        return Err(format!("Option {} was given a size too large, {}", name, value));
+    }
+
+    Ok(bytes as usize)
 }
 
-fn read_continuation(message: &mut TextMessage, reader: &mut Read) -> io::Result<()> {
-    let raw_packet = try!(read_raw_packet(reader));
+/// Parses `1/N` or just `N`, keeping one in `N` messages.
+fn parse_sample(name: &str, value: &str) -> Result<u64, String> {
+    let every = value.strip_prefix("1/").unwrap_or(value);
 
-    match raw_packet {
-        RawMessage::RawText(MessageType::Continuation, raw_message) => {
-            message.message = message.message.clone() + &convert_string(raw_message.message.to_vec());
-            try!(read_continuation(message, reader));
-        }
-        RawMessage::RawText(MessageType::ContinuationEnd, raw_message) => {
-            message.message = message.message.clone() + &convert_string(raw_message.message.to_vec());
+    match every.parse() {
+        Ok(0) | Err(_) => Err(format!("Option {} expects 1/N with N at least 1, got {}", name, value)),
+        Ok(every) => Ok(every)
+    }
+}
+
+/// Parses an RFC 3339 time like `2017-07-14T02:40:00Z` into UTC, which message timestamps are in.
+fn parse_datetime(name: &str, value: &str) -> Result<NaiveDateTime, String> {
+    match DateTime::parse_from_rfc3339(value) {
+        Ok(datetime) => Ok(datetime.naive_utc()),
+        Err(e) => Err(format!("Option {} expects an RFC 3339 time like 2017-07-14T02:40:00Z, got {}: {}", name, value, e))
+    }
+}
+
+/// Whether a message sent at `timestamp` falls within `--since` and `--until`.
+fn in_window(config: &Config, timestamp: &NaiveDateTime) -> bool {
+    config.since.is_none_or(|since| *timestamp >= since) && config.until.is_none_or(|until| *timestamp < until)
+}
+
+/// Parses the encoding of every string field, Connection messages included. `auto` guesses it for
+/// each field, see `Encoding::detect`.
+fn parse_encoding(name: &str, value: &str) -> Result<Encoding, String> {
+    match value {
+        "utf8" => Ok(Encoding::Utf8),
+        "utf16" => Ok(Encoding::Utf16Le),
+        "auto" => Ok(Encoding::Auto),
+        e => Err(format!("Option {} expects utf8, utf16 or auto, got {}", name, e))
+    }
+}
+
+fn parse_severity(name: &str, value: &str) -> Result<Severity, String> {
+    match value {
+        "info" => Ok(Severity::Info),
+        "notice" => Ok(Severity::Notice),
+        "warn" => Ok(Severity::Warn),
+        "error" => Ok(Severity::Error),
+        e => Err(format!("Option {} expects info, notice, warn or error, got {}", name, e))
+    }
+}
+
+/// Parses `<channel>=<severity>` for `--channel-severity`.
+fn parse_channel_severity(name: &str, value: &str) -> Result<(String, Severity), String> {
+    match value.rfind('=') {
+        Some(split) if split > 0 => Ok((value[..split].to_string(), parse_severity(name, &value[split + 1..])?)),
+        _ => Err(format!("Option {} expects <channel>=<severity>, got {}", name, value))
+    }
+}
+
+fn parse_endianness(name: &str, value: &str) -> Result<Endianness, String> {
+    match value {
+        "little" => Ok(Endianness::Little),
+        "big" => Ok(Endianness::Big),
+        e => Err(format!("Option {} expects little or big, got {}", name, e))
+    }
+}
+
+fn parse_header_layout(name: &str, value: &str) -> Result<HeaderLayout, String> {
+    match value {
+        "type-u32" => Ok(HeaderLayout::TypeU32),
+        "type-u64" => Ok(HeaderLayout::TypeU64),
+        "swapped" => Ok(HeaderLayout::Swapped),
+        e => Err(format!("Option {} expects type-u32, type-u64 or swapped, got {}", name, e))
+    }
+}
+
+/// Parses a list like `simple,large`. Connection messages are read either way, leaving
+/// `connection` out only keeps them from being logged.
+fn parse_message_types(name: &str, value: &str) -> Result<MessageTypes, String> {
+    let mut types = MessageTypes { connection: false, simple: false, large: false };
+
+    for message_type in value.split(',') {
+        match message_type.trim() {
+            "connection" => types.connection = true,
+            "simple" => types.simple = true,
+            "large" => types.large = true,
+            e => return Err(format!("Option {} expects connection, simple or large, got {}", name, e))
         }
-        _ => panic!("Message type was not a continuation but was in continuation mode /o\\")
     }
 
-    return Ok(());
+    Ok(types)
 }
 
-fn read_raw_packet(reader: &mut Read) -> io::Result<RawMessage> {
-    let payload_size = cmp::max(mem::size_of::<RawConnectionMessage>(), mem::size_of::<RawTextMessage>());
+/// Parses an address like `127.0.0.1:3273` or `[::1]:3273`.
+///
+/// Link-local IPv6 addresses take their zone after a `%`, either as the number the standard
+/// library understands or as the name of an interface, as in `[fe80::1%eth0]:3273`.
+fn parse_address(name: &str, value: &str) -> Result<SocketAddr, String> {
+    let invalid = || format!("Option {} expects an address like 127.0.0.1:3273, got {}", name, value);
+
+    if let Ok(address) = value.parse() {
+        return Ok(address);
+    }
 
-    let message_type = MessageType::from_u32(try!(reader.read_u32::<LittleEndian>()));
-    let _ = try!(reader.read_u32::<LittleEndian>()); // Padding
+    let (host, port) = value.strip_prefix('[').and_then(|rest| rest.split_once("]:")).ok_or_else(invalid)?;
+    let (ip, zone) = host.split_once('%').ok_or_else(invalid)?;
 
-    let mut payload = vec![0; payload_size];
-    try!(reader.read_exact(&mut payload));
+    let ip: Ipv6Addr = ip.parse().map_err(|_| invalid())?;
+    let port: u16 = port.parse().map_err(|_| invalid())?;
+    let scope = interface_index(zone).ok_or_else(|| format!("Option {} names an unknown interface {} in {}", name, zone, value))?;
 
-    return match message_type {
-        MessageType::Connection => unsafe {
-            let mut message: RawConnectionMessage = mem::zeroed();
+    Ok(SocketAddr::V6(SocketAddrV6::new(ip, port, 0, scope)))
+}
 
-            ptr::copy_nonoverlapping(payload.as_ptr() as *const RawConnectionMessage, &mut message as *mut RawConnectionMessage, 1);
+#[cfg(unix)]
+fn hostname() -> io::Result<String> {
+    let mut buffer = [0u8; 256];
 
-            Ok(RawMessage::RawConnection(message))
-        },
-        t => unsafe {
-            let mut message: RawTextMessage = mem::zeroed();
+    if unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let length = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+
+    match String::from_utf8_lossy(&buffer[..length]).into_owned() {
+        ref name if name.is_empty() || name == "." || name == ".." => Err(io::Error::other(format!("{:?} can't be a directory name", name))),
+        name => Ok(name)
+    }
+}
+
+#[cfg(not(unix))]
+fn hostname() -> io::Result<String> {
+    env::var("COMPUTERNAME").map_err(io::Error::other)
+}
+
+#[cfg(unix)]
+fn interface_index(interface: &str) -> Option<u32> {
+    use std::ffi::CString;
+
+    let interface = CString::new(interface).ok()?;
 
-            ptr::copy_nonoverlapping(payload.as_ptr() as *const RawTextMessage, &mut message as *mut RawTextMessage, 1);
+    match unsafe { libc::if_nametoindex(interface.as_ptr()) } {
+        0 => None,
+        index => Some(index)
+    }
+}
+
+#[cfg(not(unix))]
+fn interface_index(_: &str) -> Option<u32> {
+    None
+}
+
+/// Refuses `--bind` addresses that would fail to bind next to each other, naming both.
+fn check_binds(binds: &[SocketAddr]) -> Result<(), String> {
+    for (i, first) in binds.iter().enumerate() {
+        for second in &binds[i + 1..] {
+            if let Some(reason) = bind_conflict(first, second) {
+                return Err(format!("Option --bind {} conflicts with --bind {}: {}", first, second, reason));
+            }
+        }
+    }
 
-            Ok(RawMessage::RawText(t, message))
+    Ok(())
+}
+
+fn bind_conflict(first: &SocketAddr, second: &SocketAddr) -> Option<&'static str> {
+    // Port 0 picks a free port for each of them.
+    if first.port() != second.port() || first.port() == 0 {
+        return None;
+    }
+
+    if first == second {
+        return Some("the same address is given twice");
+    }
+
+    let any = |address: &SocketAddr| address.ip().is_unspecified();
+
+    if first.is_ipv4() == second.is_ipv4() {
+        if any(first) || any(second) {
+            return Some("the any-address already covers every address of its family on that port");
         }
+    } else if (first.is_ipv6() && any(first)) || (second.is_ipv6() && any(second)) {
+        return Some("the IPv6 any-address also accepts IPv4 on most systems");
+    }
+
+    None
+}
+
+/// Expands a `*` or `?` in the file name part of `pattern` into the matching files, sorted by
+/// name. Patterns without wildcards are returned as they are.
+fn expand_glob(pattern: &str) -> Result<Vec<PathBuf>, String> {
+    let path = Path::new(pattern);
+    let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("");
+
+    if !name.contains('*') && !name.contains('?') {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let expression = format!("^{}$", regex::escape(name).replace("\\*", ".*").replace("\\?", "."));
+    let regex = Regex::new(&expression).unwrap();
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new(".")
     };
+
+    let entries = fs::read_dir(directory).map_err(|e| format!("Could not expand {}: {}", pattern, e))?;
+    let mut paths: Vec<PathBuf> = entries.filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_str().is_some_and(|name| regex.is_match(name)))
+        .map(|entry| entry.path())
+        .collect();
+
+    if paths.is_empty() {
+        return Err(format!("No files match {}", pattern));
+    }
+
+    paths.sort();
+    Ok(paths)
 }
 
-fn main() {
-    let listener = TcpListener::bind("127.0.0.1:3273").unwrap();
+fn parse_char(name: &str, value: &str) -> Result<char, String> {
+    let mut chars = value.chars();
 
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
-                thread::spawn(move|| {
-                    handle_client(stream)
-                });
-            }
-            Err(e) => { println!("Connection failed {:?}", e) }
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        _ => Err(format!("Option {} expects a single character, got {}", name, value))
+    }
+}
+
+fn parse_regex(name: &str, value: &str) -> Result<Regex, String> {
+    Regex::new(value).map_err(|e| format!("Option {} has an invalid regex: {}", name, e))
+}
+
+/// Parses `<regex>=<replacement>`, splitting on the last `=` so the pattern itself may contain one.
+fn parse_redaction(name: &str, value: &str) -> Result<(Regex, String), String> {
+    let split = value.rfind('=').ok_or_else(|| format!("Option {} expects <regex>=<replacement>, got {}", name, value))?;
+    let regex = parse_regex(name, &value[..split])?;
+
+    Ok((regex, value[split + 1..].to_string()))
+}
+
+/// Applies `--channel-severity` and then `--unknown-severity-as` to `message`, returning whether
+/// it is kept and its severity passes `--min-severity`.
+fn filter_severity(filters: &Filters, message: &mut TextMessage) -> bool {
+    if let Some(severity) = filters.channel_severities.get(&*message.channel) {
+        message.severity = *severity;
+    }
+
+    if let Severity::Unknown(_) = message.severity {
+        match filters.unknown_severity {
+            UnknownSeverity::Keep => (),
+            UnknownSeverity::As(severity) => message.severity = severity,
+            UnknownSeverity::Drop => return false
         }
     }
 
+    filters.min_severity.is_none_or(|min| at_least(message.severity, min))
+}
+
+/// Whether `severity` is `min` or worse. Unknown severities can't be ranked, so they always are.
+fn at_least(severity: Severity, min: Severity) -> bool {
+    match severity {
+        Severity::Unknown(_) => true,
+        severity => severity.to_u32() >= min.to_u32()
+    }
+}
+
+/// Whether an assembled message body passes `--grep` and `--grep-v`. It is matched as the client
+/// sent it, before `--redact`.
+fn filter_body(filters: &Filters, message: &str) -> bool {
+    let wanted = filters.grep.is_empty() || filters.grep.iter().any(|regex| regex.is_match(message));
+
+    wanted && !filters.grep_v.iter().any(|regex| regex.is_match(message))
+}
+
+/// Applies every `--redact` rule in order to an assembled message body.
+fn redact(filters: &Filters, message: String) -> String {
+    filters.redactions.iter().fold(message, |message, (regex, replacement)| {
+        regex.replace_all(&message, replacement.as_str()).into_owned()
+    })
+}
+
+/// The game build in `executable_path` for `--version-regex`, the first capture group of the
+/// first match or the whole match if the regex has no groups. `None` without the option, when
+/// nothing matched, or when the group didn't take part in the match.
+fn game_build(config: &Config, executable_path: &str) -> Option<String> {
+    let captures = config.version_regex.as_ref()?.captures(executable_path)?;
+    let build = if captures.len() > 1 { captures.get(1) } else { captures.get(0) };
+
+    build.map(|build| build.as_str().to_string())
+}
+
+/// The name a channel that holds a number is written as, `chan-<id>`, or `chan-` and its bytes in
+/// hex when there are too many of them for a u64.
+fn numeric_channel(message: &TextMessage) -> String {
+    match message.channel_id() {
+        Some(id) => format!("chan-{}", id),
+        None => format!("chan-{}", message.channel.chars().map(|c| format!("{:02x}", c as u32)).collect::<String>())
+    }
+}
+
+/// Replaces an identifying connection field with a salted FNV-1a hash for `--anonymize-connection`.
+///
+/// The hash only depends on `--anon-salt` and the value, so the same machine still gets the same
+/// name across connections and runs without the name itself being written anywhere.
+fn anonymize(salt: &str, value: &str) -> String {
+    if value.is_empty() {
+        return String::new();
+    }
+
+    let hash = salt.bytes().chain(Some(0)).chain(value.bytes()).fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    });
+
+    format!("anon-{:016x}", hash)
+}
+
+/// Refuses paths that traverse a symlink when `--follow-symlinks false` is given.
+///
+/// Every existing prefix of `path` is checked with `symlink_metadata`, so a symlinked pid
+/// directory is caught as well as a symlinked module file. Components that do not exist yet
+/// are fine, they will be created as real directories and files.
+fn check_symlinks(config: &Config, path: &Path) -> io::Result<()> {
+    if config.follow_symlinks {
+        return Ok(());
+    }
+
+    let mut prefix = PathBuf::new();
+
+    for component in path.components() {
+        prefix.push(component);
+
+        match fs::symlink_metadata(&prefix) {
+            Ok(ref metadata) if metadata.file_type().is_symlink() => {
+                let message = format!("Refusing to follow symlink {}", prefix.display());
+                return Err(io::Error::new(io::ErrorKind::PermissionDenied, message));
+            },
+            Ok(_) => (),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e)
+        }
+    }
+
+    Ok(())
+}
+
+fn create_dir(config: &Config, path: &Path) -> io::Result<()> {
+    check_symlinks(config, path)?;
+
+    // Like a file in the way, a read-only file system or missing permissions.
+    fs::create_dir_all(path).map_err(|e| io::Error::new(e.kind(), format!("Could not create the directory {}: {}", path.display(), e)))
+}
+
+fn open_log(config: &Config, path: &Path) -> io::Result<LogFile> {
+    check_symlinks(config, path)?;
+
+    let mut options = fs::OpenOptions::new();
+    options.create(true);
+
+    match config.append_mode {
+        AppendMode::OAppend => options.append(true),
+        AppendMode::SeekEnd => options.write(true)
+    };
+
+    if !config.follow_symlinks {
+        no_follow(&mut options);
+    }
+
+    Ok(LogFile { file: options.open(path)?, mode: config.append_mode })
+}
+
+/// A log file as `open_log` opened it, appending the way `--append-mode` says.
+struct LogFile {
+    file: fs::File,
+    mode: AppendMode
+}
+
+impl Write for LogFile {
+    /// Writes all of `buf` or fails, with `AppendMode::SeekEnd` under the lock in one go, so a
+    /// buffered line is never split around another writer's.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.mode == AppendMode::OAppend {
+            return self.file.write(buf);
+        }
+
+        self.file.lock()?;
+        let result = self.file.seek(io::SeekFrom::End(0)).and_then(|_| self.file.write_all(buf));
+        let unlocked = self.file.unlock();
+
+        result.and(unlocked).map(|_| buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Creates `path` holding just `header` unless it already exists, without ever exposing a
+/// half-written file.
+///
+/// The header goes into a uniquely named temporary file next to the target, which is then moved
+/// into place by `move_into_place`.
+fn create_atomic(config: &Config, path: &Path, header: &[u8]) -> io::Result<()> {
+    static TEMPORARY: AtomicUsize = AtomicUsize::new(0);
+
+    if fs::symlink_metadata(path).is_ok() {
+        return Ok(());
+    }
+
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(format!(".{}-{}.tmp", process::id(), TEMPORARY.fetch_add(1, Ordering::SeqCst)));
+    let temporary = PathBuf::from(temporary);
+
+    check_symlinks(config, &temporary)?;
+
+    let result = fs::OpenOptions::new().write(true).create_new(true).open(&temporary)
+        .and_then(|mut file| file.write_all(header))
+        .and_then(|_| move_into_place(&temporary, path, |temporary, path| fs::hard_link(temporary, path)));
+
+    let _ = fs::remove_file(&temporary);
+
+    match result {
+        Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(()),
+        result => result
+    }
+}
+
+/// Moves the complete `temporary` to `path`, failing with `AlreadyExists` when something is there.
+///
+/// A hard link fails instead of replacing when another client created the file first, so their
+/// data is never clobbered. Filesystems without hard links, like FAT or many network mounts, get a
+/// rename instead, which only replaces a file created between the check and the rename.
+fn move_into_place(temporary: &Path, path: &Path, hard_link: fn(&Path, &Path) -> io::Result<()>) -> io::Result<()> {
+    match hard_link(temporary, path) {
+        Err(ref e) if e.kind() == io::ErrorKind::Unsupported || e.kind() == io::ErrorKind::PermissionDenied => {
+            if fs::symlink_metadata(path).is_ok() {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", path.display())));
+            }
+
+            fs::rename(temporary, path)
+        },
+        result => result
+    }
+}
+
+/// Removes what an earlier run left at `path` for `--truncate-existing`, the first time any client
+/// of this run gets there.
+///
+/// The file is removed rather than truncated so `create_atomic` writes its header again. The lock
+/// is held while removing, so no other client can start appending to the old file in between.
+fn discard_previous_run(path: &Path) -> io::Result<()> {
+    static CLAIMED: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+
+    let mut claimed = CLAIMED.lock().unwrap();
+
+    if !claimed.insert(path.to_path_buf()) {
+        return Ok(());
+    }
+
+    match fs::remove_file(path) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result
+    }
+}
+
+#[cfg(unix)]
+fn no_follow(options: &mut fs::OpenOptions) {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    // Closes the window between check_symlinks and open for the final component.
+    options.custom_flags(libc::O_NOFOLLOW);
+}
+
+#[cfg(not(unix))]
+fn no_follow(_: &mut fs::OpenOptions) {}
+
+/// An open log file, shared between the client writing it and the `--idle-flush` and
+/// `--heartbeat-lines` sweeps.
+struct Handle {
+    /// None once `--idle-close` has closed it, the next write opens it again.
+    file: Option<io::BufWriter<LogFile>>,
+    last_write: Instant,
+    /// A module file, which gets the `--heartbeat-lines` comments.
+    heartbeat: bool,
+    /// Its copy under `--mirror-dir`, open while the file is.
+    mirror: Option<LogFile>
+}
+
+impl Handle {
+    /// Writes `bytes` to the copy of `path` under `--mirror-dir` after they went to the file
+    /// itself, opening the copy first if it isn't open.
+    ///
+    /// The mirror is only a backup, a failure is a warning and closes the copy, and the next write
+    /// tries to open it again. Whatever was written in between is missing from the copy.
+    fn mirror(&mut self, config: &Config, path: &Path, header: &[u8], bytes: &[u8]) {
+        let mirrored = match config.mirror_dir {
+            Some(ref directory) => directory.join(path),
+            None => return
+        };
+
+        if self.mirror.is_none() {
+            match open_new(config, &mirrored, header) {
+                Ok(file) => self.mirror = Some(file),
+                Err(e) => return warn!("Could not open the mirror {}: {}", mirrored.display(), e)
+            }
+        }
+
+        if let Err(e) = self.mirror.as_mut().unwrap().write_all(bytes) {
+            warn!("Could not write to the mirror {}: {}", mirrored.display(), e);
+            self.mirror = None;
+        }
+    }
+}
+
+/// Every handle of every client, for the `--idle-flush` and `--heartbeat-lines` sweeps. Handles of
+/// clients that have gone away are dropped from it on the next sweep.
+static HANDLES: Mutex<Vec<Weak<Mutex<Handle>>>> = Mutex::new(Vec::new());
+
+/// Open log files of one client, keyed by their full path.
+///
+/// Everything that goes into the path, like the `--date-dir` date, also decides which handle is
+/// used, so the first message after midnight opens a file in the new date directory by itself.
+///
+/// Writes are flushed right away unless `--idle-flush` is given, in which case they are buffered
+/// until the buffer fills, the client disconnects, or the file has gone quiet for that long.
+struct HandleCache {
+    files: HashMap<PathBuf, Arc<Mutex<Handle>>>,
+    heartbeat: bool
+}
+
+impl HandleCache {
+    fn new() -> HandleCache {
+        HandleCache { files: HashMap::new(), heartbeat: false }
+    }
+
+    /// A cache of module files, which get `--heartbeat-lines` if it is given.
+    fn modules(config: &Config) -> HandleCache {
+        HandleCache { files: HashMap::new(), heartbeat: config.heartbeat_lines.is_some() }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        for handle in self.files.values() {
+            if let Some(ref mut file) = handle.lock().unwrap().file {
+                file.flush()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Appends `bytes` to `path`, creating the file with `header` as its first bytes if needed.
+    fn write(&mut self, config: &Config, path: PathBuf, header: &[u8], bytes: &[u8]) -> io::Result<()> {
+        if !self.files.contains_key(&path) {
+            if config.truncate_existing {
+                discard_previous_run(&path)?;
+
+                if let Some(ref directory) = config.mirror_dir {
+                    discard_previous_run(&directory.join(&path))?;
+                }
+            }
+
+            let handle = Arc::new(Mutex::new(Handle { file: None, last_write: Instant::now(), heartbeat: self.heartbeat, mirror: None }));
+
+            if config.idle_flush.is_some() || self.heartbeat {
+                HANDLES.lock().unwrap().push(Arc::downgrade(&handle));
+            }
+
+            self.files.insert(path.clone(), handle);
+        }
+
+        if let Some(max) = config.max_open_files {
+            if self.files[&path].lock().unwrap().file.is_none() {
+                self.make_room(max)?;
+            }
+        }
+
+        let mut handle = self.files[&path].lock().unwrap();
+
+        if handle.file.is_none() {
+            let created = config.index && fs::symlink_metadata(&path).is_err();
+            handle.file = Some(io::BufWriter::new(open_with_retries(config, &path, || open_new(config, &path, header))?));
+
+            if let Some(directory) = index::pid_directory(config, &path).filter(|_| created) {
+                if let Err(e) = index::update(&directory) {
+                    warn!("Could not update the index of {}: {}", directory.display(), e);
+                }
+            }
+        }
+
+        let file = handle.file.as_mut().unwrap();
+        file.write_all(bytes)?;
+        stats::STATS.wrote(bytes.len());
+
+        if config.idle_flush.is_none() {
+            file.flush()?;
+        }
+
+        handle.mirror(config, &path, header, bytes);
+        handle.last_write = Instant::now();
+
+        Ok(())
+    }
+
+    /// Flushes and closes the least recently written files until one more can be opened without
+    /// going over `--max-open-files`. They stay in the cache and are opened again on their next
+    /// write.
+    fn make_room(&self, max: usize) -> io::Result<()> {
+        let mut open: Vec<(Instant, &Arc<Mutex<Handle>>)> = self.files.values().filter_map(|handle| {
+            let locked = handle.lock().unwrap();
+            locked.file.as_ref().map(|_| (locked.last_write, handle))
+        }).collect();
+
+        open.sort_by_key(|&(last_write, _)| last_write);
+
+        for (_, handle) in open.iter().take((open.len() + 1).saturating_sub(max)) {
+            let mut handle = handle.lock().unwrap();
+            handle.mirror = None;
+
+            if let Some(mut file) = handle.file.take() {
+                file.flush()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Opens `path` with `open`, trying again up to `--open-retries` times when that fails, first after
+/// `--open-retry-delay` and then twice as long after every further failure. Each retry is logged.
+///
+/// Network filesystems fail an open now and then, like NFS with a stale file handle, that works
+/// when tried again. A symlink that `--follow-symlinks` doesn't allow is refused straight away.
+fn open_with_retries<T, F: FnMut() -> io::Result<T>>(config: &Config, path: &Path, mut open: F) -> io::Result<T> {
+    let mut delay = config.open_retry_delay;
+    let mut retries = 0;
+
+    loop {
+        match open() {
+            Err(ref e) if retries < config.open_retries && e.kind() != io::ErrorKind::PermissionDenied => {
+                retries += 1;
+                warn!("Could not open {}, retrying in {}ms ({} of {}): {}", path.display(), delay.as_millis(), retries, config.open_retries, e);
+
+                thread::sleep(delay);
+                delay = delay.saturating_mul(2);
+            },
+            result => return result
+        }
+    }
+}
+
+/// Opens `path` for appending, creating it and its directory with `header` first if needed.
+fn open_new(config: &Config, path: &Path, header: &[u8]) -> io::Result<LogFile> {
+    if let Some(parent) = path.parent() {
+        create_dir(config, parent)?;
+    }
+
+    create_atomic(config, path, header)?;
+    open_log(config, path)
+}
+
+/// Flushes every handle that hasn't been written to for `idle`, closing it as well with
+/// `--idle-close`. Runs for the lifetime of the server.
+///
+/// Each handle is locked on its own while it is looked at, so a client only ever waits for the
+/// sweep of the one file it is writing to.
+fn sweep_idle(idle: Duration, close: bool) {
+    loop {
+        thread::sleep(cmp::min(idle, Duration::from_secs(1)));
+
+        let handles: Vec<Arc<Mutex<Handle>>> = {
+            let mut handles = HANDLES.lock().unwrap();
+            handles.retain(|handle| handle.strong_count() > 0);
+            handles.iter().filter_map(|handle| handle.upgrade()).collect()
+        };
+
+        for handle in handles {
+            let mut handle = handle.lock().unwrap();
+
+            if handle.last_write.elapsed() < idle {
+                continue;
+            }
+
+            if let Some(ref mut file) = handle.file {
+                if let Err(e) = file.flush() {
+                    warn!("Could not flush an idle log file: {}", e);
+                }
+            }
+
+            if close {
+                handle.file = None;
+                handle.mirror = None;
+            }
+        }
+    }
+}
+
+/// Writes a `# heartbeat <time>` line into every open module file of a connected client every
+/// `interval`, for `--heartbeat-lines`. Runs for the lifetime of the server.
+fn sweep_heartbeats(interval: Duration, terminator: sink::LineTerminator) {
+    loop {
+        thread::sleep(interval);
+        write_heartbeats(terminator);
+    }
+}
+
+/// One round of `--heartbeat-lines`. Each line is flushed right away, even with `--idle-flush`,
+/// as keeping the modification time fresh is the point. It doesn't count as a write for
+/// `--idle-flush`, and files closed by `--idle-close` or `--max-open-files` are left alone.
+fn write_heartbeats(terminator: sink::LineTerminator) {
+    let handles: Vec<Arc<Mutex<Handle>>> = {
+        let mut handles = HANDLES.lock().unwrap();
+        handles.retain(|handle| handle.strong_count() > 0);
+        handles.iter().filter_map(|handle| handle.upgrade()).collect()
+    };
+
+    let line = format!("# heartbeat {}{}", local_time(), terminator.as_str());
+
+    for handle in handles {
+        let mut handle = handle.lock().unwrap();
+
+        if !handle.heartbeat {
+            continue;
+        }
+
+        if let Some(ref mut file) = handle.file {
+            match file.write_all(line.as_bytes()).and_then(|_| file.flush()) {
+                Ok(()) => stats::STATS.wrote(line.len()),
+                Err(e) => warn!("Could not write a heartbeat: {}", e)
+            }
+        }
+
+        if let Some(ref mut mirror) = handle.mirror {
+            if let Err(e) = mirror.write_all(line.as_bytes()) {
+                warn!("Could not write a heartbeat to a mirror: {}", e);
+                handle.mirror = None;
+            }
+        }
+    }
+}
+
+/// Makes a client-supplied name safe to use as a single path component.
+///
+/// Reserved characters are replaced with `--replacement-char`, which also takes care of path
+/// separators, and `.` and `..` are replaced entirely so a name can never leave its directory.
+/// Windows additionally rejects names ending in a dot or space, those are replaced too.
+///
+/// A name that comes out longer than `--max-name-len` bytes is cut short and ends in `~` and the
+/// FNV-1a hash of the whole name instead, so it can still be created and two long names that only
+/// differ past the cut still get a file of their own.
+fn sanitize_component(config: &Config, name: &str) -> String {
+    sanitize_file_name(config, name, "")
+}
+
+/// `sanitize_component` of `name` followed by `suffix`, like `.txt`, with the two together kept
+/// within `--max-name-len` and the suffix left whole.
+fn sanitize_file_name(config: &Config, name: &str, suffix: &str) -> String {
+    let replacement = config.replacement_char;
+    let mut component: String = name.chars().map(|c| if config.reserved_chars.is_reserved(c) { replacement } else { c }).collect();
+
+    if config.reserved_chars == ReservedChars::Windows {
+        let kept = component.trim_end_matches(['.', ' ']).len();
+        let trailing = component.len() - kept;
+
+        component.truncate(kept);
+        component.extend(iter::repeat_n(replacement, trailing));
+    }
+
+    if component == "." || component == ".." {
+        component = component.chars().map(|_| replacement).collect();
+    }
+
+    let limit = config.max_name_len.saturating_sub(suffix.len());
+
+    if component.len() > limit {
+        let hash = format!("~{:016x}", name.bytes().fold(0xcbf29ce484222325u64, |hash, byte| (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)));
+        let mut cut = limit.saturating_sub(hash.len());
+
+        while !component.is_char_boundary(cut) {
+            cut -= 1;
+        }
+
+        component.truncate(cut);
+        component.push_str(&hash);
+    }
+
+    component + suffix
+}
+
+/// The directory every file is written under: `{hostname}` with `--prefix-hostname`, otherwise
+/// the working directory as an empty path.
+fn output_root(config: &Config) -> PathBuf {
+    config.hostname.as_ref().map_or_else(PathBuf::new, PathBuf::from)
+}
+
+/// The name `--group-by exe` files a client under: the last component of `executable_path`, with
+/// either kind of separator, without its extension and made safe as a path component. `unknown`
+/// for a client that announced no path, or hasn't sent a Connection message yet.
+///
+/// With `--anonymize-connection` this is the name of the hash, which still groups the same
+/// executable together.
+fn exe_name(config: &Config, executable_path: Option<&str>) -> String {
+    let name = executable_path.and_then(|path| path.rsplit(['\\', '/']).next()).unwrap_or("");
+    let stem = match name.rfind('.') {
+        Some(dot) if dot > 0 => &name[..dot],
+        _ => name
+    };
+
+    if stem.is_empty() { "unknown".to_string() } else { sanitize_component(config, stem) }
+}
+
+/// The directories `--group-by` puts the files of `pid` in, below the date with `--date-dir`.
+fn group_directory(config: &Config, pid: u64, executable_path: Option<&str>) -> PathBuf {
+    match config.group_by {
+        GroupBy::Pid => PathBuf::from(pid.to_string()),
+        GroupBy::Exe => PathBuf::from(exe_name(config, executable_path)),
+        GroupBy::ExePid => PathBuf::from(exe_name(config, executable_path)).join(pid.to_string())
+    }
+}
+
+/// Builds `{pid}/{module}.txt`, or `{YYYY-MM-DD}/{pid}/{module}.txt` with `--date-dir`, where
+/// `--group-by` may replace `{pid}` with `{exe}` or `{exe}/{pid}`. `--split-by-channel` turns the
+/// module into a directory of `{channel}.txt` files, and a `format` of json uses `.jsonl` instead
+/// of `.txt`. `--prefix-hostname` puts all of it under `{hostname}/`. With `--shards` the file name
+/// gets the `shard` before its extension, `{module}.{shard}.txt`. Names too long for
+/// `--max-name-len` are cut, see `sanitize_component`.
+///
+/// The date is the UTC date of the message timestamp, not of the time it was received.
+#[allow(clippy::too_many_arguments)]
+fn log_path(config: &Config, format: Format, pid: u64, executable_path: Option<&str>, timestamp: &NaiveDateTime, module: &str, channel: &str, shard: Option<u64>) -> PathBuf {
+    let mut path = output_root(config);
+
+    if config.date_dir {
+        path.push(timestamp.format("%Y-%m-%d").to_string());
+    }
+
+    let extension = match shard {
+        Some(shard) => format!(".{}.{}", shard, format.extension()),
+        None => format!(".{}", format.extension())
+    };
+
+    path.push(group_directory(config, pid, executable_path));
+
+    if config.split_by_channel {
+        path.push(sanitize_component(config, module));
+        path.push(sanitize_file_name(config, channel, &extension));
+    } else {
+        path.push(sanitize_file_name(config, module, &extension));
+    }
+
+    path
+}
+
+/// State shared by every client thread.
+struct Context {
+    config: Config,
+    tail: Option<Arc<http::TailBuffer>>,
+    top: Option<Arc<top::Counters>>,
+    pids: tarball::Pids,
+    recent: reconnect::Recent,
+    written: reconnect::Written,
+    reverse_dns: Option<dns::ReverseDns>,
+    /// `clock::SystemClock`, unless a test runs sessions at a time of its own.
+    clock: Arc<dyn clock::Clock>,
+    /// The filters of `config`, until SIGHUP replaces them.
+    filters: Arc<RwLock<Arc<Filters>>>,
+    /// The command line, read again with the `--config` file on reload.
+    args: Vec<String>,
+    clients: control::Clients,
+    notifier: Option<notify::Notifier>,
+    #[cfg(feature = "sqlite")]
+    sqlite: Option<sqlite::Writer>,
+    #[cfg(feature = "parquet")]
+    parquet: Option<columnar::Writer>,
+    #[cfg(feature = "kafka")]
+    kafka: Option<kafka::Writer>,
+    #[cfg(feature = "otlp")]
+    otlp: Option<otlp::Writer>,
+    #[cfg(feature = "plugin")]
+    plugin: Option<plugin::Plugin>,
+    /// `--tls-cert` and `--tls-key`, accepted clients have to connect with TLS when set.
+    #[cfg(feature = "tls")]
+    tls: Option<Arc<rustls::ServerConfig>>
+}
+
+impl Context {
+    /// A context with only `config`, none of the servers, background sinks or TLS set up, for the
+    /// tests to run sessions in.
+    #[cfg(test)]
+    fn new(config: Config) -> Context {
+        Context {
+            tail: None,
+            top: None,
+            pids: tarball::Pids::new(),
+            recent: reconnect::Recent::new(config.reconnect_grace),
+            written: reconnect::Written::new(config.reconnect_grace.unwrap_or(reconnect::DEDUP_KEEP)),
+            reverse_dns: None,
+            clock: Arc::new(clock::SystemClock),
+            filters: Arc::new(RwLock::new(Arc::new(config.filters.clone()))),
+            args: Vec::new(),
+            clients: control::Clients::new(config.recent_messages),
+            notifier: None,
+            #[cfg(feature = "sqlite")]
+            sqlite: None,
+            #[cfg(feature = "parquet")]
+            parquet: None,
+            #[cfg(feature = "kafka")]
+            kafka: None,
+            #[cfg(feature = "otlp")]
+            otlp: None,
+            #[cfg(feature = "plugin")]
+            plugin: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+            config
+        }
+    }
+
+    /// The sinks a new client writes to: its text files or archive, or the `--merge-all` file,
+    /// and the files of each `--also-format`, then the `--errors-to-stderr` mirror, the `--notify-on` alerts, the `--summary`, the
+    /// `--sqlite` database, the `--parquet` file, the `--kafka-brokers` producer, the `--otlp`
+    /// exporter and the `--sink-plugin`.
+    ///
+    /// Each is put behind its `--buffer`, see `buffered`.
+    ///
+    /// They are flushed and finished in this order, see `sink::in_order`. The files come first as
+    /// they are the record everything else is derived from, and the database, producer and
+    /// exporter last as their writers work in the background for every client at once. The plugin
+    /// comes after everything that ships with the server.
+    fn sinks(&self) -> Vec<Box<dyn Sink + '_>> {
+        let files: Box<dyn Sink> = match (self.config.merge_all.as_ref(), self.config.format) {
+            (Some(path), _) => Box::new(merge::MergeSink::new(&self.config, path, self.config.sort_by_time)),
+            (None, Format::Text | Format::Json) => Box::new(sink::FileSink::new(&self.config, self.config.format)),
+            (None, Format::Bincode) => Box::new(archive::ArchiveSink::new(&self.config))
+        };
+
+        #[allow(unused_mut)]
+        let mut sinks = vec![self.named("files", files)];
+
+        for format in &self.config.also_format {
+            sinks.push(self.named("files", Box::new(sink::FileSink::new(&self.config, *format))));
+        }
+
+        if let Some(min) = self.config.errors_to_stderr {
+            sinks.push(self.named("mirror", Box::new(sink::MirrorSink::new(&self.config, min, io::stderr().is_terminal(), io::stderr()))));
+        }
+
+        if let Some(ref notifier) = self.notifier {
+            sinks.push(self.named("notify", Box::new(notifier.sink())));
+        }
+
+        if self.config.summary {
+            sinks.push(self.named("summary", Box::new(summary::SummarySink::new(&self.config))));
+        }
+
+        #[cfg(feature = "sqlite")]
+        {
+            if let Some(ref writer) = self.sqlite {
+                sinks.push(self.named("sqlite", Box::new(writer.sink())));
+            }
+        }
+
+        #[cfg(feature = "parquet")]
+        {
+            if let Some(ref writer) = self.parquet {
+                sinks.push(self.named("parquet", Box::new(writer.sink())));
+            }
+        }
+
+        #[cfg(feature = "kafka")]
+        {
+            if let Some(ref writer) = self.kafka {
+                sinks.push(self.named("kafka", Box::new(writer.sink(&self.config))));
+            }
+        }
+
+        #[cfg(feature = "otlp")]
+        {
+            if let Some(ref writer) = self.otlp {
+                sinks.push(self.named("otlp", Box::new(writer.sink())));
+            }
+        }
+
+        #[cfg(feature = "plugin")]
+        {
+            if let Some(ref plugin) = self.plugin {
+                sinks.push(self.named("plugin", Box::new(plugin.sink())));
+            }
+        }
+
+        sinks
+    }
+
+    /// `sink` behind its `--buffer`, if it was given one other than `none`, and with any `--route`
+    /// only given the messages routed to it. Routing comes first so nothing is held for a sink that
+    /// won't get it.
+    fn named<'s>(&'s self, name: &'static str, sink: Box<dyn Sink + 's>) -> Box<dyn Sink + 's> {
+        let sink = match self.config.buffers.iter().find(|(buffered, _)| buffered == name) {
+            Some(&(_, buffer)) if buffer != sink::Buffer::None => Box::new(sink::Buffered::new(sink, buffer)),
+            _ => sink
+        };
+
+        if self.config.routes.is_empty() { sink } else { Box::new(route::Routed::new(&self.config, name, sink)) }
+    }
+
+    /// Waits for sinks that write in the background to catch up.
+    fn flush(&self) {
+        #[cfg(feature = "sqlite")]
+        {
+            if let Some(ref writer) = self.sqlite {
+                writer.flush();
+            }
+        }
+
+        #[cfg(feature = "kafka")]
+        {
+            if let Some(ref writer) = self.kafka {
+                writer.flush();
+            }
+        }
+
+        #[cfg(feature = "otlp")]
+        {
+            if let Some(ref writer) = self.otlp {
+                writer.flush();
+            }
+        }
+
+        #[cfg(feature = "plugin")]
+        {
+            if let Some(ref plugin) = self.plugin {
+                if let Err(e) = plugin.flush() {
+                    warn!("{}", e);
+                }
+            }
+        }
+    }
+
+    /// Flushes like `flush` once the server is about to exit, finishes the `--parquet` file and
+    /// shuts the `--otlp` exporter and the `--sink-plugin` down.
+    fn close(&self) {
+        self.flush();
+
+        #[cfg(feature = "parquet")]
+        {
+            if let Some(ref writer) = self.parquet {
+                writer.close();
+            }
+        }
+
+        #[cfg(feature = "otlp")]
+        {
+            if let Some(ref writer) = self.otlp {
+                writer.shutdown();
+            }
+        }
+
+        #[cfg(feature = "plugin")]
+        {
+            if let Some(ref plugin) = self.plugin {
+                plugin.shutdown();
+            }
+        }
+    }
+}
+
+/// Holds one slot of `--max-connections` for as long as a client is being handled.
+///
+/// The slot is released on drop so the count stays correct even if `handle_client` panics.
+struct ConnectionGuard {
+    active: Arc<AtomicUsize>
+}
+
+impl ConnectionGuard {
+    fn acquire(active: &Arc<AtomicUsize>, max: Option<usize>) -> Option<ConnectionGuard> {
+        let previous = active.fetch_add(1, Ordering::SeqCst);
+        let guard = ConnectionGuard { active: active.clone() };
+
+        match max {
+            Some(max) if previous >= max => None,
+            _ => Some(guard)
+        }
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Rejects a Connection whose pid is below `--min-pid`, by default only pid 0: that is what an
+/// uninitialised client sends, and it is where text sent before any Connection ends up. With
+/// `--pid` a pid that isn't one of those given is rejected too, before anything is written for it.
+///
+/// Windows pids are 32 bits wide, so anything larger is accepted but most likely garbage.
+fn check_pid(config: &Config, pid: u64) -> io::Result<()> {
+    if pid < config.min_pid {
+        let message = format!("Rejecting pid {}, below --min-pid {}", pid, config.min_pid);
+        return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+    }
+
+    if !config.pids.is_empty() && !config.pids.contains(&pid) {
+        let message = format!("Rejecting pid {}, not one of the --pid given", pid);
+        return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+    }
+
+    if pid > u64::from(u32::MAX) {
+        warn!("Pid {} is implausibly large, the client may be sending garbage", pid);
+    }
+
+    Ok(())
+}
+
+/// How far message timestamps are behind the server's clock when they arrive, in seconds.
+///
+/// A large or drifting skew points at a client with a wrong clock, or at timestamps we decode
+/// wrongly, which is why the raw values are kept as well.
+#[derive(Default)]
+struct Skew {
+    count: u64,
+    min: i64,
+    max: i64,
+    sum: i64,
+    first_raw: u64,
+    last_raw: u64
+}
+
+impl Skew {
+    fn record(&mut self, received: i64, timestamp: &NaiveDateTime, raw: u64) {
+        let skew = received - timestamp.timestamp();
+
+        if self.count == 0 {
+            self.min = skew;
+            self.max = skew;
+            self.first_raw = raw;
+        }
+
+        self.count += 1;
+        self.min = cmp::min(self.min, skew);
+        self.max = cmp::max(self.max, skew);
+        self.sum += skew;
+        self.last_raw = raw;
+    }
+}
+
+impl fmt::Display for Skew {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.count == 0 {
+            return write!(f, "skew n/a");
+        }
+
+        write!(f, "skew min={}s max={}s mean={:.1}s (raw timestamps first={} last={})",
+               self.min, self.max, self.sum as f64 / self.count as f64, self.first_raw, self.last_raw)
+    }
+}
+
+/// `--monotonic-timestamps`, which moves a timestamp that went back from the one before it of the
+/// same (pid, module) to a nanosecond after that one, so the stream of each module only ever goes
+/// forward however the clock of the client was adjusted.
+struct Monotonic {
+    last: HashMap<(u64, String), NaiveDateTime>,
+    adjusted: u64
+}
+
+impl Monotonic {
+    fn new() -> Monotonic {
+        Monotonic { last: HashMap::new(), adjusted: 0 }
+    }
+
+    /// The timestamp of a message of `module` under `pid`, counting it as adjusted if it was.
+    fn clamp(&mut self, pid: u64, module: &str, timestamp: NaiveDateTime) -> NaiveDateTime {
+        let last = self.last.entry((pid, module.to_string())).or_insert(timestamp);
+
+        if timestamp < *last {
+            self.adjusted += 1;
+            stats::STATS.timestamp_adjusted();
+            *last = *last + chrono::Duration::nanoseconds(1);
+        } else {
+            *last = timestamp;
+        }
+
+        *last
+    }
+}
+
+/// `--sample`, which keeps the first of every `every` messages of each (pid, module) and drops the
+/// rest, however fast or slow they come.
+struct Sampler {
+    every: u64,
+    seen: HashMap<(u64, String), u64>,
+    dropped: u64
+}
+
+impl Sampler {
+    fn new(every: u64) -> Sampler {
+        Sampler { every, seen: HashMap::new(), dropped: 0 }
+    }
+
+    /// Counts a message of `module` under `pid`, returning whether it is kept.
+    fn keep(&mut self, pid: u64, module: &str) -> bool {
+        let seen = self.seen.entry((pid, module.to_string())).or_insert(0);
+        let kept = seen.is_multiple_of(self.every);
+        *seen += 1;
+
+        if !kept {
+            self.dropped += 1;
+            stats::STATS.sampled_out();
+        }
+
+        kept
+    }
+}
+
+/// The malformed packets of one client, which is disconnected once more than `--max-errors` of
+/// them arrive in a row.
+struct Malformed {
+    max: usize,
+    consecutive: usize,
+    total: u64
+}
+
+impl Malformed {
+    fn new(max: usize) -> Malformed {
+        Malformed { max, consecutive: 0, total: 0 }
+    }
+
+    /// Counts `error`, returning the error that ends the connection once past the threshold.
+    fn record(&mut self, pid: u64, error: &io::Error) -> io::Result<()> {
+        self.consecutive += 1;
+        self.total += 1;
+
+        if self.consecutive > self.max {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("more than {} malformed packets in a row, the last was: {}", self.max, error)));
+        }
+
+        warn!("Skipped a packet from pid {}: {}", pid, error);
+        Ok(())
+    }
+
+    fn parsed(&mut self) {
+        self.consecutive = 0;
+    }
+}
+
+/// Everything known about one connected client.
+struct Session<'a> {
+    version: u32,
+    pid: u64,
+    /// When the client connected or, once it sent one, its last Connection message was read, for
+    /// `--timestamp-mode relative`.
+    connected: NaiveDateTime,
+    /// The pids this client announced itself as, for `--archive-on-disconnect`.
+    announced: Vec<u64>,
+    /// Its id in `Context::clients`, for connected clients rather than replays.
+    client: Option<u64>,
+    /// The id `--session-ids` reports, that of `client` unless the session carries on with one
+    /// that ended within `--reconnect-grace`.
+    id: Option<u64>,
+    /// Where a connected client connected from, for the Connection line.
+    peer: Option<SocketAddr>,
+    /// The name of the address of `peer` with `--reverse-dns`, if it has one.
+    peer_name: Option<String>,
+    sinks: Vec<Box<dyn Sink + 'a>>,
+    batch: sink::Batch,
+    messages: u64,
+    /// `RELOADS` as of the last time the sinks were flushed.
+    reloads: usize,
+    /// The modules written to under each pid, for the disconnect summary and the `modules` control
+    /// command.
+    modules: BTreeMap<u64, BTreeSet<String>>,
+    /// The `--sequence-numbers` counter of each (pid, module).
+    sequences: HashMap<(u64, String), u64>,
+    malformed: Malformed,
+    skew: Skew,
+    sampler: Option<Sampler>,
+    monotonic: Option<Monotonic>,
+    name_audit: Option<audit::NameAudit>,
+    /// Until when `--dedup-on-reconnect` skips the lines written before, and how many it skipped.
+    replaying_until: Option<Instant>,
+    deduplicated: u64
+}
+
+impl<'a> Session<'a> {
+    fn new(context: &'a Context, client: Option<u64>) -> Session<'a> {
+        let peer = client.and_then(|client| context.clients.peer(client));
+
+        Session {
+            version: 1,
+            pid: 0,
+            connected: context.clock.now().naive_utc(),
+            announced: Vec::new(),
+            client,
+            id: client,
+            peer,
+            peer_name: peer.and_then(|peer| context.reverse_dns.as_ref()?.lookup(peer.ip())),
+            sinks: context.sinks(),
+            batch: sink::Batch::new(context.config.batch_size, context.config.batch_time),
+            messages: 0,
+            reloads: RELOADS.load(Ordering::SeqCst),
+            modules: BTreeMap::new(),
+            sequences: HashMap::new(),
+            malformed: Malformed::new(context.config.max_errors),
+            skew: Skew::default(),
+            sampler: context.config.sample.map(Sampler::new),
+            monotonic: Some(Monotonic::new()).filter(|_| context.config.monotonic_timestamps),
+            name_audit: Some(audit::NameAudit::new()).filter(|_| context.config.audit_names),
+            replaying_until: None,
+            deduplicated: 0
+        }
+    }
+}
+
+/// Handles the messages of one client, writing them to its sinks.
+///
+/// A client that closes its connection without sending a single byte, like a port check or a
+/// client giving up right away, is no session at all: it is only logged with `--verbose`, and
+/// leaves no files, records or statistics behind.
+fn handle_client<I: Iterator<Item = io::Result<Message>>>(messages: I, context: &Context, client: Option<u64>) -> io::Result<()> {
+    let connected = Instant::now();
+    let mut session = Session::new(context, client);
+    let mut messages = messages.peekable();
+
+    if messages.peek().is_none() {
+        debug!("Empty connection{}", session.peer.map(|peer| format!(" from {}", peer)).unwrap_or_default());
+        return Ok(());
+    }
+
+    stats::STATS.connected();
+    let result = match context.config.join_partial {
+        Some(window) => process_messages(join::JoinPartial::new(messages, window), context, &mut session),
+        None => process_messages(messages, context, &mut session)
+    };
+    stats::STATS.disconnected();
+
+    let sampled = match session.sampler {
+        Some(ref sampler) => format!(" sampled_out={}", sampler.dropped),
+        None => String::new()
+    };
+
+    let adjusted = match session.monotonic {
+        Some(ref monotonic) => format!(" timestamps_adjusted={}", monotonic.adjusted),
+        None => String::new()
+    };
+
+    let deduplicated = if context.config.dedup_on_reconnect { format!(" deduplicated={}", session.deduplicated) } else { String::new() };
+
+    info!("Disconnected: pid={} messages={} malformed={}{}{}{} duration={:.3}s {}", session.pid, session.messages, session.malformed.total, sampled, adjusted, deduplicated, connected.elapsed().as_secs_f64(), session.skew);
+
+    for (pid, modules) in &session.modules {
+        info!("Modules of pid {}: {}", pid, modules.iter().cloned().collect::<Vec<_>>().join(", "));
+    }
+
+    if let Some(ref counters) = context.top {
+        counters.remove(session.pid);
+    }
+
+    let pid = session.pid;
+
+    if let Err(e) = session.batch.write(&mut session.sinks) {
+        warn!("Could not write the last batch of pid {}: {}", pid, e);
+    }
+
+    if context.config.close_records {
+        close_session(context, &mut session, connected, &result);
+    }
+
+    sink::in_order(&mut session.sinks, |sink| sink.finish(), |e| warn!("Could not finish writing pid {}: {}", pid, e));
+
+    // Dropping the session closes its files, which have to be complete before they are indexed and
+    // archived.
+    let announced = mem::take(&mut session.announced);
+    let mut pids: BTreeSet<u64> = session.modules.keys().cloned().collect();
+
+    for pid in &pids {
+        stats::STATS.left(*pid);
+    }
+
+    pids.insert(session.pid);
+
+    let mut sequences: BTreeMap<u64, HashMap<String, u64>> = pids.iter().map(|pid| (*pid, HashMap::new())).collect();
+
+    for ((pid, module), sequence) in mem::take(&mut session.sequences) {
+        sequences.entry(pid).or_default().insert(module, sequence);
+    }
+
+    let id = session.id;
+    drop(session);
+
+    for (pid, sequences) in sequences {
+        context.recent.leave(pid, id, sequences);
+
+        if context.config.dedup_on_reconnect {
+            context.written.leave(pid);
+        }
+    }
+
+    if context.config.index {
+        for pid in pids {
+            let result = tarball::pid_directories(&context.config, pid)
+                .and_then(|directories| directories.iter().try_for_each(|directory| index::update(directory)));
+
+            if let Err(e) = result {
+                warn!("Could not update the index of pid {}: {}", pid, e);
+            }
+        }
+    }
+
+    for pid in announced {
+        if let Err(e) = context.pids.leave(&context.config, pid) {
+            warn!("Could not archive pid {}: {}", pid, e);
+        }
+    }
+
+    result
+}
+
+/// `--close-records`, tells every sink how the session that connected at `connected` ended.
+fn close_session(context: &Context, session: &mut Session<'_>, connected: Instant, result: &io::Result<()>) {
+    let reason = match *result {
+        _ if control::SHUTTING_DOWN.load(Ordering::SeqCst) => "shutdown".to_string(),
+        Ok(()) => "disconnected".to_string(),
+        Err(ref e) => e.to_string()
+    };
+
+    let close = sink::SessionClose {
+        pid: session.pid,
+        session: session.id.filter(|_| context.config.session_ids),
+        duration_ms: connected.elapsed().as_millis() as u64,
+        messages: session.messages,
+        malformed: session.malformed.total,
+        reason
+    };
+
+    let pid = session.pid;
+    sink::in_order(&mut session.sinks, |sink| sink.close_session(&close), |e| warn!("Could not record the close of pid {}: {}", pid, e));
+}
+
+fn process_messages<I: Iterator<Item = io::Result<Message>>>(messages: I, context: &Context, session: &mut Session<'_>) -> io::Result<()> {
+    let config = &context.config;
+
+    for message in messages {
+        let message = match message {
+            Ok(message) => message,
+            Err(ref e) if is_malformed(e) => {
+                session.malformed.record(session.pid, e)?;
+                continue;
+            },
+            Err(e) => match truncated_packet(&e) {
+                // A client that went away partway through a packet, everything before it is written.
+                Some(truncated) => {
+                    warn!("truncated final packet from pid={} (got {} of {} bytes)", session.pid, truncated.got, truncated.expected);
+                    return Ok(());
+                },
+                None => return Err(e)
+            }
+        };
+
+        session.malformed.parsed();
+
+        let reloads = RELOADS.load(Ordering::SeqCst);
+
+        if reloads != session.reloads {
+            session.reloads = reloads;
+
+            let pid = session.pid;
+
+            if let Err(e) = session.batch.write(&mut session.sinks) {
+                warn!("Could not write the batch of pid {} after reloading: {}", pid, e);
+            }
+
+            sink::in_order(&mut session.sinks, |sink| sink.flush(), |e| warn!("Could not flush pid {} after reloading: {}", pid, e));
+        }
+
+        match message {
+            Message::Connection(mut msg) => {
+                let build = game_build(config, &msg.executable_path);
+
+                if config.anonymize_connection {
+                    msg.machine_name = anonymize(&config.anon_salt.0, &msg.machine_name);
+                    msg.executable_path = anonymize(&config.anon_salt.0, &msg.executable_path);
+                }
+
+                check_pid(config, msg.pid)?;
+
+                if let Some(ref counters) = context.top {
+                    counters.remove(session.pid);
+                }
+
+                session.version = msg.version;
+                session.pid = msg.pid;
+                session.connected = context.clock.now().naive_utc();
+
+                if config.dedup_on_reconnect {
+                    session.replaying_until = Some(Instant::now() + reconnect::DEDUP_WINDOW);
+                }
+
+                let resumed = context.recent.rejoin(msg.pid);
+
+                if let Some(ref left) = resumed {
+                    session.id = left.session.or(session.id);
+
+                    for (module, sequence) in &left.sequences {
+                        session.sequences.insert((msg.pid, module.clone()), *sequence);
+                    }
+                }
+
+                if let Some(client) = session.client {
+                    context.clients.announce(client, msg.pid);
+
+                    // Back on a pid it had announced before.
+                    for module in session.modules.get(&msg.pid).into_iter().flatten() {
+                        context.clients.module(client, module);
+                    }
+                }
+
+                if config.archive_on_disconnect && !session.announced.contains(&msg.pid) {
+                    context.pids.enter(msg.pid);
+                    session.announced.push(msg.pid);
+                }
+
+                // What the batch holds was sent before, under the previous pid.
+                session.batch.write(&mut session.sinks)?;
+
+                for sink in &mut session.sinks {
+                    sink.connection(&msg, build.as_deref()).map_err(|e| io::Error::new(e.kind(), format!("Could not start writing pid {}: {}", msg.pid, e)))?;
+                }
+
+                let mut peer = session.peer.map(|peer| format!(" peer={}", peer)).unwrap_or_default();
+
+                if let Some(ref name) = session.peer_name {
+                    peer.push_str(&format!(" peer_name={}", name));
+                }
+
+                if let Some(id) = session.id.filter(|_| config.session_ids) {
+                    peer.push_str(&format!(" session={}", id));
+                }
+
+                match (&config.version_regex, build) {
+                    // Back within --reconnect-grace, carrying on with the session it left.
+                    _ if resumed.is_some() => (),
+                    _ if !config.limits.message_types.connection => (),
+                    (Some(_), Some(build)) => info!("Connected: {}{} build={}", msg, peer, build),
+                    (Some(_), None) => info!("Connected: {}{} build unknown", msg, peer),
+                    (None, _) => info!("Connected: {}{}", msg, peer)
+                }
+            },
+            Message::Text(mut msg) => {
+                // Text sent before the Connection message is under pid 0, which --pid never lets by.
+                if !config.pids.is_empty() && !config.pids.contains(&session.pid) {
+                    continue;
+                }
+
+                // Before anything replaces an empty module or a numeric channel, and whether or not
+                // the message is filtered out.
+                if let Some(ref mut audit) = session.name_audit {
+                    if let Err(e) = audit.check(config, session.pid, &msg) {
+                        warn!("Could not write to {}: {}", audit::FILE, e);
+                    }
+                }
+
+                if msg.message.starts_with(REASSEMBLY_GAP) {
+                    warn!("Packets of a Large message from pid={} module={} went missing or got mixed up, it is marked {}", session.pid, msg.module, REASSEMBLY_GAP.trim_end());
+                }
+
+                let filters = context.filters.read().unwrap().clone();
+
+                // read_packet has already assembled any continuations, and --join-partial any partial
+                // lines, so patterns spanning chunks match.
+                if !filter_severity(&filters, &mut msg) || !filter_body(&filters, &msg.message) {
+                    continue;
+                }
+
+                msg.message = redact(&filters, msg.message);
+
+                // An empty module would otherwise be written to the hidden file `{pid}/.txt`.
+                if msg.module.is_empty() {
+                    msg.module = config.default_module.as_str().into();
+                }
+
+                // Control characters would otherwise end up in every line, and with
+                // --split-by-channel in the file names.
+                if !msg.channel_is_textual() {
+                    msg.channel = numeric_channel(&msg).into();
+                }
+
+                let received = context.clock.now().naive_utc();
+
+                // Only read_packet knows the versions it parsed, a bincode archive can hold any.
+                let sent = match session.version {
+                    1 => config.timestamp_mode.decode(msg.timestamp, session.connected),
+                    e => {
+                        session.malformed.record(session.pid, &io::Error::new(io::ErrorKind::InvalidData, format!("text of version {}, which is unknown", e)))?;
+                        continue;
+                    }
+                };
+
+                // Out of range, which leaves nothing better than when it arrived. The skew still
+                // shows the raw timestamp.
+                let sent = sent.unwrap_or_else(|| {
+                    debug!("Timestamp {} of pid={} module={} is out of range, using the receive time", msg.timestamp, session.pid, msg.module);
+                    received
+                });
+
+                if !in_window(config, &sent) {
+                    continue;
+                }
+
+                if let Some(ref mut sampler) = session.sampler {
+                    if !sampler.keep(session.pid, &msg.module) {
+                        continue;
+                    }
+                }
+
+                if config.dedup_on_reconnect {
+                    let replaying = session.replaying_until.is_some_and(|until| Instant::now() < until);
+
+                    if !context.written.write(session.pid, &msg, replaying) {
+                        session.deduplicated += 1;
+                        continue;
+                    }
+                }
+
+                session.messages += 1;
+                session.skew.record(received.timestamp(), &sent, msg.timestamp);
+
+                let sent = match session.monotonic {
+                    Some(ref mut monotonic) => monotonic.clamp(session.pid, &msg.module, sent),
+                    None => sent
+                };
+
+                let timestamp = config.time_source.pick(sent, received);
+
+                let pid = session.pid;
+
+                if let Some(ref counters) = context.top {
+                    counters.record(pid, &msg.module);
+                }
+
+                let modules = session.modules.entry(pid).or_insert_with(|| {
+                    stats::STATS.joined(pid);
+                    BTreeSet::new()
+                });
+
+                if modules.insert(msg.module.to_string()) {
+                    if let Some(client) = session.client {
+                        context.clients.module(client, &msg.module);
+                    }
+                }
+
+                stats::STATS.message(pid, msg.severity, msg.message.len());
+
+                if disk::may_write(config) {
+                    let sequence = if config.sequence_numbers {
+                        let sequence = session.sequences.entry((pid, msg.module.to_string())).or_insert(0);
+                        *sequence += 1;
+
+                        Some(*sequence)
+                    } else {
+                        None
+                    };
+
+                    let entry = sink::Batched { pid, sequence, timestamp, received, message: msg };
+                    session.batch.text(&mut session.sinks, &entry, Instant::now())?;
+                    msg = entry.message;
+                }
+
+                let remembered = session.client.filter(|_| config.recent_messages > 0);
+
+                if context.tail.is_none() && remembered.is_none() {
+                    continue;
+                }
+
+                let entry = http::TailEntry {
+                    pid,
+                    timestamp: sink::format_timestamp(config, &timestamp),
+                    severity: config.severity_style.label(msg.severity),
+                    module: msg.module.to_string(),
+                    channel: msg.channel.to_string(),
+                    message: msg.message
+                };
+
+                if let Some(client) = remembered {
+                    context.clients.remember(client, entry.clone());
+                }
+
+                if let Some(ref tail) = context.tail {
+                    tail.push(entry);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Binds the client listener, with the accept queue sized by `--listen-backlog` when given.
+///
+/// The requested backlog is only a hint: Linux silently caps it at `net.core.somaxconn`, macOS and
+/// the BSDs at `kern.ipc.somaxconn`, and Windows at its own `SOMAXCONN`. Raise those as well when
+/// asking for a large queue.
+fn bind_listener(config: &Config, address: SocketAddr) -> io::Result<TcpListener> {
+    let backlog = match config.listen_backlog {
+        Some(backlog) => backlog,
+        None => return TcpListener::bind(address)
+    };
+
+    let socket = Socket::new(Domain::for_address(address), Type::STREAM, Some(Protocol::TCP))?;
+
+    // Matches what the standard library does, so restarts don't fail on sockets in TIME_WAIT.
+    if cfg!(unix) {
+        socket.set_reuse_address(true)?;
+    }
+
+    socket.bind(&address.into())?;
+    socket.listen(backlog)?;
+
+    Ok(socket.into())
+}
+
+/// Feeds the `--replay` captures through the normal client handling as one stream, instead of
+/// listening for clients.
+///
+/// The files are read back to back in the order given, with each wildcard pattern contributing its
+/// matches sorted by name. A Large message whose continuations were split across two files is
+/// therefore reassembled as long as the files are listed in capture order.
+///
+/// `--format bincode` archives are recognised by their header and can be mixed with captures, they
+/// hold whole messages so they also end any capture run before them.
+fn replay(context: &Context) -> i32 {
+    type Stream<'a> = Box<dyn Iterator<Item = io::Result<Message>> + 'a>;
+
+    let limits = &context.config.limits;
+    let parse = |capture: Box<dyn Read>| -> Stream<'_> {
+        if context.config.strict_parse {
+            Box::new(strict::Strict::new(context, MessagesWithBytes::with_limits(capture, limits.clone())))
+        } else {
+            Box::new(Messages::with_limits(capture, limits.clone()))
+        }
+    };
+
+    let mut messages: Stream<'_> = Box::new(iter::empty());
+    let mut capture: Option<Box<dyn Read>> = None;
+
+    for path in &context.config.replay {
+        let mut file = match fs::File::open(path) {
+            Ok(file) => io::BufReader::new(file),
+            Err(e) => {
+                eprintln!("Could not open {}: {}", path.display(), e);
+                return 1;
+            }
+        };
+
+        match archive::is_archive(&mut file) {
+            Ok(true) => {
+                if let Some(previous) = capture.take() {
+                    messages = Box::new(messages.chain(parse(previous)));
+                }
+
+                messages = Box::new(messages.chain(archive::Frames::new(file)));
+            },
+            Ok(false) => {
+                capture = Some(match capture.take() {
+                    Some(previous) => Box::new(previous.chain(file)),
+                    None => Box::new(file)
+                });
+            },
+            Err(e) => {
+                eprintln!("Could not read {}: {}", path.display(), e);
+                return 1;
+            }
+        }
+    }
+
+    if let Some(previous) = capture {
+        messages = Box::new(messages.chain(parse(previous)));
+    }
+
+    if context.config.realtime {
+        let config = &context.config;
+        messages = Box::new(realtime::Realtime::new(messages, config.timestamp_mode, config.speed, config.realtime_max_gap));
+    }
+
+    let result = handle_client(messages, context, None);
+    context.close();
+
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Replay failed: {}", e);
+            1
+        }
+    }
+}
+
+/// `--stdin`, handles stdin as a single client, so the logger can sit at the end of a pipe.
+fn read_stdin(context: &Context) -> i32 {
+    let stdin = io::stdin();
+    let result = read_client(stdin.lock(), context, None);
+    context.close();
+
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Reading stdin failed: {}", e);
+            1
+        }
+    }
+}
+
+fn main() {
+    match env::args().nth(1).as_deref() {
+        Some("selftest") => process::exit(if selftest::run() { 0 } else { 1 }),
+        Some("probe") => process::exit(probe::run(env::args().skip(2))),
+        Some("convert") => process::exit(convert::run(env::args().skip(2))),
+        Some("verify") => process::exit(verify::run(env::args().skip(2))),
+        Some("dump-struct-layout") => {
+            payload_layouts().iter().for_each(|layout| print!("{}", layout));
+            process::exit(0)
+        },
+        _ => ()
+    }
+
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let config = match Config::load(&args) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2)
+        }
+    };
+
+    if config.print_config {
+        println!("{:#?}", config);
+        process::exit(0)
+    }
+
+    VERBOSE.store(config.verbose, Ordering::Relaxed);
+
+    if config.stdin && io::stdin().is_terminal() {
+        eprintln!("Option --stdin reads a capture piped in, as in `eve-logger --stdin < capture.bin`, but stdin is a terminal");
+        process::exit(2)
+    }
+
+    let tail = match config.http {
+        Some(address) => {
+            let tail = Arc::new(http::TailBuffer::new(config.tail_buffer));
+            let listener = match TcpListener::bind(address) {
+                Ok(listener) => listener,
+                Err(e) => {
+                    eprintln!("Could not bind {} for --http: {}", address, e);
+                    process::exit(1)
+                }
+            };
+            let served = tail.clone();
+
+            thread::spawn(move|| http::serve(listener, served));
+
+            Some(tail)
+        },
+        None => None
+    };
+
+    #[cfg(feature = "sqlite")]
+    let sqlite = match config.sqlite {
+        Some(ref path) => match sqlite::Writer::open(path) {
+            Ok(writer) => Some(writer),
+            Err(e) => {
+                eprintln!("Could not open {}: {}", path.display(), e);
+                process::exit(1)
+            }
+        },
+        None => None
+    };
+
+    #[cfg(not(feature = "sqlite"))]
+    {
+        if config.sqlite.is_some() {
+            eprintln!("Option --sqlite requires a build with the sqlite feature");
+            process::exit(2)
+        }
+    }
+
+    #[cfg(feature = "parquet")]
+    let parquet = match config.parquet {
+        Some(ref path) => match columnar::Writer::open(path) {
+            Ok(writer) => Some(writer),
+            Err(e) => {
+                eprintln!("Could not create {}: {}", path.display(), e);
+                process::exit(1)
+            }
+        },
+        None => None
+    };
+
+    #[cfg(not(feature = "parquet"))]
+    {
+        if config.parquet.is_some() {
+            eprintln!("Option --parquet requires a build with the parquet feature");
+            process::exit(2)
+        }
+    }
+
+    #[cfg(not(feature = "notify"))]
+    {
+        if config.notify_on.is_some() && config.notify == notify::Alert::Desktop {
+            eprintln!("Option --notify desktop requires a build with the notify feature");
+            process::exit(2)
+        }
+    }
+
+    let notifier = config.notify_on.map(|min| notify::Notifier::new(config.notify, min, config.notify_debounce));
+
+    #[cfg(feature = "kafka")]
+    let kafka = match (&config.kafka_brokers, &config.kafka_topic) {
+        (Some(brokers), Some(topic)) => match kafka::Writer::open(brokers, topic) {
+            Ok(writer) => Some(writer),
+            Err(e) => {
+                eprintln!("Could not create a Kafka producer for {}: {}", brokers, e);
+                process::exit(1)
+            }
+        },
+        _ => None
+    };
+
+    #[cfg(not(feature = "kafka"))]
+    {
+        if config.kafka_brokers.is_some() {
+            eprintln!("Option --kafka-brokers requires a build with the kafka feature");
+            process::exit(2)
+        }
+    }
+
+    #[cfg(feature = "otlp")]
+    let otlp = match config.otlp {
+        Some(ref endpoint) => match otlp::Writer::open(endpoint) {
+            Ok(writer) => Some(writer),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1)
+            }
+        },
+        None => None
+    };
+
+    #[cfg(not(feature = "otlp"))]
+    {
+        if config.otlp.is_some() {
+            eprintln!("Option --otlp requires a build with the otlp feature");
+            process::exit(2)
+        }
+    }
+
+    #[cfg(feature = "plugin")]
+    let plugin = match config.sink_plugin {
+        Some(ref path) => match plugin::Plugin::load(path, &config.sink_plugin_config) {
+            Ok(plugin) => Some(plugin),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1)
+            }
+        },
+        None => None
+    };
+
+    #[cfg(not(feature = "plugin"))]
+    {
+        if config.sink_plugin.is_some() {
+            eprintln!("Option --sink-plugin requires a build with the plugin feature");
+            process::exit(2)
+        }
+    }
+
+    #[cfg(feature = "tls")]
+    let tls = match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => match tls::server_config(cert, key) {
+            Ok(tls) => Some(tls),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1)
+            }
+        },
+        _ => None
+    };
+
+    #[cfg(not(feature = "tls"))]
+    {
+        if config.tls_cert.is_some() {
+            eprintln!("Option --tls-cert requires a build with the tls feature");
+            process::exit(2)
+        }
+    }
+
+    stats::dump_on_sigusr1();
+
+    let filters = Arc::new(RwLock::new(Arc::new(config.filters.clone())));
+
+    if let Some(ref path) = config.config_file {
+        let reloaded = filters.clone();
+        let path = path.clone();
+
+        let args = args.clone();
+
+        let result = signal::on_signal(signal::Signal::Hup, move|| match reload(&reloaded, &args) {
+            Ok(()) => info!("Reloaded --min-severity, --channel-severity, --unknown-severity-as, --grep, --grep-v and --redact from {}", path.display()),
+            Err(e) => warn!("Could not reload, keeping the previous filters: {}", e)
+        });
+
+        if let Err(e) = result {
+            warn!("Could not set up reloading on SIGHUP: {}", e);
+        }
+    }
+
+    if let Some(min_free) = config.min_free_bytes {
+        thread::spawn(move|| disk::watch(min_free));
+    }
+
+    if let Some(idle) = config.idle_flush {
+        let close = config.idle_close;
+        thread::spawn(move|| sweep_idle(idle, close));
+    }
+
+    if let Some(interval) = config.heartbeat_lines {
+        let terminator = config.line_terminator;
+        thread::spawn(move|| sweep_heartbeats(interval, terminator));
+    }
+
+    let top = if config.top {
+        let counters = Arc::new(top::Counters::new());
+        let drawn = counters.clone();
+
+        QUIET.store(true, Ordering::Relaxed);
+        thread::spawn(move|| top::run(&drawn));
+
+        Some(counters)
+    } else {
+        None
+    };
+
+    let recent = reconnect::Recent::new(config.reconnect_grace);
+    let written = reconnect::Written::new(config.reconnect_grace.unwrap_or(reconnect::DEDUP_KEEP));
+    let reverse_dns = Some(dns::ReverseDns::new(Arc::new(dns::SystemResolver))).filter(|_| config.reverse_dns);
+    let mut clients = control::Clients::new(config.recent_messages);
+
+    if config.session_ids {
+        let root = output_root(&config);
+
+        let counter = if root.as_os_str().is_empty() { Ok(()) } else { create_dir(&config, &root).map_err(|e| e.to_string()) }
+            .and_then(|_| counter::SessionCounter::open(&root.join(counter::FILE)));
+
+        match counter {
+            Ok(counter) => clients = clients.counting(counter),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1)
+            }
+        }
+    }
+
+    let context = Arc::new(Context {
+        config,
+        tail,
+        top,
+        pids: tarball::Pids::new(),
+        recent,
+        written,
+        reverse_dns,
+        clock: Arc::new(clock::SystemClock),
+        filters,
+        args,
+        clients,
+        notifier,
+        #[cfg(feature = "sqlite")]
+        sqlite,
+        #[cfg(feature = "parquet")]
+        parquet,
+        #[cfg(feature = "kafka")]
+        kafka,
+        #[cfg(feature = "otlp")]
+        otlp,
+        #[cfg(feature = "plugin")]
+        plugin,
+        #[cfg(feature = "tls")]
+        tls
+    });
+    let config = &context.config;
+
+    if !config.replay.is_empty() {
+        process::exit(replay(&context));
+    }
+
+    if config.stdin {
+        process::exit(read_stdin(&context));
+    }
+
+    let stopping = context.clone();
+
+    let result = signal::on_signal(signal::Signal::Term, move|| {
+        info!("Shutting down on SIGTERM: {}", control::shutdown(&stopping));
+        process::exit(0)
+    });
+
+    if let Err(e) = result {
+        warn!("Could not set up shutting down on SIGTERM: {}", e);
+    }
+
+    // Shuts down like SIGTERM does, whoever is still connected. Replays and --stdin end by
+    // themselves and never get here.
+    if let Some(runtime) = config.max_runtime {
+        let stopping = context.clone();
+
+        thread::spawn(move|| {
+            thread::sleep(runtime);
+            info!("Shutting down after the --max-runtime of {}s: {}", runtime.as_secs(), control::shutdown(&stopping));
+            process::exit(0)
+        });
+    }
+
+    // Before accepting, so no client is writing yet. Replays and --stdin don't sweep.
+    if let Some(days) = config.retention_days {
+        retention::sweep_output(&context, days);
+
+        if let Some(interval) = config.retention_interval {
+            let sweeping = context.clone();
+            thread::spawn(move|| retention::watch(sweeping, days, interval));
+        }
+    }
+
+    if let Some(ref path) = config.control {
+        if let Err(e) = control::serve(path, context.clone()) {
+            eprintln!("Could not listen on {}: {}", path.display(), e);
+            process::exit(1)
+        }
+    }
+
+    if let Some(address) = config.udp {
+        let socket = match UdpSocket::bind(address) {
+            Ok(socket) => socket,
+            Err(e) => {
+                eprintln!("Could not bind {}: {}", address, e);
+                process::exit(1)
+            }
+        };
+
+        let limits = config.limits.clone();
+        let context = context.clone();
+
+        thread::spawn(move|| udp::serve(socket, limits, move|from, messages| {
+            if let Err(e) = handle_client(messages.into_iter(), &context, None) {
+                info!("UDP client {} failed {:?}", from, e)
+            }
+        }));
+    }
+
+    let listeners: Vec<TcpListener> = config.bind.iter().map(|address| match bind_listener(config, *address) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Could not bind {}: {}", address, e);
+            process::exit(1)
+        }
+    }).collect();
+    let active = Arc::new(AtomicUsize::new(0));
+
+    let mut accepting = Vec::new();
+
+    for listener in listeners {
+        let context = context.clone();
+        let active = active.clone();
+
+        accepting.push(thread::spawn(move|| {
+            let address = listener.local_addr();
+
+            if let Err(e) = accept(listener, &context, &active) {
+                match address {
+                    Ok(address) => warn!("Stopped accepting clients on {}: {}", address, e),
+                    Err(_) => warn!("Stopped accepting clients: {}", e)
+                }
+
+                process::exit(1)
+            }
+        }));
+    }
+
+    for thread in accepting {
+        let _ = thread.join();
+    }
+}
+
+/// Reads the messages of an accepted client, through TLS with `--tls-cert`.
+///
+/// A client that closes its connection before sending anything is turned away before it becomes
+/// a session, so it takes no `--session-ids` id.
+fn serve_client(stream: TcpStream, context: &Context) -> io::Result<()> {
+    if stream.peek(&mut [0])? == 0 {
+        debug!("Empty connection{}", stream.peer_addr().map(|peer| format!(" from {}", peer)).unwrap_or_default());
+        return Ok(());
+    }
+
+    let client = context.clients.enter(stream.try_clone()?);
+    let result = serve_stream(stream, context, client);
+    context.clients.leave(client);
+
+    result
+}
+
+fn serve_stream(stream: TcpStream, context: &Context, client: u64) -> io::Result<()> {
+    #[cfg(feature = "tls")]
+    {
+        if let Some(ref config) = context.tls {
+            return read_client(tls::accept(config, stream)?, context, Some(client));
+        }
+    }
+
+    read_client(stream, context, Some(client))
+}
+
+/// Parses what a client sends, saving it with `--save-raw`.
+fn read_client<R: Read>(reader: R, context: &Context, client: Option<u64>) -> io::Result<()> {
+    match context.config.save_raw {
+        Some(ref directory) => {
+            let capture = raw::Capture::new(directory, client.unwrap_or(0), context.config.checkpoint_interval);
+            let result = parse_client(raw::Tee::new(reader, &capture), context, client, Some(&capture));
+            capture.finish();
+
+            result
+        },
+        None => parse_client(reader, context, client, None)
+    }
+}
+
+/// Parses what a client sends, recording every packet with `--hexdump` and stopping at the first
+/// parse error with `--strict-parse`.
+fn parse_client<R: Read>(reader: R, context: &Context, client: Option<u64>, capture: Option<&raw::Capture>) -> io::Result<()> {
+    let limits = context.config.limits.clone();
+
+    if context.config.hexdump {
+        handle_client(raw::Named::new(hexdump::Hexdump::new(&context.config, MessagesWithBytes::with_limits(reader, limits)), capture), context, client)
+    } else if context.config.strict_parse {
+        handle_client(raw::Named::new(strict::Strict::new(context, MessagesWithBytes::with_limits(reader, limits)), capture), context, client)
+    } else {
+        handle_client(raw::Named::new(Messages::with_limits(reader, limits), capture), context, client)
+    }
+}
+
+/// Hands every client of `listener` to a thread of its own. `active` is shared by all listeners,
+/// so `--max-connections` counts clients across every `--bind`.
+///
+/// Only returns once accepting has failed for good, see `accept_with_backoff`.
+fn accept(listener: TcpListener, context: &Arc<Context>, active: &Arc<AtomicUsize>) -> io::Result<()> {
+    let config = &context.config;
+
+    accept_with_backoff(config.accept_retries, || listener.accept().map(|(stream, _)| stream), |stream| {
+        if control::SHUTTING_DOWN.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let guard = match ConnectionGuard::acquire(active, config.max_connections) {
+            Some(guard) => guard,
+            None => {
+                let peer = stream.peer_addr().map(|addr| addr.to_string()).unwrap_or_else(|_| "unknown peer".to_string());
+
+                info!("Rejected connection from {}: at the limit of {} connections", peer, config.max_connections.unwrap_or(0));
+                return;
+            }
+        };
+
+        let context = context.clone();
+
+        thread::spawn(move|| {
+            let _guard = guard;
+
+            if let Err(e) = serve_client(stream, &context) {
+                info!("Client failed: {}", e)
+            }
+        });
+    })
+}
+
+/// Accepts with `next` until it fails in a way that waiting won't fix, handing every accepted
+/// stream to `handle`.
+///
+/// Failures that leave the listener usable, like running out of file descriptors or a connection
+/// reset before it was accepted, are logged and retried after a pause that doubles from 10ms up to
+/// 1s with every failure in a row. An accepted stream starts the count over. A listener that can't
+/// be accepted on any more, or more than `retries` failures in a row, ends the loop with the last
+/// error.
+fn accept_with_backoff<S, A, H>(retries: u32, mut next: A, mut handle: H) -> io::Result<()>
+    where A: FnMut() -> io::Result<S>, H: FnMut(S) {
+    let mut failures = 0;
+
+    loop {
+        match next() {
+            Ok(stream) => {
+                failures = 0;
+                handle(stream);
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => {
+                failures += 1;
+
+                if !accept_recoverable(&e) || failures > retries {
+                    return Err(e);
+                }
+
+                let pause = Duration::from_millis(10 << (failures - 1).min(7)).min(Duration::from_secs(1));
+                warn!("Could not accept a connection, retrying in {}ms ({} of {}): {}", pause.as_millis(), failures, retries, e);
+                thread::sleep(pause);
+            }
+        }
+    }
+}
+
+/// Whether the listener can still be accepted on after `error`.
+fn accept_recoverable(error: &io::Error) -> bool {
+    match error.kind() {
+        io::ErrorKind::InvalidInput | io::ErrorKind::NotConnected | io::ErrorKind::Unsupported => false,
+        _ => !matches!(error.raw_os_error(), Some(libc::EBADF) | Some(libc::ENOTSOCK))
+    }
 }
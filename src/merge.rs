@@ -0,0 +1,134 @@
+use std::{fs, io};
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+use chrono;
+use chrono::naive::datetime::NaiveDateTime;
+
+use eve_logger::{ConnectionMessage, TextMessage};
+
+use sink::{self, Sink};
+use {Config, Format};
+
+/// A line held back by `--sort-by-time`, ordered by its timestamp and then by when it arrived.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct Held {
+    timestamp: NaiveDateTime,
+    arrived: u64,
+    line: String
+}
+
+/// `--merge-all <file>`, writes the messages of every pid of a replay to one file rather than to
+/// a file per pid and module, in the layout of the files with a `{pid}/{module}: ` prefix in the
+/// default text layout as the lines of different pids would otherwise look alike.
+///
+/// Without `--sort-by-time` lines are written in the order they were read. With it, each one is
+/// held back until a message `window` newer has been read, and the held ones are written oldest
+/// first by the `--time-source` timestamp, keeping the order they were read in among equal ones.
+/// What is held is only bounded by the window, a capture with many messages a second holds that
+/// many times the window. Captures are only roughly in order, so a message that arrives more than
+/// the window after a newer one can come after lines already written: it is written straight
+/// away where it arrived, and how many of them there were is a warning at the end.
+pub struct MergeSink<'a> {
+    config: &'a Config,
+    path: &'a Path,
+    /// Opened with the first line, so a replay that fails to start leaves no file behind.
+    file: Option<io::BufWriter<fs::File>>,
+    builds: HashMap<u64, String>,
+    window: Option<chrono::Duration>,
+    held: BinaryHeap<Reverse<Held>>,
+    arrived: u64,
+    /// The newest timestamp read and the last one written.
+    newest: Option<NaiveDateTime>,
+    written: Option<NaiveDateTime>,
+    late: u64
+}
+
+impl<'a> MergeSink<'a> {
+    pub fn new(config: &'a Config, path: &'a Path, window: Option<Duration>) -> MergeSink<'a> {
+        MergeSink {
+            config,
+            path,
+            file: None,
+            builds: HashMap::new(),
+            window: window.map(|window| chrono::Duration::milliseconds(window.as_millis() as i64)),
+            held: BinaryHeap::new(),
+            arrived: 0,
+            newest: None,
+            written: None,
+            late: 0
+        }
+    }
+
+    fn write(&mut self, timestamp: NaiveDateTime, line: &str) -> io::Result<()> {
+        if self.file.is_none() {
+            self.file = Some(io::BufWriter::new(fs::File::create(self.path)?));
+        }
+
+        self.written = self.written.max(Some(timestamp));
+        self.file.as_mut().unwrap().write_all(line.as_bytes())
+    }
+
+    /// Writes the held lines older than `before`, or all of them for `None`.
+    fn release(&mut self, before: Option<NaiveDateTime>) -> io::Result<()> {
+        while self.held.peek().is_some_and(|Reverse(held)| before.is_none_or(|before| held.timestamp < before)) {
+            let Reverse(held) = self.held.pop().unwrap();
+            self.write(held.timestamp, &held.line)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> Sink for MergeSink<'a> {
+    fn connection(&mut self, message: &ConnectionMessage, build: Option<&str>) -> io::Result<()> {
+        sink::remember_build(&mut self.builds, message.pid, build);
+        Ok(())
+    }
+
+    fn text(&mut self, pid: u64, sequence: Option<u64>, timestamp: &NaiveDateTime, received: &NaiveDateTime, message: &TextMessage) -> io::Result<()> {
+        let mut line = sink::format_line(self.config, pid, self.builds.get(&pid).map(|build| &build[..]), sequence, timestamp, received, message)?;
+
+        if self.config.format != Format::Json && self.config.line_format.is_none() {
+            line = format!("{}/{}: {}", pid, message.module, line);
+        }
+
+        let window = match self.window {
+            Some(window) => window,
+            None => return self.write(*timestamp, &line)
+        };
+
+        if self.written.is_some_and(|written| *timestamp < written) {
+            self.late += 1;
+            return self.write(*timestamp, &line);
+        }
+
+        self.arrived += 1;
+        self.held.push(Reverse(Held { timestamp: *timestamp, arrived: self.arrived, line }));
+
+        self.newest = self.newest.max(Some(*timestamp));
+        let before = self.newest.map(|newest| newest - window);
+        self.release(before)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.file {
+            Some(ref mut file) => file.flush(),
+            None => Ok(())
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.release(None)?;
+
+        if self.late > 0 {
+            warn!("{} messages arrived more than the --sort-by-time window late, they were written where they arrived", self.late);
+        }
+
+        self.flush()
+    }
+}
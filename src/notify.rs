@@ -0,0 +1,130 @@
+use std::{io, mem, thread};
+
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::naive::datetime::NaiveDateTime;
+
+use eve_logger::{ConnectionMessage, Severity, TextMessage};
+
+use sink::Sink;
+use at_least;
+
+/// How `--notify-on` alerts, chosen with `--notify`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Alert {
+    /// A BEL on stderr, which most terminals turn into a beep or a flash.
+    Bell,
+    /// A notification on the desktop with the module and the message, in builds with the `notify`
+    /// feature.
+    Desktop
+}
+
+impl Alert {
+    pub fn from_name(name: &str, value: &str) -> Result<Alert, String> {
+        match value {
+            "bell" => Ok(Alert::Bell),
+            "desktop" => Ok(Alert::Desktop),
+            e => Err(format!("Option {} expects bell or desktop, got {}", name, e))
+        }
+    }
+}
+
+/// Lets one alert through per window, counting the ones held back in between.
+pub struct Debounce {
+    window: Duration,
+    last: Option<Instant>,
+    held: u64
+}
+
+impl Debounce {
+    pub fn new(window: Duration) -> Debounce {
+        Debounce { window, last: None, held: 0 }
+    }
+
+    /// Whether an alert at `now` may go out, with how many were held back since the last one that
+    /// did. Those held back after the last alert are only reported with the next.
+    pub fn admit(&mut self, now: Instant) -> Option<u64> {
+        match self.last {
+            Some(last) if now.saturating_duration_since(last) < self.window => {
+                self.held += 1;
+                None
+            },
+            _ => {
+                self.last = Some(now);
+                Some(mem::replace(&mut self.held, 0))
+            }
+        }
+    }
+}
+
+/// `--notify-on`, shared by every client so a storm of errors across clients still only alerts
+/// once per `--notify-debounce`.
+pub struct Notifier {
+    alert: Alert,
+    min: Severity,
+    debounce: Mutex<Debounce>
+}
+
+impl Notifier {
+    pub fn new(alert: Alert, min: Severity, window: Duration) -> Notifier {
+        Notifier { alert, min, debounce: Mutex::new(Debounce::new(window)) }
+    }
+
+    pub fn sink(&self) -> NotifySink<'_> {
+        NotifySink { notifier: self }
+    }
+
+    fn notify(&self, pid: u64, message: &TextMessage) -> io::Result<()> {
+        let held = match self.debounce.lock().unwrap().admit(Instant::now()) {
+            Some(held) => held,
+            None => return Ok(())
+        };
+
+        match self.alert {
+            Alert::Bell => io::stderr().write_all(b"\x07"),
+            Alert::Desktop => {
+                let summary = format!("{:?} from {}/{}", message.severity, pid, message.module);
+                let body = match held {
+                    0 => message.message.trim_end().to_string(),
+                    held => format!("{}\n({} more held back)", message.message.trim_end(), held)
+                };
+
+                // Showing waits for the notification daemon, which the client shouldn't.
+                thread::spawn(move|| show(&summary, &body));
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(feature = "notify")]
+fn show(summary: &str, body: &str) {
+    if let Err(e) = ::notify_rust::Notification::new().appname("eve-logger").summary(summary).body(body).show() {
+        warn!("Could not show a desktop notification: {}", e);
+    }
+}
+
+// `--notify desktop` is refused at startup without the feature, this is never reached.
+#[cfg(not(feature = "notify"))]
+fn show(_: &str, _: &str) {}
+
+/// Alerts on every message of at least the `--notify-on` severity.
+pub struct NotifySink<'a> {
+    notifier: &'a Notifier
+}
+
+impl<'a> Sink for NotifySink<'a> {
+    fn connection(&mut self, _: &ConnectionMessage, _: Option<&str>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn text(&mut self, pid: u64, _: Option<u64>, _: &NaiveDateTime, _: &NaiveDateTime, message: &TextMessage) -> io::Result<()> {
+        if !at_least(message.severity, self.notifier.min) {
+            return Ok(());
+        }
+
+        self.notifier.notify(pid, message)
+    }
+}
@@ -0,0 +1,271 @@
+use std::io;
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chrono::naive::datetime::NaiveDateTime;
+
+use eve_logger::{ConnectionMessage, Severity, TextMessage};
+
+use opentelemetry::logs::{self, AnyValue, LogRecord, Logger, LoggerProvider};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::error::OTelSdkResult;
+use opentelemetry_sdk::logs::{LogBatch, LogExporter, SdkLogger, SdkLoggerProvider};
+
+use sink::{SessionClose, Sink};
+use stats;
+
+/// The OpenTelemetry severity number of a severity. Notice sits between Info and Warn, as INFO2.
+/// An unknown severity has none, which OTLP sends as unspecified.
+pub fn severity_number(severity: Severity) -> Option<logs::Severity> {
+    match severity {
+        Severity::Info => Some(logs::Severity::Info),
+        Severity::Notice => Some(logs::Severity::Info2),
+        Severity::Warn => Some(logs::Severity::Warn),
+        Severity::Error => Some(logs::Severity::Error),
+        Severity::Unknown(_) => None
+    }
+}
+
+fn severity_text(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Info => "Info",
+        Severity::Notice => "Notice",
+        Severity::Warn => "Warn",
+        Severity::Error => "Error",
+        Severity::Unknown(_) => "Unknown"
+    }
+}
+
+/// What a text message becomes as an OTLP log record.
+#[derive(Debug, PartialEq)]
+pub struct Record {
+    /// The `--time-source` timestamp, with the time the server read the message as the observed
+    /// timestamp.
+    pub timestamp: SystemTime,
+    pub observed: SystemTime,
+    pub severity_number: Option<logs::Severity>,
+    pub severity_text: &'static str,
+    pub body: String,
+    /// `module`, `channel` and `pid`, and `machine` once a Connection message announced it.
+    pub attributes: Vec<(&'static str, AnyValue)>
+}
+
+fn system_time(datetime: &NaiveDateTime) -> SystemTime {
+    match datetime.timestamp() {
+        seconds if seconds >= 0 => UNIX_EPOCH + Duration::new(seconds as u64, datetime.timestamp_subsec_nanos()),
+        _ => UNIX_EPOCH
+    }
+}
+
+pub fn record(pid: u64, machine: Option<&str>, timestamp: &NaiveDateTime, received: &NaiveDateTime, message: &TextMessage) -> Record {
+    let mut attributes = vec![
+        ("module", AnyValue::from(message.module.to_string())),
+        ("channel", AnyValue::from(message.channel.to_string())),
+        ("pid", AnyValue::Int(pid as i64))
+    ];
+
+    if let Some(machine) = machine {
+        attributes.push(("machine", AnyValue::from(machine.to_string())));
+    }
+
+    Record {
+        timestamp: system_time(timestamp),
+        observed: system_time(received),
+        severity_number: severity_number(message.severity),
+        severity_text: severity_text(message.severity),
+        body: message.message.trim_end_matches('\n').to_string(),
+        attributes
+    }
+}
+
+/// What a `--close-records` close becomes, an Info record saying the session closed with the close
+/// as its attributes, `event` being `close`.
+pub fn close_record(machine: Option<&str>, close: &SessionClose) -> Record {
+    let now = SystemTime::now();
+
+    let mut attributes = vec![
+        ("event", AnyValue::from("close".to_string())),
+        ("pid", AnyValue::Int(close.pid as i64)),
+        ("duration_ms", AnyValue::Int(close.duration_ms as i64)),
+        ("messages", AnyValue::Int(close.messages as i64)),
+        ("malformed", AnyValue::Int(close.malformed as i64)),
+        ("reason", AnyValue::from(close.reason.clone()))
+    ];
+
+    if let Some(machine) = machine {
+        attributes.push(("machine", AnyValue::from(machine.to_string())));
+    }
+
+    Record {
+        timestamp: now,
+        observed: now,
+        severity_number: Some(logs::Severity::Info),
+        severity_text: "Info",
+        body: "Session closed".to_string(),
+        attributes
+    }
+}
+
+/// An exporter that counts the records it failed to export as dropped, and warns when exporting
+/// starts failing and once it works again rather than on every batch.
+#[derive(Debug)]
+struct Counting<E> {
+    exporter: E,
+    failing: Arc<AtomicBool>
+}
+
+impl<E: LogExporter> LogExporter for Counting<E> {
+    fn export(&self, batch: LogBatch<'_>) -> impl Future<Output = OTelSdkResult> + Send {
+        let records = batch.iter().count();
+        Exported { export: Box::pin(self.exporter.export(batch)), failing: &self.failing, records }
+    }
+
+    fn shutdown_with_timeout(&self, timeout: Duration) -> OTelSdkResult {
+        self.exporter.shutdown_with_timeout(timeout)
+    }
+
+    fn set_resource(&mut self, resource: &Resource) {
+        self.exporter.set_resource(resource)
+    }
+}
+
+struct Exported<'a, F> {
+    export: Pin<Box<F>>,
+    failing: &'a AtomicBool,
+    records: usize
+}
+
+impl<'a, F: Future<Output = OTelSdkResult>> Future for Exported<'a, F> {
+    type Output = OTelSdkResult;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<OTelSdkResult> {
+        let exported = self.get_mut();
+        let result = match exported.export.as_mut().poll(cx) {
+            Poll::Ready(result) => result,
+            Poll::Pending => return Poll::Pending
+        };
+
+        match result {
+            Ok(()) if exported.failing.swap(false, Ordering::SeqCst) => warn!("Exporting to the --otlp collector works again"),
+            Ok(()) => (),
+            Err(ref e) => {
+                if !exported.failing.swap(true, Ordering::SeqCst) {
+                    warn!("Could not export to the --otlp collector, dropping records until it can be reached: {}", e);
+                }
+
+                for _ in 0..exported.records {
+                    stats::STATS.dropped_record();
+                }
+            }
+        }
+
+        Poll::Ready(result)
+    }
+}
+
+/// The `--otlp` exporter, sending every text message to an OpenTelemetry collector as a log record
+/// over OTLP/HTTP with protobuf.
+///
+/// Records are batched and exported by a thread of the SDK's batch processor, clients only hand
+/// them over. It holds up to 2048 records, the ones that don't fit while the collector is slow or
+/// down are dropped, and so are the batches the collector doesn't take, which count as dropped
+/// records in the stats. Files never wait for the collector.
+pub struct Writer {
+    provider: SdkLoggerProvider,
+    logger: SdkLogger,
+    /// Set while exports fail, which has been warned about already.
+    failing: Arc<AtomicBool>
+}
+
+impl Writer {
+    /// `endpoint` is the full URL of the logs endpoint, like `http://localhost:4318/v1/logs`.
+    pub fn open(endpoint: &str) -> Result<Writer, String> {
+        let exporter = opentelemetry_otlp::LogExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .build()
+            .map_err(|e| format!("Option --otlp could not set up exporting to {}: {}", endpoint, e))?;
+
+        let failing = Arc::new(AtomicBool::new(false));
+        let provider = SdkLoggerProvider::builder()
+            .with_resource(Resource::builder_empty().with_service_name("eve-logger").build())
+            .with_batch_exporter(Counting { exporter, failing: failing.clone() })
+            .build();
+
+        let logger = provider.logger("eve-logger");
+
+        Ok(Writer { provider, logger, failing })
+    }
+
+    pub fn sink(&self) -> OtlpSink<'_> {
+        OtlpSink { writer: self, machines: HashMap::new() }
+    }
+
+    fn emit(&self, record: Record) {
+        let mut log = self.logger.create_log_record();
+        log.set_timestamp(record.timestamp);
+        log.set_observed_timestamp(record.observed);
+        log.set_severity_text(record.severity_text);
+        log.set_body(AnyValue::from(record.body));
+
+        if let Some(number) = record.severity_number {
+            log.set_severity_number(number);
+        }
+
+        for (key, value) in record.attributes {
+            log.add_attribute(key, value);
+        }
+
+        self.logger.emit(log);
+    }
+
+    /// Waits for the records handed over so far to be exported.
+    pub fn flush(&self) {
+        if let Err(e) = self.provider.force_flush() {
+            if !self.failing.load(Ordering::SeqCst) {
+                warn!("Could not flush the --otlp exporter: {}", e);
+            }
+        }
+    }
+
+    /// Exports what is left, after which records are dropped.
+    pub fn shutdown(&self) {
+        if let Err(e) = self.provider.shutdown() {
+            warn!("Could not shut down the --otlp exporter: {}", e);
+        }
+    }
+}
+
+/// Hands the text messages of one client to the exporter, with the machine of their pid.
+pub struct OtlpSink<'a> {
+    writer: &'a Writer,
+    machines: HashMap<u64, String>
+}
+
+impl<'a> Sink for OtlpSink<'a> {
+    fn connection(&mut self, message: &ConnectionMessage, _: Option<&str>) -> io::Result<()> {
+        self.machines.insert(message.pid, message.machine_name.clone());
+        Ok(())
+    }
+
+    fn text(&mut self, pid: u64, _: Option<u64>, timestamp: &NaiveDateTime, received: &NaiveDateTime, message: &TextMessage) -> io::Result<()> {
+        let machine = self.machines.get(&pid).map(|machine| &machine[..]);
+        self.writer.emit(record(pid, machine, timestamp, received, message));
+
+        Ok(())
+    }
+
+    fn close_session(&mut self, close: &SessionClose) -> io::Result<()> {
+        let machine = self.machines.get(&close.pid).map(|machine| &machine[..]);
+        self.writer.emit(close_record(machine, close));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,200 @@
+use std::io;
+
+use std::error::Error;
+use std::ffi::{c_char, c_int, c_void, CString};
+use std::path::Path;
+use std::sync::Mutex;
+
+use chrono::naive::datetime::NaiveDateTime;
+
+use eve_logger::{ConnectionMessage, TextMessage};
+
+use libloading::Library;
+
+use sink::Sink;
+
+/// The version of the interface below, which a plugin has to return from
+/// `eve_logger_plugin_abi`. It only changes when the functions or `PluginMessage` do.
+pub const ABI_VERSION: u32 = 1;
+
+/// A UTF-8 string that is only valid for the duration of the call it is passed to, not
+/// NUL-terminated.
+#[repr(C)]
+pub struct PluginStr {
+    pub ptr: *const u8,
+    pub len: usize
+}
+
+impl PluginStr {
+    fn new(s: &str) -> PluginStr {
+        PluginStr { ptr: s.as_ptr(), len: s.len() }
+    }
+}
+
+/// A text message as a plugin gets it.
+#[repr(C)]
+pub struct PluginMessage {
+    pub pid: u64,
+    /// The `--sequence-numbers` counter of the message, 0 without it.
+    pub sequence: u64,
+    /// The `--time-source` timestamp, in seconds since the Unix epoch.
+    pub timestamp: i64,
+    /// The time the server read the message, in milliseconds since the Unix epoch.
+    pub received_ms: i64,
+    /// The severity as sent on the wire, 0 to 3 for Info, Notice, Warn and Error.
+    pub severity: u32,
+    /// 1 when the message was cut short by `--max-message-bytes` or `--max-continuation-chunks`.
+    pub truncated: u8,
+    pub module: PluginStr,
+    pub channel: PluginStr,
+    pub message: PluginStr
+}
+
+type Abi = unsafe extern "C" fn() -> u32;
+type Init = unsafe extern "C" fn(*const c_char) -> *mut c_void;
+type Write = unsafe extern "C" fn(*mut c_void, *const PluginMessage) -> c_int;
+type Flush = unsafe extern "C" fn(*mut c_void) -> c_int;
+type Shutdown = unsafe extern "C" fn(*mut c_void);
+
+/// The state `init` returned, which only the plugin's own functions look at.
+struct State(*mut c_void);
+
+// The plugin is only ever called with the lock on the state held.
+unsafe impl Send for State {}
+
+/// A `--sink-plugin`, a shared library that gets every text message.
+///
+/// The library exports these C functions, with `PluginMessage` and `PluginStr` laid out as their
+/// `#[repr(C)]` definitions here:
+///
+/// ```c
+/// uint32_t eve_logger_plugin_abi(void);
+/// void *eve_logger_plugin_init(const char *config);
+/// int eve_logger_plugin_write(void *state, const struct PluginMessage *message);
+/// int eve_logger_plugin_flush(void *state);
+/// void eve_logger_plugin_shutdown(void *state);
+/// ```
+///
+/// `abi` returns the `ABI_VERSION` the plugin was written for, a library for any other version is
+/// refused before anything else is called. `init` gets the `--sink-plugin-config` string, and
+/// returns the state passed to every other call or NULL when it can't start. `write` and `flush`
+/// return 0 on success. `shutdown` is called once, when the server exits after a SIGTERM, a
+/// `shutdown` on the control socket, `--max-runtime` or the end of a replay.
+///
+/// The server serializes every call, so a plugin needs no locking of its own. Calls are made from
+/// the client threads and hold up the client while they run, so a slow output should queue the
+/// message and write it on a thread of its own. See `examples/sink-plugin.rs` for a plugin that
+/// counts the messages it gets.
+pub struct Plugin {
+    write: Write,
+    flush: Flush,
+    shutdown: Shutdown,
+    state: Mutex<Option<State>>,
+    // Last, as the functions above point into it.
+    _library: Library
+}
+
+impl Plugin {
+    pub fn load(path: &Path, config: &str) -> Result<Plugin, String> {
+        let library = unsafe { Library::new(path) }.map_err(|e| match e.source() {
+            // The reason dlopen gave, rather than only that it failed.
+            Some(reason) => format!("Could not load the sink plugin {}: {}", path.display(), reason),
+            None => format!("Could not load the sink plugin {}: {}", path.display(), e)
+        })?;
+
+        let (abi, init, write, flush, shutdown) = unsafe {
+            let symbol = |name: &str| format!("The sink plugin {} has no {}", path.display(), name);
+
+            (
+                *library.get::<Abi>(b"eve_logger_plugin_abi\0").map_err(|_| symbol("eve_logger_plugin_abi"))?,
+                *library.get::<Init>(b"eve_logger_plugin_init\0").map_err(|_| symbol("eve_logger_plugin_init"))?,
+                *library.get::<Write>(b"eve_logger_plugin_write\0").map_err(|_| symbol("eve_logger_plugin_write"))?,
+                *library.get::<Flush>(b"eve_logger_plugin_flush\0").map_err(|_| symbol("eve_logger_plugin_flush"))?,
+                *library.get::<Shutdown>(b"eve_logger_plugin_shutdown\0").map_err(|_| symbol("eve_logger_plugin_shutdown"))?
+            )
+        };
+
+        let version = unsafe { abi() };
+
+        if version != ABI_VERSION {
+            return Err(format!("The sink plugin {} is for plugin ABI {}, this server has {}", path.display(), version, ABI_VERSION));
+        }
+
+        let config = CString::new(config).map_err(|_| "Option --sink-plugin-config can't hold a NUL".to_string())?;
+        let state = unsafe { init(config.as_ptr()) };
+
+        if state.is_null() {
+            return Err(format!("The sink plugin {} failed to start", path.display()));
+        }
+
+        Ok(Plugin { write, flush, shutdown, state: Mutex::new(Some(State(state))), _library: library })
+    }
+
+    pub fn sink(&self) -> PluginSink<'_> {
+        PluginSink { plugin: self }
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        match *self.state.lock().unwrap() {
+            Some(ref state) => check("flush", unsafe { (self.flush)(state.0) }),
+            None => Ok(())
+        }
+    }
+
+    /// Calls the plugin's `shutdown`, after which it gets nothing more.
+    pub fn shutdown(&self) {
+        if let Some(state) = self.state.lock().unwrap().take() {
+            unsafe { (self.shutdown)(state.0) }
+        }
+    }
+
+    fn write(&self, message: &PluginMessage) -> io::Result<()> {
+        match *self.state.lock().unwrap() {
+            Some(ref state) => check("write a message", unsafe { (self.write)(state.0, message) }),
+            None => Ok(())
+        }
+    }
+}
+
+fn check(what: &str, result: c_int) -> io::Result<()> {
+    match result {
+        0 => Ok(()),
+        e => Err(io::Error::other(format!("The sink plugin failed to {}, returning {}", what, e)))
+    }
+}
+
+/// Hands the text messages of one client to the plugin.
+pub struct PluginSink<'a> {
+    plugin: &'a Plugin
+}
+
+impl<'a> Sink for PluginSink<'a> {
+    fn connection(&mut self, _: &ConnectionMessage, _: Option<&str>) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// A message the plugin fails to write is a warning, the client and its other sinks carry on.
+    fn text(&mut self, pid: u64, sequence: Option<u64>, timestamp: &NaiveDateTime, received: &NaiveDateTime, message: &TextMessage) -> io::Result<()> {
+        let message = PluginMessage {
+            pid,
+            sequence: sequence.unwrap_or(0),
+            timestamp: timestamp.timestamp(),
+            received_ms: received.timestamp() * 1000 + i64::from(received.timestamp_subsec_millis()),
+            severity: message.severity.to_u32(),
+            truncated: message.truncated as u8,
+            module: PluginStr::new(&message.module),
+            channel: PluginStr::new(&message.channel),
+            message: PluginStr::new(&message.message)
+        };
+
+        if let Err(e) = self.plugin.write(&message) {
+            warn!("{}", e);
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.plugin.flush()
+    }
+}
@@ -0,0 +1,64 @@
+use std::{io, process};
+
+use std::io::Read;
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use eve_logger::{write_packet, ConnectionMessage, Message, DEFAULT_PORT};
+
+use {option_value, parse_address, parse_number};
+
+/// `eve-logger probe [--bind <addr>] [--send-connection] [--timeout <ms>]`, a liveness check
+/// for a running server.
+///
+/// Connects to the server and waits a moment: a server at `--max-connections` closes the
+/// connection right away, one that accepted it keeps waiting for packets. With
+/// `--send-connection` a Connection message for this process is sent first, note that the server
+/// logs it like any other client and creates its pid directory. Returns the exit code.
+pub fn run<I: Iterator<Item = String>>(args: I) -> i32 {
+    match probe(args) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Probe failed: {}", e);
+            1
+        }
+    }
+}
+
+fn probe<I: Iterator<Item = String>>(args: I) -> Result<(), String> {
+    let mut args = args;
+    let mut address = SocketAddr::from(([127, 0, 0, 1], DEFAULT_PORT));
+    let mut send_connection = false;
+    let mut timeout = Duration::from_millis(1000);
+
+    while let Some(arg) = args.next() {
+        match arg.as_ref() {
+            "--bind" => address = parse_address(&arg, &option_value(&arg, &mut args)?)?,
+            "--send-connection" => send_connection = true,
+            "--timeout" => timeout = Duration::from_millis(parse_number(&arg, &option_value(&arg, &mut args)?)?),
+            e => return Err(format!("Unknown option {}", e))
+        }
+    }
+
+    let mut stream = TcpStream::connect_timeout(&address, timeout).map_err(|e| format!("could not connect to {}: {}", address, e))?;
+
+    if send_connection {
+        let message = Message::Connection(ConnectionMessage {
+            version: 1,
+            pid: u64::from(process::id()),
+            machine_name: "probe".to_string(),
+            executable_path: "eve-logger probe".to_string()
+        });
+
+        write_packet(&mut stream, &message).map_err(|e| format!("could not send Connection message: {}", e))?;
+    }
+
+    stream.set_read_timeout(Some(timeout / 4)).map_err(|e| e.to_string())?;
+
+    match stream.read(&mut [0; 1]) {
+        Ok(0) => Err(format!("{} closed the connection, it may be at its connection limit", address)),
+        Ok(_) => Ok(()),
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => Ok(()),
+        Err(e) => Err(format!("connection to {} failed: {}", address, e))
+    }
+}
@@ -0,0 +1,225 @@
+use std::{fs, io, mem};
+
+use std::cell::{Cell, RefCell};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use eve_logger::Message;
+
+enum State {
+    /// What was read before the first Connection message named the file.
+    Pending(Vec<u8>),
+    /// The capture and, with `--checkpoint-interval`, its index.
+    Open(PathBuf, io::BufWriter<fs::File>, Option<io::BufWriter<fs::File>>),
+    /// Opening or writing failed, which was warned about once.
+    Failed
+}
+
+/// `--save-raw`, every byte one client sent, written to `{dir}/{pid}-{session}.bin` so it can be
+/// given to `--replay` as it is.
+///
+/// The pid is that of the first Connection message, later ones in the same stream stay in the same
+/// file so it replays the same. A stream without a Connection message goes to `0-{session}.bin`.
+/// The session is the id the client has on the control socket, and 0 for `--stdin`, so the files
+/// of an earlier run of the server with the same names are replaced, unless `--session-ids` has
+/// the ids carry on from that run.
+///
+/// The bytes are saved as they were read, before any parsing, so packets that are malformed or cut
+/// short are saved too. A file that can't be written is a warning, the client carries on without it.
+///
+/// With `--checkpoint-interval` the capture gets an index next to it, `{pid}-{session}.idx`, so a
+/// long one can be seeked into. Every time a message has been read and the interval has passed
+/// since the last checkpoint, a record of 16 bytes is appended to it: the offset into the capture
+/// where the next packet starts and the time it was written as milliseconds since the Unix epoch,
+/// both as little-endian u64. The offsets only grow, and each is the start of a packet that begins
+/// a message, never a continuation. Replaying from one needs the Connection message in front of
+/// it, which is the first packet of the capture for any client that announced itself. Both files
+/// are flushed at every checkpoint, so an offset never points past what is on disk.
+pub struct Capture {
+    directory: PathBuf,
+    session: u64,
+    checkpoint_interval: Option<Duration>,
+    state: RefCell<State>,
+    /// The bytes saved so far, which is the offset of the next one in the file.
+    saved: Cell<u64>,
+    checkpointed: Cell<Instant>
+}
+
+impl Capture {
+    pub fn new(directory: &Path, session: u64, checkpoint_interval: Option<Duration>) -> Capture {
+        Capture {
+            directory: directory.to_path_buf(),
+            session,
+            checkpoint_interval,
+            state: RefCell::new(State::Pending(Vec::new())),
+            saved: Cell::new(0),
+            checkpointed: Cell::new(Instant::now())
+        }
+    }
+
+    fn save(&self, bytes: &[u8]) {
+        self.saved.set(self.saved.get() + bytes.len() as u64);
+
+        let mut state = self.state.borrow_mut();
+
+        let failed = match *state {
+            State::Pending(ref mut pending) => {
+                pending.extend_from_slice(bytes);
+                return;
+            },
+            State::Open(ref path, ref mut file, _) => match file.write_all(bytes) {
+                Ok(()) => return,
+                Err(e) => format!("Could not write the raw capture {}: {}", path.display(), e)
+            },
+            State::Failed => return
+        };
+
+        warn!("{}", failed);
+        *state = State::Failed;
+    }
+
+    /// Opens the file of `pid`, unless the file was already opened for an earlier pid.
+    fn name(&self, pid: u64) {
+        let mut state = self.state.borrow_mut();
+
+        let pending = match *state {
+            State::Pending(ref mut pending) => mem::take(pending),
+            _ => return
+        };
+
+        let path = self.directory.join(format!("{}-{}.bin", pid, self.session));
+
+        let opened = fs::create_dir_all(&self.directory)
+            .and_then(|_| fs::File::create(&path))
+            .map(io::BufWriter::new)
+            .and_then(|mut file| file.write_all(&pending).map(|_| file));
+
+        let index = self.checkpoint_interval.and_then(|_| {
+            let index = path.with_extension("idx");
+
+            match fs::File::create(&index) {
+                Ok(file) => Some(io::BufWriter::new(file)),
+                Err(e) => {
+                    warn!("Could not open the checkpoint index {}: {}", index.display(), e);
+                    None
+                }
+            }
+        });
+
+        *state = match opened {
+            Ok(file) => State::Open(path, file, index),
+            Err(e) => {
+                warn!("Could not open the raw capture {}: {}", path.display(), e);
+                State::Failed
+            }
+        };
+    }
+
+    /// Records where the next packet starts in the index, if `--checkpoint-interval` has passed
+    /// since the last time. Only called between messages, so this is a packet boundary.
+    fn checkpoint(&self) {
+        match self.checkpoint_interval {
+            Some(interval) if self.checkpointed.get().elapsed() >= interval => (),
+            _ => return
+        }
+
+        let mut state = self.state.borrow_mut();
+
+        if let State::Open(ref path, ref mut file, ref mut index) = *state {
+            let written = match *index {
+                Some(ref mut writer) => {
+                    let millis = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_millis() as u64);
+
+                    let mut record = [0; 16];
+                    record[..8].copy_from_slice(&self.saved.get().to_le_bytes());
+                    record[8..].copy_from_slice(&millis.to_le_bytes());
+
+                    file.flush().and_then(|_| writer.write_all(&record)).and_then(|_| writer.flush())
+                },
+                None => return
+            };
+
+            match written {
+                Ok(()) => self.checkpointed.set(Instant::now()),
+                Err(e) => {
+                    warn!("Could not write the checkpoint index of {}: {}", path.display(), e);
+                    *index = None;
+                }
+            }
+        }
+    }
+
+    /// Writes out what is left once the client is gone. A client that sent nothing at all gets
+    /// no file.
+    pub fn finish(&self) {
+        if let State::Pending(ref pending) = *self.state.borrow() {
+            if pending.is_empty() {
+                return;
+            }
+        }
+
+        self.name(0);
+
+        let failed = match *self.state.borrow_mut() {
+            State::Open(ref path, ref mut file, ref mut index) => match file.flush().and_then(|_| index.as_mut().map_or(Ok(()), |index| index.flush())) {
+                Ok(()) => return,
+                Err(e) => format!("Could not write the raw capture {}: {}", path.display(), e)
+            },
+            _ => return
+        };
+
+        warn!("{}", failed);
+    }
+}
+
+/// A reader that saves everything read through it to a `Capture`.
+pub struct Tee<'a, R> {
+    reader: R,
+    capture: &'a Capture
+}
+
+impl<'a, R: Read> Tee<'a, R> {
+    pub fn new(reader: R, capture: &'a Capture) -> Tee<'a, R> {
+        Tee { reader, capture }
+    }
+}
+
+impl<'a, R: Read> Read for Tee<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        self.capture.save(&buf[..read]);
+
+        Ok(read)
+    }
+}
+
+/// The messages read through a `Tee`, naming its `Capture` after the first Connection message.
+pub struct Named<'a, I> {
+    messages: I,
+    capture: Option<&'a Capture>
+}
+
+impl<'a, I> Named<'a, I> {
+    pub fn new(messages: I, capture: Option<&'a Capture>) -> Named<'a, I> {
+        Named { messages, capture }
+    }
+}
+
+impl<'a, I: Iterator<Item = io::Result<Message>>> Iterator for Named<'a, I> {
+    type Item = io::Result<Message>;
+
+    fn next(&mut self) -> Option<io::Result<Message>> {
+        let message = self.messages.next();
+
+        if let (Some(capture), Some(Ok(Message::Connection(ref connection)))) = (self.capture, &message) {
+            capture.name(connection.pid);
+        }
+
+        if let (Some(capture), Some(Ok(_))) = (self.capture, &message) {
+            capture.checkpoint();
+        }
+
+        message
+    }
+}
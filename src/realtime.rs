@@ -0,0 +1,71 @@
+use std::{io, thread};
+
+use std::time::{Duration, Instant};
+
+use chrono::naive::datetime::NaiveDateTime;
+
+use eve_logger::Message;
+
+use sink::TimestampMode;
+
+/// `--realtime`, replaying text messages as far apart as their timestamps are, divided by
+/// `--speed`.
+///
+/// Each message is held back until its place in a schedule started by the first one, rather than
+/// by its gap to the one before, so the time the sinks take doesn't add up over a long replay. A
+/// gap is at most `--realtime-max-gap`, which keeps a capture spanning a quiet night from pausing
+/// for hours, and a timestamp earlier than the one before is no gap at all. Timestamps are decoded
+/// with `--timestamp-mode`, so relative ones are paced to the millisecond and absolute ones to the
+/// second. Everything but text messages passes straight through.
+pub struct Realtime<I> {
+    messages: I,
+    mode: TimestampMode,
+    speed: f64,
+    max_gap: Duration,
+    /// The timestamp of the last text message and when it was due.
+    last: Option<(NaiveDateTime, Instant)>
+}
+
+impl<I> Realtime<I> {
+    pub fn new(messages: I, mode: TimestampMode, speed: f64, max_gap: Duration) -> Realtime<I> {
+        Realtime { messages, mode, speed, max_gap, last: None }
+    }
+}
+
+impl<I: Iterator<Item = io::Result<Message>>> Iterator for Realtime<I> {
+    type Item = io::Result<Message>;
+
+    fn next(&mut self) -> Option<io::Result<Message>> {
+        let message = self.messages.next();
+
+        if let Some(Ok(Message::Text(ref text))) = message {
+            // Relative timestamps only have to be apart the right amount, any start does. One out of
+            // range is no gap, as if it were sent along with the one before.
+            let start = NaiveDateTime::from_timestamp_opt(0, 0).unwrap();
+            let timestamp = match (self.mode.decode(text.timestamp, start), self.last) {
+                (Some(timestamp), _) => timestamp,
+                (None, Some((last, _))) => last,
+                (None, None) => start
+            };
+
+            let due = match self.last {
+                Some((last, due)) => {
+                    // A slow enough --speed makes one longer than a Duration holds.
+                    let gap = (timestamp - last).to_std().unwrap_or_default();
+                    due + Duration::try_from_secs_f64(gap.as_secs_f64() / self.speed).map_or(self.max_gap, |gap| gap.min(self.max_gap))
+                },
+                None => Instant::now()
+            };
+
+            let now = Instant::now();
+
+            if due > now {
+                thread::sleep(due - now);
+            }
+
+            self.last = Some((timestamp, due));
+        }
+
+        message
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::{HashMap, VecDeque};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use eve_logger::TextMessage;
+
+/// What a session left behind for one pid when it ended.
+pub struct Left {
+    at: Instant,
+    /// The id of the session, which the one carrying on with it reports as its own.
+    pub session: Option<u64>,
+    /// The `--sequence-numbers` counter of each module.
+    pub sequences: HashMap<String, u64>
+}
+
+/// `--reconnect-grace`, the pids whose clients disconnected recently, so one that connects again
+/// within the grace period carries on where it left off.
+///
+/// The session id and the sequence counters carry over, files are opened again and the pid
+/// directory was the same to begin with. The client keeps the id it connected with on the control
+/// socket, and `--save-raw` saves what it sends under that one. A pid that was announced by several
+/// clients at once carries over the session of whichever of them disconnected last.
+pub struct Recent {
+    grace: Option<Duration>,
+    left: Mutex<HashMap<u64, Left>>
+}
+
+impl Recent {
+    pub fn new(grace: Option<Duration>) -> Recent {
+        Recent { grace, left: Mutex::new(HashMap::new()) }
+    }
+
+    /// Records that the session `session` of `pid` ended with `sequences`, forgetting the pids
+    /// whose grace period has passed. Does nothing without `--reconnect-grace`.
+    pub fn leave(&self, pid: u64, session: Option<u64>, sequences: HashMap<String, u64>) {
+        let grace = match self.grace {
+            Some(grace) => grace,
+            None => return
+        };
+
+        let mut left = self.left.lock().unwrap();
+        left.retain(|_, left| left.at.elapsed() < grace);
+        left.insert(pid, Left { at: Instant::now(), session, sequences });
+    }
+
+    /// What `pid` left behind if its last session ended within the grace period, which the new
+    /// session then takes over.
+    pub fn rejoin(&self, pid: u64) -> Option<Left> {
+        let grace = self.grace?;
+        let left = self.left.lock().unwrap().remove(&pid)?;
+
+        if left.at.elapsed() < grace { Some(left) } else { None }
+    }
+}
+
+/// Lines remembered of each (pid, module) by `--dedup-on-reconnect`.
+const TAIL: usize = 64;
+
+/// How long after a Connection message `--dedup-on-reconnect` skips lines it has seen already.
+pub const DEDUP_WINDOW: Duration = Duration::from_secs(10);
+
+/// How long `--dedup-on-reconnect` remembers the lines of a pid that left, without
+/// `--reconnect-grace`.
+pub const DEDUP_KEEP: Duration = Duration::from_secs(300);
+
+/// The lines remembered of one pid.
+#[derive(Default)]
+struct Tail {
+    /// When its last session ended, `None` while it is writing.
+    left: Option<Instant>,
+    hashes: HashMap<String, VecDeque<u64>>
+}
+
+/// `--dedup-on-reconnect`, the hashes of the last `TAIL` lines written for each (pid, module),
+/// kept across sessions so a client that replays what it sent before it reconnected doesn't write
+/// those lines twice.
+///
+/// For `DEDUP_WINDOW` after its Connection message, a message whose hash is among those of its
+/// (pid, module) is skipped, after that every message is written again whatever it is. The hash
+/// is the SipHash of `DefaultHasher` over the raw timestamp, the severity, the channel and the
+/// body, so the same line sent at another time is still written. Two different lines with the
+/// same hash would lose the second one, which with 64 bits is left to chance.
+///
+/// The lines of a pid are forgotten once it has been gone for longer than `keep`, its
+/// `--reconnect-grace` or `DEDUP_KEEP`, so a server seeing one pid after the other for months
+/// doesn't remember all of them.
+pub struct Written {
+    keep: Duration,
+    tails: Mutex<HashMap<u64, Tail>>
+}
+
+impl Written {
+    pub fn new(keep: Duration) -> Written {
+        Written { keep, tails: Mutex::new(HashMap::new()) }
+    }
+
+    /// Records that a session of `pid` ended, forgetting the pids that have been gone for longer
+    /// than `keep`.
+    pub fn leave(&self, pid: u64) {
+        let mut tails = self.tails.lock().unwrap();
+
+        if let Some(tail) = tails.get_mut(&pid) {
+            tail.left = Some(Instant::now());
+        }
+
+        tails.retain(|_, tail| tail.left.is_none_or(|left| left.elapsed() < self.keep));
+    }
+
+    /// How many pids have lines remembered.
+    #[cfg(test)]
+    pub fn pids(&self) -> usize {
+        self.tails.lock().unwrap().len()
+    }
+
+    /// Whether `message` of `pid` is to be written, remembering it if it is. A message is only
+    /// skipped while `replaying`, the window after a Connection message.
+    pub fn write(&self, pid: u64, message: &TextMessage, replaying: bool) -> bool {
+        let hash = line_hash(message);
+        let mut tails = self.tails.lock().unwrap();
+        let pid = tails.entry(pid).or_default();
+        pid.left = None;
+
+        let tail = pid.hashes.entry(message.module.to_string()).or_default();
+
+        if replaying && tail.contains(&hash) {
+            return false;
+        }
+
+        if tail.len() == TAIL {
+            tail.pop_front();
+        }
+
+        tail.push_back(hash);
+        true
+    }
+}
+
+fn line_hash(message: &TextMessage) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    message.timestamp.hash(&mut hasher);
+    message.severity.to_u32().hash(&mut hasher);
+    message.channel.hash(&mut hasher);
+    message.message.hash(&mut hasher);
+    hasher.finish()
+}
@@ -0,0 +1,131 @@
+use std::{fs, io, thread};
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use regex::Regex;
+
+use {output_root, Context};
+
+/// `--retention-days`, removes the pid directories under `root` whose newest file is older than
+/// `days`, along with the `--archive-on-disconnect` archives that old. Returns what was removed.
+///
+/// Only `{pid}/` and `{pid}.tar.gz` (or `{pid}.N.tar.gz`) are looked at, below each
+/// `{YYYY-MM-DD}/` instead with `--date-dir`, where a date directory the sweep emptied is removed
+/// as well. Anything else in the output directory is never touched, and the directories of the
+/// pids in `open` are skipped however old they are. Links are removed rather than followed.
+///
+/// An entry that can't be looked at or removed is a warning, the others are still swept.
+pub fn sweep(root: &Path, date_dir: bool, days: u64, open: &BTreeSet<u64>) -> Vec<PathBuf> {
+    let cutoff = SystemTime::now() - Duration::from_secs(days * 24 * 60 * 60);
+    let mut removed = Vec::new();
+
+    if !date_dir {
+        sweep_directory(root, cutoff, open, &mut removed);
+        return removed;
+    }
+
+    let date = Regex::new(r"^\d{4}-\d{2}-\d{2}$").unwrap();
+
+    for directory in entries(root).into_iter().filter(|path| is_named(path, &date) && fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_dir())) {
+        let before = removed.len();
+        sweep_directory(&directory, cutoff, open, &mut removed);
+
+        if removed.len() > before && entries(&directory).is_empty() && fs::remove_dir(&directory).is_ok() {
+            removed.push(directory);
+        }
+    }
+
+    removed
+}
+
+fn sweep_directory(directory: &Path, cutoff: SystemTime, open: &BTreeSet<u64>, removed: &mut Vec<PathBuf>) {
+    let pid = Regex::new(r"^(\d+)(\.\d+\.tar\.gz|\.tar\.gz)?$").unwrap();
+
+    for path in entries(directory) {
+        let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default().to_string();
+
+        let is_open = match pid.captures(&name) {
+            Some(captures) => captures[1].parse().is_ok_and(|pid: u64| open.contains(&pid)),
+            None => continue
+        };
+
+        if is_open {
+            continue;
+        }
+
+        let result = newest(&path).and_then(|newest| if newest < cutoff { remove(&path).map(|_| true) } else { Ok(false) });
+
+        match result {
+            Ok(true) => removed.push(path),
+            Ok(false) => (),
+            Err(e) => warn!("Could not sweep {} for --retention-days: {}", path.display(), e)
+        }
+    }
+}
+
+/// The entries of `directory`, none if it can't be read.
+fn entries(directory: &Path) -> Vec<PathBuf> {
+    match fs::read_dir(directory) {
+        Ok(entries) => entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect(),
+        Err(_) => Vec::new()
+    }
+}
+
+fn is_named(path: &Path, pattern: &Regex) -> bool {
+    path.file_name().and_then(|name| name.to_str()).is_some_and(|name| pattern.is_match(name))
+}
+
+/// When the newest file in `path` was last modified, `path` itself when it is a file or holds no
+/// files at all.
+fn newest(path: &Path) -> io::Result<SystemTime> {
+    match newest_file(path)? {
+        Some(newest) => Ok(newest),
+        None => fs::symlink_metadata(path)?.modified()
+    }
+}
+
+fn newest_file(path: &Path) -> io::Result<Option<SystemTime>> {
+    let metadata = fs::symlink_metadata(path)?;
+
+    if !metadata.is_dir() {
+        return metadata.modified().map(Some);
+    }
+
+    let mut newest = None;
+
+    for entry in fs::read_dir(path)? {
+        newest = newest.max(newest_file(&entry?.path())?);
+    }
+
+    Ok(newest)
+}
+
+fn remove(path: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// Sweeps the output directory of the server, skipping the pids of connected clients.
+pub fn sweep_output(context: &Context, days: u64) {
+    let root = output_root(&context.config);
+    let root = if root.as_os_str().is_empty() { PathBuf::from(".") } else { root };
+    let open = context.clients.modules().into_keys().collect();
+
+    for path in sweep(&root, context.config.date_dir, days, &open) {
+        info!("Removed {}, older than the --retention-days of {}", path.display(), days);
+    }
+}
+
+/// `--retention-interval`, sweeps again every `interval` for the lifetime of the server.
+pub fn watch(context: Arc<Context>, days: u64, interval: Duration) {
+    loop {
+        thread::sleep(interval);
+        sweep_output(&context, days);
+    }
+}
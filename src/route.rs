@@ -0,0 +1,162 @@
+use std::io;
+
+use chrono::naive::datetime::NaiveDateTime;
+
+use eve_logger::{ConnectionMessage, Severity, TextMessage};
+
+use regex::Regex;
+
+use sink::{Batched, SessionClose, Sink, SINK_NAMES};
+
+use {at_least, parse_regex, parse_severity, Config};
+
+/// `--route-match`, which of the `--route`s that match a message decide where it goes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RouteMatch {
+    /// The first of them, in the order they were given.
+    First,
+    /// Every one of them, the message going to each sink any of them names.
+    All
+}
+
+impl RouteMatch {
+    pub fn from_name(name: &str, value: &str) -> Result<RouteMatch, String> {
+        match value {
+            "first" => Ok(RouteMatch::First),
+            "all" => Ok(RouteMatch::All),
+            e => Err(format!("Option {} expects first or all, got {}", name, e))
+        }
+    }
+}
+
+/// A `--route`, the sinks the text messages it matches go to.
+#[derive(Debug)]
+pub struct Route {
+    min_severity: Option<Severity>,
+    module: Option<Regex>,
+    channel: Option<Regex>,
+    grep: Option<Regex>,
+    sinks: Vec<String>
+}
+
+impl Route {
+    /// Whether `message` passes every condition of the route.
+    fn matches(&self, message: &TextMessage) -> bool {
+        self.min_severity.is_none_or(|min| at_least(message.severity, min))
+            && self.module.as_ref().is_none_or(|module| module.is_match(&message.module))
+            && self.channel.as_ref().is_none_or(|channel| channel.is_match(&message.channel))
+            && self.grep.as_ref().is_none_or(|grep| grep.is_match(&message.message))
+    }
+}
+
+/// Parses a `--route`, conditions and the sinks they send to separated by `->`, like
+/// `min-severity=error module=combat* -> notify,files`.
+///
+/// The conditions are separated by whitespace and all have to hold: `min-severity=` a severity
+/// like `--min-severity`, `module=` and `channel=` a name where `*` stands for any run of
+/// characters and `?` for any one, and `grep=` a regular expression found in the body, which
+/// can't hold whitespace itself but `\s` instead. A route of just `*` matches every message. The
+/// sinks are the names `--buffer` knows.
+pub fn parse_route(name: &str, value: &str) -> Result<Route, String> {
+    let expected = || format!("Option {} expects <conditions> -> <sink>,<sink>, got {}", name, value);
+    let (conditions, sinks) = value.split_once("->").ok_or_else(expected)?;
+
+    let mut route = Route { min_severity: None, module: None, channel: None, grep: None, sinks: Vec::new() };
+    let conditions: Vec<&str> = conditions.split_whitespace().collect();
+
+    if conditions.is_empty() {
+        return Err(expected());
+    }
+
+    for condition in conditions {
+        match condition.split_once('=') {
+            Some(("min-severity", severity)) => route.min_severity = Some(parse_severity(name, severity)?),
+            Some(("module", module)) => route.module = Some(glob(module)),
+            Some(("channel", channel)) => route.channel = Some(glob(channel)),
+            Some(("grep", grep)) => route.grep = Some(parse_regex(name, grep)?),
+            None if condition == "*" => (),
+            _ => return Err(format!("Option {} expects conditions min-severity=, module=, channel=, grep= or *, got {}", name, condition))
+        }
+    }
+
+    for sink in sinks.split(',').map(str::trim) {
+        if !SINK_NAMES.contains(&sink) {
+            return Err(format!("Option {} expects one of the sinks {}, got {}", name, SINK_NAMES.join(", "), sink));
+        }
+
+        route.sinks.push(sink.to_string());
+    }
+
+    Ok(route)
+}
+
+/// A whole name matching `pattern`, taking `*` and `?` like `--replay` does.
+fn glob(pattern: &str) -> Regex {
+    Regex::new(&format!("^{}$", regex::escape(pattern).replace("\\*", ".*").replace("\\?", "."))).unwrap()
+}
+
+/// Whether the `--route`s send `message` to the sink called `sink`. A message no route matches
+/// goes nowhere.
+pub fn sends(config: &Config, sink: &str, message: &TextMessage) -> bool {
+    let mut matching = config.routes.iter().filter(|route| route.matches(message));
+
+    match config.route_match {
+        RouteMatch::First => matching.next().is_some_and(|route| route.sinks.iter().any(|name| name == sink)),
+        RouteMatch::All => matching.any(|route| route.sinks.iter().any(|name| name == sink))
+    }
+}
+
+/// A sink that only gets the text messages the `--route`s send to it. Everything else, the
+/// Connection messages, flushes and the end of the session, reaches it as it always does, so its
+/// files are opened and closed the same whether or not any message got to it.
+pub struct Routed<'a> {
+    config: &'a Config,
+    name: &'static str,
+    sink: Box<dyn Sink + 'a>
+}
+
+impl<'a> Routed<'a> {
+    pub fn new(config: &'a Config, name: &'static str, sink: Box<dyn Sink + 'a>) -> Routed<'a> {
+        Routed { config, name, sink }
+    }
+}
+
+impl<'a> Sink for Routed<'a> {
+    fn connection(&mut self, message: &ConnectionMessage, build: Option<&str>) -> io::Result<()> {
+        self.sink.connection(message, build)
+    }
+
+    fn text(&mut self, pid: u64, sequence: Option<u64>, timestamp: &NaiveDateTime, received: &NaiveDateTime, message: &TextMessage) -> io::Result<()> {
+        if !sends(self.config, self.name, message) {
+            return Ok(());
+        }
+
+        self.sink.text(pid, sequence, timestamp, received, message)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+
+    fn close_session(&mut self, close: &SessionClose) -> io::Result<()> {
+        self.sink.close_session(close)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.sink.finish()
+    }
+
+    fn batches(&self) -> bool {
+        self.sink.batches()
+    }
+
+    fn write_batch(&mut self, batch: &[Batched]) -> io::Result<()> {
+        let routed: Vec<Batched> = batch.iter().filter(|entry| sends(self.config, self.name, &entry.message)).cloned().collect();
+
+        if routed.is_empty() {
+            return Ok(());
+        }
+
+        self.sink.write_batch(&routed)
+    }
+}
@@ -0,0 +1,81 @@
+use eve_logger::{write_packet, ConnectionMessage, Message, MessagesWithBytes, Severity, TextMessage};
+
+/// A case of `run`, failing with what it got wrong.
+type Case = fn() -> Result<(), String>;
+
+/// Round-trips every kind of message through `write_packet` and `read_packet`.
+///
+/// Meant for validating the encoder against the parser, and by extension a custom producer that
+/// follows the same layout. Returns whether every case passed.
+pub fn run() -> bool {
+    let cases: &[(&str, Case)] = &[
+        ("connection", || check(&connection())),
+        ("simple", || check(&text("A short message"))),
+        ("large", || check(&text(&"0123456789".repeat(100)))),
+        // 255 bytes, so the first character after them straddles the chunk boundary.
+        ("large-multibyte", || check(&text(&("x".repeat(255) + &"\u{e5}\u{e4}\u{f6}\u{1f680}".repeat(50)))))
+    ];
+
+    let mut passed = true;
+
+    for &(name, case) in cases {
+        match case() {
+            Ok(()) => println!("{}: ok", name),
+            Err(e) => {
+                println!("{}: FAILED, {}", name, e);
+                passed = false;
+            }
+        }
+    }
+
+    passed
+}
+
+/// Whether `message` decodes back to itself.
+fn check(message: &Message) -> Result<(), String> {
+    match round_trip(message)? {
+        ref decoded if decoded == message => Ok(()),
+        decoded => Err(format!("decoded {:?}", decoded))
+    }
+}
+
+pub fn connection() -> Message {
+    connection_with("HOST", "C:\\EVE\\bin\\exefile.exe")
+}
+
+pub fn connection_with(machine_name: &str, executable_path: &str) -> Message {
+    Message::Connection(ConnectionMessage {
+        version: 1,
+        pid: 1234,
+        machine_name: machine_name.to_string(),
+        executable_path: executable_path.to_string()
+    })
+}
+
+pub fn text(message: &str) -> Message {
+    Message::Text(TextMessage {
+        timestamp: 1500000000,
+        severity: Severity::Warn,
+        module: "module".into(),
+        channel: "channel".into(),
+        message: message.to_string(),
+        truncated: false
+    })
+}
+
+pub fn round_trip(message: &Message) -> Result<Message, String> {
+    let mut bytes = Vec::new();
+    write_packet(&mut bytes, message).map_err(|e| e.to_string())?;
+
+    let mut messages = MessagesWithBytes::new(&bytes[..]);
+    let (decoded, raw) = match messages.next() {
+        Some(result) => result.map_err(|e| e.to_string())?,
+        None => return Err("no message decoded".to_string())
+    };
+
+    if raw != bytes {
+        return Err(format!("parsed from {} of the {} bytes written", raw.len(), bytes.len()));
+    }
+
+    Ok(decoded)
+}
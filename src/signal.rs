@@ -0,0 +1,68 @@
+use std::io;
+
+/// The signals the server acts on.
+#[derive(Clone, Copy, Debug)]
+pub enum Signal {
+    Hup, Term, Usr1
+}
+
+/// Calls `f` on a thread of its own every time the process receives `signal`, as in
+/// `kill -HUP <pid>`.
+///
+/// The signal handler only writes a byte to a pipe, `f` runs as an ordinary function so it may
+/// take locks and allocate. Only one `f` is kept per signal.
+#[cfg(unix)]
+pub fn on_signal<F: Fn() + Send + 'static>(signal: Signal, f: F) -> io::Result<()> {
+    use std::thread;
+
+    use std::sync::atomic::{AtomicI32, Ordering};
+
+    use libc;
+
+    /// The write end of the pipe for each signal number.
+    static PIPES: [AtomicI32; 32] = [const { AtomicI32::new(-1) }; 32];
+
+    extern "C" fn handle(signal: libc::c_int) {
+        let byte = 0u8;
+
+        unsafe {
+            libc::write(PIPES[signal as usize].load(Ordering::Relaxed), &byte as *const u8 as *const libc::c_void, 1);
+        }
+    }
+
+    let number = match signal {
+        Signal::Hup => libc::SIGHUP,
+        Signal::Term => libc::SIGTERM,
+        Signal::Usr1 => libc::SIGUSR1
+    };
+
+    let mut pipe = [0; 2];
+
+    if unsafe { libc::pipe(pipe.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    PIPES[number as usize].store(pipe[1], Ordering::Relaxed);
+
+    unsafe {
+        libc::signal(number, handle as *const () as libc::sighandler_t);
+    }
+
+    thread::spawn(move|| loop {
+        let mut byte = 0u8;
+
+        match unsafe { libc::read(pipe[0], &mut byte as *mut u8 as *mut libc::c_void, 1) } {
+            1 => f(),
+            _ if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => (),
+            _ => return
+        }
+    });
+
+    Ok(())
+}
+
+/// There are no signals to wait for elsewhere.
+#[cfg(not(unix))]
+pub fn on_signal<F: Fn() + Send + 'static>(_: Signal, _: F) -> io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,1086 @@
+use std::{cmp, io, mem};
+
+use std::borrow::Cow;
+use std::convert::TryFrom;
+
+use std::io::Write;
+
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono;
+use chrono::UTC;
+use chrono::naive::datetime::NaiveDateTime;
+
+use eve_logger::{ConnectionMessage, Severity, TextMessage};
+
+use serde::ser::{Serialize, SerializeMap, Serializer};
+
+use serde_json;
+
+use {at_least, create_dir, group_directory, log_path, output_root, parse_byte_size, Config, Format, HandleCache, ShardBy};
+
+/// A message as written by `--format json`, fields given to `--drop-fields` are left out.
+#[derive(Serialize)]
+struct JsonLine<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sequence: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pid: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    build: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timestamp: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    received: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    severity: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    module: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    channel: Option<&'a str>,
+    message: Cow<'a, str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    truncated: Option<bool>,
+    #[serde(flatten)]
+    labels: Labels<'a>
+}
+
+/// The version of the `--json-envelope` schema, `v` in every envelope.
+pub const ENVELOPE_VERSION: u32 = 1;
+
+/// `--json-envelope`, a record wrapped as `{"v":1,"type":"text","data":{...}}`, the type being
+/// `text`, `connection` or `close`.
+#[derive(Serialize)]
+struct Envelope<'a, T: 'a> {
+    v: u32,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    data: &'a T
+}
+
+/// A Connection message as a `connection` envelope has it, with the build `--version-regex` found.
+#[derive(Serialize)]
+struct ConnectionRecord<'a> {
+    #[serde(flatten)]
+    message: &'a ConnectionMessage,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    build: Option<&'a str>
+}
+
+/// `data` as JSON, in an envelope of `kind` with `--json-envelope`.
+fn json_record<T: Serialize>(config: &Config, kind: &'static str, data: &T) -> io::Result<String> {
+    let json = match (config.json_envelope, config.json_pretty) {
+        (true, true) => serde_json::to_string_pretty(&Envelope { v: ENVELOPE_VERSION, kind, data }),
+        (true, false) => serde_json::to_string(&Envelope { v: ENVELOPE_VERSION, kind, data }),
+        (false, true) => serde_json::to_string_pretty(data),
+        (false, false) => serde_json::to_string(data)
+    };
+
+    Ok(json.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))? + "\n")
+}
+
+/// A Connection message as a line of `--format json` with `--json-envelope`.
+pub fn json_connection(config: &Config, message: &ConnectionMessage, build: Option<&str>) -> io::Result<String> {
+    json_record(config, "connection", &ConnectionRecord { message, build })
+}
+
+/// A `--close-records` close as a line of `--format json` with `--json-envelope`.
+pub fn json_close(config: &Config, close: &SessionClose) -> io::Result<String> {
+    json_record(config, "close", close)
+}
+
+/// The `--label`s, as top-level keys in the order they were given.
+struct Labels<'a>(&'a [(String, String)]);
+
+impl<'a> Serialize for Labels<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+
+        for (key, value) in self.0 {
+            map.serialize_entry(key, value)?;
+        }
+
+        map.end()
+    }
+}
+
+/// The `--label`s as ` key=value` pairs, for the end of a text line.
+fn label_pairs(config: &Config) -> String {
+    config.labels.iter().map(|(key, value)| format!(" {}={}", key, value)).collect()
+}
+
+/// Parses a `--label key=value`. Neither may hold whitespace, so the pairs at the end of a text
+/// line split back apart, and the key can't be one `--format json` already writes.
+pub fn parse_label(name: &str, value: &str) -> Result<(String, String), String> {
+    let (key, label) = match value.split_once('=') {
+        Some((key, label)) if !key.is_empty() && !label.is_empty() => (key, label),
+        _ => return Err(format!("Option {} expects key=value, got {}", name, value))
+    };
+
+    if value.contains(char::is_whitespace) {
+        return Err(format!("Option {} expects a key and a value without whitespace, got {:?}", name, value));
+    }
+
+    if Field::from_name(key).is_some() {
+        return Err(format!("Option {} can't use {} as a key, messages already have it", name, key));
+    }
+
+    Ok((key.to_string(), label.to_string()))
+}
+
+/// How severities are written, set with `--severity-label` and `--severity-color`.
+///
+/// The labels default to the names the files have always used, an unknown code is written as its
+/// label followed by the code, `Unknown(7)` or with `--severity-label unknown=?` `?(7)`. Colors are
+/// only used on a terminal, for `--errors-to-stderr`.
+#[derive(Clone, Debug)]
+pub struct SeverityStyle {
+    /// Info, Notice, Warn, Error and Unknown, in that order.
+    labels: [String; 5],
+    colors: [Option<u8>; 5]
+}
+
+impl Default for SeverityStyle {
+    fn default() -> SeverityStyle {
+        SeverityStyle {
+            labels: ["Info", "Notice", "Warn", "Error", "Unknown"].map(str::to_string),
+            colors: [None, Some(36), Some(33), Some(31), Some(35)]
+        }
+    }
+}
+
+impl SeverityStyle {
+    pub fn label(&self, severity: Severity) -> String {
+        match severity {
+            Severity::Unknown(code) => format!("{}({})", self.labels[4], code),
+            severity => self.labels[style_index(severity)].clone()
+        }
+    }
+
+    /// The width of the longest label of a known severity, what `--align` pads them to. An
+    /// unknown one is longer with its code, and pushes the rest of its line out.
+    pub fn width(&self) -> usize {
+        self.labels[..4].iter().map(|label| label.chars().count()).max().unwrap_or(0)
+    }
+
+    /// `line` in the color of `severity`, its line terminator left after the reset.
+    pub fn paint(&self, severity: Severity, line: &str) -> String {
+        match self.colors[style_index(severity)] {
+            Some(color) => {
+                let text = line.trim_end_matches(['\r', '\n']);
+                format!("\x1b[{}m{}\x1b[0m{}", color, text, &line[text.len()..])
+            },
+            None => line.to_string()
+        }
+    }
+
+    /// Parses `<severity>=<label>` for `--severity-label`.
+    pub fn set_label(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let (index, label) = style_setting(name, value, "label")?;
+        self.labels[index] = label.to_string();
+
+        Ok(())
+    }
+
+    /// Parses `<severity>=<color>` for `--severity-color`.
+    pub fn set_color(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let (index, color) = style_setting(name, value, "color")?;
+
+        self.colors[index] = match color {
+            "none" => None,
+            "black" => Some(30),
+            "red" => Some(31),
+            "green" => Some(32),
+            "yellow" => Some(33),
+            "blue" => Some(34),
+            "magenta" => Some(35),
+            "cyan" => Some(36),
+            "white" => Some(37),
+            e => return Err(format!("Option {} expects black, red, green, yellow, blue, magenta, cyan, white or none, got {}", name, e))
+        };
+
+        Ok(())
+    }
+}
+
+pub fn style_index(severity: Severity) -> usize {
+    match severity {
+        Severity::Info => 0,
+        Severity::Notice => 1,
+        Severity::Warn => 2,
+        Severity::Error => 3,
+        Severity::Unknown(_) => 4
+    }
+}
+
+fn style_setting<'a>(name: &str, value: &'a str, what: &str) -> Result<(usize, &'a str), String> {
+    let split = value.find('=').ok_or_else(|| format!("Option {} expects <severity>=<{}>, got {}", name, what, value))?;
+
+    let index = match &value[..split] {
+        "info" => 0,
+        "notice" => 1,
+        "warn" => 2,
+        "error" => 3,
+        "unknown" => 4,
+        e => return Err(format!("Option {} expects info, notice, warn, error or unknown before the =, got {}", name, e))
+    };
+
+    Ok((index, &value[split + 1..]))
+}
+
+/// `--line-terminator`, what ends every written line.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineTerminator {
+    Lf, Crlf
+}
+
+impl LineTerminator {
+    pub fn from_name(name: &str, value: &str) -> Result<LineTerminator, String> {
+        match value {
+            "lf" => Ok(LineTerminator::Lf),
+            "crlf" => Ok(LineTerminator::Crlf),
+            e => Err(format!("Option {} expects lf or crlf, got {}", name, e))
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineTerminator::Lf => "\n",
+            LineTerminator::Crlf => "\r\n"
+        }
+    }
+}
+
+/// A message body without the newline most clients end it with, the terminator is added when it is
+/// written so a line never ends in two.
+fn without_newline(message: &str) -> &str {
+    message.strip_suffix('\n').map_or(message, |message| message.strip_suffix('\r').unwrap_or(message))
+}
+
+/// `--max-line-bytes`, the message cut to at most that many bytes without splitting a character,
+/// followed by `…` when anything was cut. The marker comes on top of the limit.
+fn clip<'a>(config: &Config, message: &'a str) -> Cow<'a, str> {
+    match config.max_line_bytes {
+        Some(max) if message.len() > max => Cow::Owned(format!("{}\u{2026}", &message[..message.floor_char_boundary(max)])),
+        _ => Cow::Borrowed(message)
+    }
+}
+
+/// `--wrap-width`, `body` cut into pieces of at most `width` characters, never within a character.
+fn wrap(body: &str, width: usize) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut rest = body;
+
+    while let Some((end, _)) = rest.char_indices().nth(width) {
+        pieces.push(&rest[..end]);
+        rest = &rest[end..];
+    }
+
+    pieces.push(rest);
+    pieces
+}
+
+/// `--time-source`, which timestamp messages are written with and filed under.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimeSource {
+    /// The timestamp the client put in the message.
+    Message,
+    /// The time the server read the message, for clients whose clock or encoding can't be trusted.
+    Receive,
+    /// The message timestamp, followed by the receive time as a `received` field.
+    Both
+}
+
+impl TimeSource {
+    pub fn from_name(name: &str, value: &str) -> Result<TimeSource, String> {
+        match value {
+            "message" => Ok(TimeSource::Message),
+            "receive" => Ok(TimeSource::Receive),
+            "both" => Ok(TimeSource::Both),
+            e => Err(format!("Option {} expects message, receive or both, got {}", name, e))
+        }
+    }
+
+    /// The timestamp a message is written with, and filed under with `--date-dir`.
+    pub fn pick(self, sent: NaiveDateTime, received: NaiveDateTime) -> NaiveDateTime {
+        match self {
+            TimeSource::Receive => received,
+            TimeSource::Message | TimeSource::Both => sent
+        }
+    }
+}
+
+/// `--timestamp-mode`, how the `timestamp` of a text message is read.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimestampMode {
+    /// Seconds since the Unix epoch.
+    Absolute,
+    /// Milliseconds since the client connected, for clients that send a monotonic clock.
+    ///
+    /// The offset is added to the time the server read the client's Connection message, or to the
+    /// time it connected for text sent before one. That assumes the client's clock starts when it
+    /// connects, and puts every message later by however long the Connection message took to
+    /// arrive. A client that announces itself again starts over from its new Connection message.
+    Relative
+}
+
+impl TimestampMode {
+    pub fn from_name(name: &str, value: &str) -> Result<TimestampMode, String> {
+        match value {
+            "absolute" => Ok(TimestampMode::Absolute),
+            "relative" => Ok(TimestampMode::Relative),
+            e => Err(format!("Option {} expects absolute or relative, got {}", name, e))
+        }
+    }
+
+    /// The time `raw` stands for in a message of a client that connected at `connected`, if it
+    /// stands for one at all. An absolute timestamp past what a date can hold has none.
+    pub fn decode(self, raw: u64, connected: NaiveDateTime) -> Option<NaiveDateTime> {
+        match self {
+            TimestampMode::Absolute => i64::try_from(raw).ok().and_then(|secs| NaiveDateTime::from_timestamp_opt(secs, 0)),
+            TimestampMode::Relative => {
+                let offset = chrono::Duration::milliseconds(cmp::min(raw, i64::MAX as u64) as i64);
+                Some(connected.checked_add(offset).unwrap_or(connected))
+            }
+        }
+    }
+}
+
+/// Receive times are the server's own clock, so down to the millisecond unlike message timestamps.
+const RECEIVED_FORMAT: &str = "%F %T%.3f";
+
+/// `--time-precision`, the digits of the second written timestamps have.
+///
+/// Without it message timestamps are written with as many as they carry, which for version 1
+/// clients is none, and receive times down to the millisecond.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimePrecision {
+    Secs, Millis, Micros, Nanos
+}
+
+impl TimePrecision {
+    pub fn from_name(name: &str, value: &str) -> Result<TimePrecision, String> {
+        match value {
+            "secs" => Ok(TimePrecision::Secs),
+            "millis" => Ok(TimePrecision::Millis),
+            "micros" => Ok(TimePrecision::Micros),
+            "nanos" => Ok(TimePrecision::Nanos),
+            e => Err(format!("Option {} expects secs, millis, micros or nanos, got {}", name, e))
+        }
+    }
+
+    fn format(self) -> &'static str {
+        match self {
+            TimePrecision::Secs => "%F %T",
+            TimePrecision::Millis => "%F %T%.3f",
+            TimePrecision::Micros => "%F %T%.6f",
+            TimePrecision::Nanos => "%F %T%.9f"
+        }
+    }
+}
+
+/// A message timestamp as every layout, the tail and the summaries write it.
+pub fn format_timestamp(config: &Config, timestamp: &NaiveDateTime) -> String {
+    timestamp.format(config.time_precision.map_or("%F %T%.f", TimePrecision::format)).to_string()
+}
+
+/// A receive time as every layout writes it.
+fn format_received(config: &Config, received: &NaiveDateTime) -> String {
+    received.format(config.time_precision.map_or(RECEIVED_FORMAT, TimePrecision::format)).to_string()
+}
+
+/// A part of a written message, as named by `--line-format` placeholders and `--drop-fields`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Field {
+    Timestamp, Received, Severity, Module, Channel, Message, Pid, Build, Sequence, Truncated
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Field> {
+        match name {
+            "timestamp" => Some(Field::Timestamp),
+            "received" => Some(Field::Received),
+            "severity" => Some(Field::Severity),
+            "module" => Some(Field::Module),
+            "channel" => Some(Field::Channel),
+            "message" => Some(Field::Message),
+            "pid" => Some(Field::Pid),
+            "build" => Some(Field::Build),
+            "sequence" => Some(Field::Sequence),
+            "truncated" => Some(Field::Truncated),
+            _ => None
+        }
+    }
+}
+
+/// Parses the comma separated list of `--drop-fields`. The message itself can't be dropped.
+pub fn parse_drop_fields(name: &str, value: &str) -> Result<Vec<Field>, String> {
+    value.split(',').map(|field| match Field::from_name(field.trim()) {
+        Some(Field::Message) => Err(format!("Option {} can't drop the message itself", name)),
+        Some(field) => Ok(field),
+        None => Err(format!("Option {} expects timestamp, received, severity, module, channel, pid, build, sequence or truncated, got {}", name, field))
+    }).collect()
+}
+
+#[derive(Debug)]
+enum Piece {
+    Literal(String), Field(Field), Labels
+}
+
+/// The layout of a text line given by `--line-format`, like
+/// `"{timestamp} {severity} [{channel}] > {message}"`.
+///
+/// `{pid}`, `{build}`, `{sequence}` and `{truncated}` (`[truncated]` when a limit cut the message
+/// short) are available as well. `{build}` is what `--version-regex` found in the executable path
+/// of the client and empty when it found nothing, `{sequence}` is empty without
+/// `--sequence-numbers`. `{labels}` is where the `--label`s go, which are left out of lines
+/// without it. Literal braces are written `{{` and `}}`. A newline is added after every line.
+#[derive(Debug)]
+pub struct LineFormat {
+    pieces: Vec<Piece>
+}
+
+impl LineFormat {
+    pub fn parse(template: &str) -> Result<LineFormat, String> {
+        let mut pieces = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                },
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                },
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest.find('}').ok_or_else(|| format!("has an unclosed {{ in {}", template))?;
+
+                    let piece = match Field::from_name(&rest[..end]) {
+                        Some(field) => Piece::Field(field),
+                        None if &rest[..end] == "labels" => Piece::Labels,
+                        None => return Err(format!("has an unknown placeholder {{{}}}", &rest[..end]))
+                    };
+
+                    if !literal.is_empty() {
+                        pieces.push(Piece::Literal(mem::take(&mut literal)));
+                    }
+
+                    pieces.push(piece);
+                    chars = rest[end + 1..].chars();
+                },
+                '}' => return Err(format!("has an unmatched }} in {}", template)),
+                c => literal.push(c)
+            }
+        }
+
+        if !literal.is_empty() {
+            pieces.push(Piece::Literal(literal));
+        }
+
+        Ok(LineFormat { pieces })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn render(&self, config: &Config, pid: u64, build: Option<&str>, sequence: Option<u64>, timestamp: &NaiveDateTime, received: Option<&NaiveDateTime>, message: &TextMessage) -> String {
+        let mut line = String::new();
+
+        for piece in &self.pieces {
+            match *piece {
+                Piece::Literal(ref literal) => line.push_str(literal),
+                Piece::Field(Field::Timestamp) => line.push_str(&format_timestamp(config, timestamp)),
+                Piece::Field(Field::Received) => line.push_str(&received.map(|received| format_received(config, received)).unwrap_or_default()),
+                Piece::Field(Field::Severity) => line.push_str(&config.severity_style.label(message.severity)),
+                Piece::Field(Field::Module) => line.push_str(&message.module),
+                Piece::Field(Field::Channel) => line.push_str(&message.channel),
+                Piece::Field(Field::Message) => line.push_str(&clip(config, without_newline(&message.message))),
+                Piece::Field(Field::Pid) => line.push_str(&pid.to_string()),
+                Piece::Field(Field::Build) => line.push_str(build.unwrap_or_default()),
+                Piece::Field(Field::Sequence) => line.push_str(&sequence.map(|sequence| sequence.to_string()).unwrap_or_default()),
+                Piece::Field(Field::Truncated) => line.push_str(if message.truncated { "[truncated]" } else { "" }),
+                Piece::Labels => line.push_str(label_pairs(config).trim_start())
+            }
+        }
+
+        line.push_str(config.line_terminator.as_str());
+        line
+    }
+}
+
+/// A message as one line of `--format json`, or as an indented object with `--json-pretty`,
+/// without the keys of the `--drop-fields` and with a key for each `--label` after the others. In
+/// a `text` envelope with `--json-envelope`.
+pub fn json_line(config: &Config, pid: u64, build: Option<&str>, sequence: Option<u64>, timestamp: &NaiveDateTime, received: Option<&NaiveDateTime>, message: &TextMessage) -> io::Result<String> {
+    let kept = |field| !config.drop_fields.contains(&field);
+
+    let line = JsonLine {
+        sequence: sequence.filter(|_| kept(Field::Sequence)),
+        pid: Some(pid).filter(|_| kept(Field::Pid)),
+        build: build.filter(|_| kept(Field::Build)),
+        timestamp: Some(timestamp).filter(|_| kept(Field::Timestamp)).map(|timestamp| format_timestamp(config, timestamp)),
+        received: received.filter(|_| kept(Field::Received)).map(|received| format_received(config, received)),
+        severity: Some(message.severity).filter(|_| kept(Field::Severity)).map(|severity| config.severity_style.label(severity)),
+        module: Some(&message.module[..]).filter(|_| kept(Field::Module)),
+        channel: Some(&message.channel[..]).filter(|_| kept(Field::Channel)),
+        message: clip(config, &message.message),
+        truncated: Some(message.truncated).filter(|_| kept(Field::Truncated)),
+        labels: Labels(&config.labels)
+    };
+
+    json_record(config, "text", &line)
+}
+
+/// How a client's session ended, for `--close-records`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SessionClose {
+    /// The last pid the client announced, 0 if it never did.
+    pub pid: u64,
+    /// The id of the session with `--session-ids`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session: Option<u64>,
+    pub duration_ms: u64,
+    /// The text messages written, after filtering and sampling, and the malformed packets skipped.
+    pub messages: u64,
+    pub malformed: u64,
+    /// `disconnected` when the client closed the connection or its stream ended, `shutdown` when
+    /// the server disconnected it to shut down, or otherwise the error that ended it.
+    pub reason: String
+}
+
+/// Somewhere the messages of one client are written to.
+///
+/// Every client gets its own set of sinks, `text` is only called after a Connection has set the
+/// pid, or with pid 0 for text sent before one.
+pub trait Sink {
+    /// `build` is what `--version-regex` found in the executable path, taken before
+    /// `--anonymize-connection` replaced it.
+    fn connection(&mut self, message: &ConnectionMessage, build: Option<&str>) -> io::Result<()>;
+
+    /// `sequence` is the `--sequence-numbers` counter of the message, `timestamp` the one
+    /// `--time-source` picked and `received` always the time the server read the message.
+    fn text(&mut self, pid: u64, sequence: Option<u64>, timestamp: &NaiveDateTime, received: &NaiveDateTime, message: &TextMessage) -> io::Result<()>;
+
+    /// Writes out what the sink holds back, on SIGHUP or a `reload` on the control socket. Most
+    /// sinks write every message as it comes and have nothing to do.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called once with `--close-records` when the client disconnects, before `finish`. Only the
+    /// sinks that record sessions do anything with it, the database, the producer, the exporter
+    /// and the JSON files with `--json-envelope`.
+    fn close_session(&mut self, _: &SessionClose) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called once when the client disconnects, before any of its files are archived.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Whether the sink takes text messages in `--batch-size` batches through `write_batch` rather
+    /// than one at a time through `text`. The files and the alerts want every message as it comes.
+    fn batches(&self) -> bool {
+        false
+    }
+
+    /// Writes the messages of a batch in the order they arrived, by default one at a time.
+    fn write_batch(&mut self, batch: &[Batched]) -> io::Result<()> {
+        for entry in batch {
+            self.text(entry.pid, entry.sequence, &entry.timestamp, &entry.received, &entry.message)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A text message held for the sinks that take batches, with what `Sink::text` would be given.
+#[derive(Clone, Debug)]
+pub struct Batched {
+    pub pid: u64,
+    pub sequence: Option<u64>,
+    pub timestamp: NaiveDateTime,
+    pub received: NaiveDateTime,
+    pub message: TextMessage
+}
+
+/// The text messages of one client on their way to the sinks that take batches.
+///
+/// A batch is written once it holds `--batch-size` messages, once its first message has waited
+/// `--batch-time` when the next one arrives, and before a Connection message, a flush and the
+/// disconnect. Time is only looked at as messages arrive, so a client that stops sending keeps
+/// its last few messages held back until it sends again, a reload or it disconnects.
+pub struct Batch {
+    size: usize,
+    time: Duration,
+    pending: Vec<Batched>,
+    started: Option<Instant>
+}
+
+impl Batch {
+    pub fn new(size: usize, time: Duration) -> Batch {
+        Batch { size, time, pending: Vec::new(), started: None }
+    }
+
+    /// Passes `entry` on to the sinks that take messages one at a time and holds it for the others,
+    /// writing the batch when it is due at `now`.
+    pub fn text<'a>(&mut self, sinks: &mut [Box<dyn Sink + 'a>], entry: &Batched, now: Instant) -> io::Result<()> {
+        let mut batching = false;
+
+        for sink in sinks.iter_mut() {
+            if sink.batches() {
+                batching = true;
+            } else {
+                sink.text(entry.pid, entry.sequence, &entry.timestamp, &entry.received, &entry.message)?;
+            }
+        }
+
+        if !batching {
+            return Ok(());
+        }
+
+        let started = *self.started.get_or_insert(now);
+        self.pending.push(entry.clone());
+
+        if self.pending.len() >= self.size || now.saturating_duration_since(started) >= self.time {
+            self.write(sinks)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes what is held to the sinks that take batches, leaving the batch empty even when one
+    /// of them fails.
+    pub fn write<'a>(&mut self, sinks: &mut [Box<dyn Sink + 'a>]) -> io::Result<()> {
+        self.started = None;
+
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        let pending = mem::take(&mut self.pending);
+
+        for sink in sinks.iter_mut().filter(|sink| sink.batches()) {
+            sink.write_batch(&pending)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The names `--buffer` and `--route` know the sinks by. `files` covers the `--merge-all` file, the archive of
+/// `--format bincode` and the files of each `--also-format` too.
+pub const SINK_NAMES: [&str; 9] = ["files", "mirror", "notify", "summary", "sqlite", "parquet", "kafka", "otlp", "plugin"];
+
+/// How `--buffer` holds messages back from a sink before handing them over and flushing it.
+///
+/// Without `--buffer` every sink is `None`, which is how they have always been written: the files
+/// flush every line unless `--idle-flush` is given, the alerts and the mirror write as they go, and
+/// the database, the Parquet file, the producer, the exporter and the plugin get their messages in
+/// `--batch-size` batches and flush on their own terms. A sink given any other strategy is
+/// buffered by it instead of by `--batch-size` and `--batch-time`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Buffer {
+    /// Hands each message over as it comes, leaving flushing to the sink.
+    None,
+    /// Hands each message over as it comes and flushes the sink after every one of them, so each
+    /// line is on disk, committed or sent before the next message is read.
+    Line,
+    /// Holds messages until their bodies add up to this many bytes.
+    Bytes(usize),
+    /// Holds messages until the first of them has waited this long when another one arrives.
+    /// Like `--batch-time`, time is only looked at as messages arrive.
+    Time(Duration),
+    /// Holds messages until there are this many.
+    Count(usize)
+}
+
+/// Parses a `--buffer sink=strategy`, the strategy being `none`, `line`, `bytes:N` with the
+/// suffixes of `parse_byte_size`, `time:Nms` or `time:Ns`, or `count:N`.
+pub fn parse_buffer(name: &str, value: &str) -> Result<(String, Buffer), String> {
+    let expected = || format!("Option {} expects sink=none, line, bytes:N, time:Nms or count:N, got {}", name, value);
+    let (sink, strategy) = value.split_once('=').ok_or_else(expected)?;
+
+    if !SINK_NAMES.contains(&sink) {
+        return Err(format!("Option {} expects one of the sinks {}, got {}", name, SINK_NAMES.join(", "), sink));
+    }
+
+    let at_least_one = |number: u64| if number == 0 { Err(format!("Option {} expects a buffer of at least 1, got {}", name, value)) } else { Ok(number) };
+
+    let buffer = match strategy.split_once(':') {
+        None if strategy == "none" => Buffer::None,
+        None if strategy == "line" => Buffer::Line,
+        Some(("bytes", bytes)) => Buffer::Bytes(at_least_one(parse_byte_size(name, bytes)?)? as usize),
+        Some(("count", count)) => Buffer::Count(at_least_one(count.parse().map_err(|_| expected())?)? as usize),
+        Some(("time", time)) => {
+            let (number, unit) = match time.strip_suffix("ms") {
+                Some(number) => (number, 1),
+                None => (time.strip_suffix('s').ok_or_else(expected)?, 1000)
+            };
+
+            Buffer::Time(Duration::from_millis(at_least_one(number.parse().map_err(|_| expected())?)? * unit))
+        },
+        _ => return Err(expected())
+    };
+
+    Ok((sink.to_string(), buffer))
+}
+
+/// A sink behind a `--buffer` strategy other than `Buffer::None`.
+///
+/// What is held is handed over as a batch, through `write_batch`, and the sink flushed right after.
+/// It is also handed over before a Connection message, a flush, the close of the session and the
+/// disconnect, so nothing held is lost or comes out after what followed it.
+pub struct Buffered<'a> {
+    sink: Box<dyn Sink + 'a>,
+    buffer: Buffer,
+    held: Vec<Batched>,
+    bytes: usize,
+    started: Option<Instant>
+}
+
+impl<'a> Buffered<'a> {
+    pub fn new(sink: Box<dyn Sink + 'a>, buffer: Buffer) -> Buffered<'a> {
+        Buffered { sink, buffer, held: Vec::new(), bytes: 0, started: None }
+    }
+
+    /// Hands what is held over to the sink and flushes it, leaving nothing held even when the sink
+    /// fails.
+    fn release(&mut self) -> io::Result<()> {
+        self.started = None;
+        self.bytes = 0;
+
+        if self.held.is_empty() {
+            return Ok(());
+        }
+
+        let held = mem::take(&mut self.held);
+        self.sink.write_batch(&held)?;
+        self.sink.flush()
+    }
+}
+
+impl<'a> Sink for Buffered<'a> {
+    fn connection(&mut self, message: &ConnectionMessage, build: Option<&str>) -> io::Result<()> {
+        self.release()?;
+        self.sink.connection(message, build)
+    }
+
+    fn text(&mut self, pid: u64, sequence: Option<u64>, timestamp: &NaiveDateTime, received: &NaiveDateTime, message: &TextMessage) -> io::Result<()> {
+        let now = Instant::now();
+        let started = *self.started.get_or_insert(now);
+
+        self.bytes += message.message.len();
+        self.held.push(Batched { pid, sequence, timestamp: *timestamp, received: *received, message: message.clone() });
+
+        let due = match self.buffer {
+            Buffer::None | Buffer::Line => true,
+            Buffer::Bytes(bytes) => self.bytes >= bytes,
+            Buffer::Time(time) => now.saturating_duration_since(started) >= time,
+            Buffer::Count(count) => self.held.len() >= count
+        };
+
+        if due {
+            self.release()?;
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.release()?;
+        self.sink.flush()
+    }
+
+    fn close_session(&mut self, close: &SessionClose) -> io::Result<()> {
+        self.release()?;
+        self.sink.close_session(close)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.release()?;
+        self.sink.finish()
+    }
+}
+
+/// Calls `f` on each of `sinks` in turn, in the order `Context::sinks` gives them, so a flush or
+/// finish always reaches the files before the mirror, the summary and the database. Every sink
+/// gets its turn even when an earlier one fails, each failure is passed to `failed`.
+pub fn in_order<'a, F, E>(sinks: &mut [Box<dyn Sink + 'a>], mut f: F, mut failed: E)
+    where F: FnMut(&mut dyn Sink) -> io::Result<()>, E: FnMut(io::Error) {
+    for sink in sinks {
+        if let Err(e) = f(&mut **sink) {
+            failed(e);
+        }
+    }
+}
+
+/// Keeps the build of each pid for its lines, forgetting it when the pid comes back without one.
+pub fn remember_build(builds: &mut HashMap<u64, String>, pid: u64, build: Option<&str>) {
+    match build {
+        Some(build) => builds.insert(pid, build.to_string()),
+        None => builds.remove(&pid)
+    };
+}
+
+/// The `{pid}/{module}.txt` files, or their `.jsonl` counterparts with `--format json`, where
+/// `--json-envelope` adds a `{pid}/session.jsonl` of `connection` and `close` envelopes. A module
+/// called `session` shares that file, its lines being `text` envelopes. A sink
+/// of each `--also-format` writes the same paths with the extension of its format, with a cache
+/// of its own so `--max-open-files` is a limit on each format.
+///
+/// `--shards` spreads the messages of each module over that many files, `{module}.0.txt` up to
+/// `{module}.{n-1}.txt`, for a module too busy for one file or one reader. Each file keeps the
+/// order its messages arrived in, but nothing says how the lines of different shards interleave,
+/// a reader that needs the order of the whole module has to merge them by timestamp. Round-robin
+/// starts over at shard 0 with every run and keeps the shards even, `--shard-by hash` keeps every
+/// message of a channel in one file and the order within it, at the cost of shards as uneven as
+/// the channels are.
+pub struct FileSink<'a> {
+    config: &'a Config,
+    format: Format,
+    files: HandleCache,
+    builds: HashMap<u64, String>,
+    /// The `executable_path` each pid announced, for `--group-by exe`.
+    executables: HashMap<u64, String>,
+    /// The shard the next message of each module goes to with `--shard-by round-robin`.
+    next_shard: HashMap<(u64, Arc<str>), u64>
+}
+
+impl<'a> FileSink<'a> {
+    pub fn new(config: &'a Config, format: Format) -> FileSink<'a> {
+        let mut files = HandleCache::modules(config);
+
+        // Only text has comment lines, --heartbeat-lines is refused with any other --format.
+        files.heartbeat = files.heartbeat && format == Format::Text;
+
+        FileSink { config, format, files, builds: HashMap::new(), executables: HashMap::new(), next_shard: HashMap::new() }
+    }
+}
+
+impl<'a> FileSink<'a> {
+    /// Whether Connection messages and closes are written, to `session.jsonl`.
+    fn writes_sessions(&self) -> bool {
+        self.format == Format::Json && self.config.json_envelope
+    }
+
+    /// Appends `line` to the `session.jsonl` of `pid`, next to its module files, under the date it
+    /// is now with `--date-dir` as the events have no timestamp of their own.
+    fn write_session(&mut self, pid: u64, line: &str) -> io::Result<()> {
+        let config = self.config;
+        let mut path = output_root(config);
+
+        if config.date_dir {
+            path.push(UTC::now().format("%Y-%m-%d").to_string());
+        }
+
+        path.push(group_directory(config, pid, self.executables.get(&pid).map(|path| &path[..])));
+        path.push("session.jsonl");
+
+        self.files.write(config, path, &[], line.as_bytes())
+    }
+
+    /// The `--shards` file `message` goes to, `None` without the option.
+    fn shard(&mut self, pid: u64, message: &TextMessage) -> Option<u64> {
+        let shards = self.config.shards?;
+
+        Some(match self.config.shard_by {
+            ShardBy::RoundRobin => {
+                let next = self.next_shard.entry((pid, message.module.clone())).or_insert(0);
+                let shard = *next;
+                *next = (shard + 1) % shards;
+                shard
+            },
+            ShardBy::Hash => {
+                let mut hasher = DefaultHasher::new();
+                message.channel.hash(&mut hasher);
+                hasher.finish() % shards
+            }
+        })
+    }
+}
+
+impl<'a> Sink for FileSink<'a> {
+    fn connection(&mut self, message: &ConnectionMessage, build: Option<&str>) -> io::Result<()> {
+        remember_build(&mut self.builds, message.pid, build);
+        self.executables.insert(message.pid, message.executable_path.clone());
+
+        if self.writes_sessions() {
+            let line = json_connection(self.config, message, build)?;
+            return self.write_session(message.pid, &line);
+        }
+
+        // The directory of a date only comes with its first message, as does every directory with
+        // --no-create-empty, so a client whose messages are all filtered out leaves nothing behind.
+        if self.config.date_dir || self.config.no_create_empty {
+            return Ok(());
+        }
+
+        create_dir(self.config, &output_root(self.config).join(group_directory(self.config, message.pid, Some(&message.executable_path))))
+    }
+
+    fn text(&mut self, pid: u64, sequence: Option<u64>, timestamp: &NaiveDateTime, received: &NaiveDateTime, message: &TextMessage) -> io::Result<()> {
+        let config = self.config;
+        let shard = self.shard(pid, message);
+        let executable = self.executables.get(&pid).map(|path| &path[..]);
+        let path = log_path(config, self.format, pid, executable, timestamp, &message.module, &message.channel, shard);
+
+        let line = format_line_as(config, self.format, pid, self.builds.get(&pid).map(|build| &build[..]), sequence, timestamp, received, message)?;
+        self.files.write(config, path, &[], line.as_bytes())
+    }
+
+    fn close_session(&mut self, close: &SessionClose) -> io::Result<()> {
+        if !self.writes_sessions() {
+            return Ok(());
+        }
+
+        let line = json_close(self.config, close)?;
+        self.write_session(close.pid, &line)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.files.flush()
+    }
+}
+
+/// A message as `FileSink` writes it, in the layout of `--format` and `--line-format`.
+///
+/// The default text layout leaves out each part given to `--drop-fields` along with the space
+/// after it, the pid and module were never part of it. `--line-format` can't be combined with
+/// `--drop-fields`, its placeholders already say what is written. Every layout ends in exactly
+/// one `--line-terminator`, whether or not the message ended in a newline of its own, after the
+/// `--label`s as ` key=value` pairs in the default layout.
+///
+/// `--align` pads the severity to the longest label of a known severity and the channel to the
+/// width given inside its brackets, so the `>` of every line in a file is in the same column. A
+/// longer channel is written whole and pushes its line out, so do sequence numbers once they have
+/// more digits.
+pub fn format_line(config: &Config, pid: u64, build: Option<&str>, sequence: Option<u64>, timestamp: &NaiveDateTime, received: &NaiveDateTime, message: &TextMessage) -> io::Result<String> {
+    format_line_as(config, config.format, pid, build, sequence, timestamp, received, message)
+}
+
+/// Like `format_line`, in `format` rather than in `--format`, for the files of `--also-format`.
+/// `--line-format` is a layout of text lines, JSON lines are written in their own layout.
+#[allow(clippy::too_many_arguments)]
+pub fn format_line_as(config: &Config, format: Format, pid: u64, build: Option<&str>, sequence: Option<u64>, timestamp: &NaiveDateTime, received: &NaiveDateTime, message: &TextMessage) -> io::Result<String> {
+    let dropped = &config.drop_fields;
+    let received = Some(received).filter(|_| config.time_source == TimeSource::Both);
+
+    if format == Format::Json {
+        let line = json_line(config, pid, build, sequence, timestamp, received, message)?;
+        return Ok(line.trim_end_matches('\n').to_string() + config.line_terminator.as_str());
+    }
+
+    if let Some(ref format) = config.line_format {
+        return Ok(format.render(config, pid, build, sequence, timestamp, received, message));
+    }
+
+    let mut line = String::new();
+
+    if let Some(sequence) = sequence.filter(|_| !dropped.contains(&Field::Sequence)) {
+        line.push_str(&format!("{} ", sequence));
+    }
+
+    if !dropped.contains(&Field::Timestamp) {
+        line.push_str(&format!("{} ", format_timestamp(config, timestamp)));
+    }
+
+    if let Some(received) = received.filter(|_| !dropped.contains(&Field::Received)) {
+        line.push_str(&format!("(received {}) ", format_received(config, received)));
+    }
+
+    let (severity_width, channel_width) = match config.align {
+        Some(width) => (config.severity_style.width(), width),
+        None => (0, 0)
+    };
+
+    if !dropped.contains(&Field::Severity) {
+        line.push_str(&format!("{:<width$} ", config.severity_style.label(message.severity), width = severity_width));
+    }
+
+    if !dropped.contains(&Field::Channel) {
+        line.push_str(&format!("[{:<width$}] ", message.channel, width = channel_width));
+    }
+
+    let body = clip(config, without_newline(&message.message));
+
+    match config.wrap_width {
+        Some(width) => {
+            let indent = line.chars().count();
+            let mut pieces = wrap(&body, width).into_iter();
+
+            line.push_str("> ");
+            line.push_str(pieces.next().unwrap_or_default());
+
+            for piece in pieces {
+                line.push_str(&format!("{}{:indent$}| {}", config.line_terminator.as_str(), "", piece, indent = indent));
+            }
+        },
+        None => {
+            line.push_str("> ");
+            line.push_str(&body);
+        }
+    }
+
+    if message.truncated && !dropped.contains(&Field::Truncated) {
+        line.push_str(" [truncated]");
+    }
+
+    line.push_str(&label_pairs(config));
+    line.push_str(config.line_terminator.as_str());
+    Ok(line)
+}
+
+/// `--errors-to-stderr`, a copy of every message of at least `min` severity on top of the files.
+///
+/// Lines are formatted like the files, except that the default layout gets a `{pid}/{module}: `
+/// prefix since the messages of every client end up in the one stream. Each line is written in
+/// one go to an unbuffered writer, so lines of concurrent clients never mix. With `colored` they
+/// are in the `--severity-color` of their severity.
+pub struct MirrorSink<'a, W> {
+    config: &'a Config,
+    min: Severity,
+    colored: bool,
+    writer: W,
+    builds: HashMap<u64, String>
+}
+
+impl<'a, W: Write> MirrorSink<'a, W> {
+    pub fn new(config: &'a Config, min: Severity, colored: bool, writer: W) -> MirrorSink<'a, W> {
+        MirrorSink { config, min, colored, writer, builds: HashMap::new() }
+    }
+}
+
+impl<'a, W: Write> Sink for MirrorSink<'a, W> {
+    fn connection(&mut self, message: &ConnectionMessage, build: Option<&str>) -> io::Result<()> {
+        remember_build(&mut self.builds, message.pid, build);
+        Ok(())
+    }
+
+    fn text(&mut self, pid: u64, _: Option<u64>, timestamp: &NaiveDateTime, received: &NaiveDateTime, message: &TextMessage) -> io::Result<()> {
+        if !at_least(message.severity, self.min) {
+            return Ok(());
+        }
+
+        let mut line = format_line(self.config, pid, self.builds.get(&pid).map(|build| &build[..]), None, timestamp, received, message)?;
+
+        if self.config.format != Format::Json && self.config.line_format.is_none() {
+            line = format!("{}/{}: {}", pid, message.module, line);
+        }
+
+        if self.colored {
+            line = self.config.severity_style.paint(message.severity, &line);
+        }
+
+        self.writer.write_all(line.as_bytes())?;
+        self.writer.flush()
+    }
+}
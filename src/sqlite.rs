@@ -0,0 +1,214 @@
+use std::{io, thread};
+
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use chrono::UTC;
+use chrono::naive::datetime::NaiveDateTime;
+
+use eve_logger::{ConnectionMessage, TextMessage};
+
+use rusqlite::{params, Connection, Statement};
+
+use sink::{Batched, SessionClose, Sink};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS connections (
+        received INTEGER NOT NULL,
+        pid INTEGER NOT NULL,
+        version INTEGER NOT NULL,
+        machine_name TEXT NOT NULL,
+        executable_path TEXT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS messages (
+        timestamp INTEGER NOT NULL,
+        pid INTEGER NOT NULL,
+        severity TEXT NOT NULL,
+        module TEXT NOT NULL,
+        channel TEXT NOT NULL,
+        message TEXT NOT NULL,
+        truncated INTEGER NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS sessions (
+        closed INTEGER NOT NULL,
+        pid INTEGER NOT NULL,
+        duration_ms INTEGER NOT NULL,
+        messages INTEGER NOT NULL,
+        malformed INTEGER NOT NULL,
+        reason TEXT NOT NULL
+    );
+
+    CREATE INDEX IF NOT EXISTS messages_pid_timestamp ON messages (pid, timestamp);
+";
+
+/// Rows written in one transaction at most, whatever else is queued waits for the next one. A
+/// `--batch-size` batch counts as one row.
+const BATCH: usize = 512;
+
+enum Row {
+    Connection { received: i64, pid: u64, version: u32, machine_name: String, executable_path: String },
+    Text { timestamp: i64, pid: u64, severity: String, module: String, channel: String, message: String, truncated: bool },
+    /// The `Text` rows of a `--batch-size` batch, queued at once.
+    Texts(Vec<Row>),
+    /// A `--close-records` close, stamped with when it was queued.
+    Close { closed: i64, close: SessionClose },
+    Flush(Sender<()>)
+}
+
+/// The `--sqlite` database, written by a thread of its own so a slow disk never holds up the
+/// clients or their text files.
+///
+/// Rows are queued as they arrive and inserted in batches, each batch being whatever was queued by
+/// the time the previous one was committed.
+pub struct Writer {
+    sender: Sender<Row>
+}
+
+impl Writer {
+    /// Opens or creates the database at `path`, creating the tables if they don't exist yet.
+    pub fn open(path: &Path) -> rusqlite::Result<Writer> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(SCHEMA)?;
+
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move|| write_rows(connection, receiver));
+
+        Ok(Writer { sender })
+    }
+
+    /// A sink for one client that queues its messages for this writer.
+    pub fn sink(&self) -> SqliteSink {
+        SqliteSink { sender: self.sender.clone() }
+    }
+
+    /// Waits until everything queued so far has been committed.
+    pub fn flush(&self) {
+        let (done, waiting) = mpsc::channel();
+
+        if self.sender.send(Row::Flush(done)).is_ok() {
+            let _ = waiting.recv();
+        }
+    }
+}
+
+fn write_rows(mut connection: Connection, receiver: Receiver<Row>) {
+    while let Ok(row) = receiver.recv() {
+        let mut batch = vec![row];
+
+        while batch.len() < BATCH {
+            match receiver.try_recv() {
+                Ok(row) => batch.push(row),
+                Err(_) => break
+            }
+        }
+
+        if let Err(e) = insert(&mut connection, &batch) {
+            warn!("Could not write {} rows to SQLite: {}", batch.len(), e);
+        }
+
+        for row in batch {
+            if let Row::Flush(done) = row {
+                let _ = done.send(());
+            }
+        }
+    }
+}
+
+fn insert(connection: &mut Connection, batch: &[Row]) -> rusqlite::Result<()> {
+    let transaction = connection.transaction()?;
+
+    {
+        let mut connections = transaction.prepare_cached("INSERT INTO connections VALUES (?1, ?2, ?3, ?4, ?5)")?;
+        let mut messages = transaction.prepare_cached("INSERT INTO messages VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)")?;
+        let mut sessions = transaction.prepare_cached("INSERT INTO sessions VALUES (?1, ?2, ?3, ?4, ?5, ?6)")?;
+
+        for row in batch {
+            insert_row(&mut connections, &mut messages, &mut sessions, row)?;
+        }
+    }
+
+    transaction.commit()
+}
+
+fn insert_row(connections: &mut Statement<'_>, messages: &mut Statement<'_>, sessions: &mut Statement<'_>, row: &Row) -> rusqlite::Result<()> {
+    match *row {
+        Row::Connection { received, pid, version, ref machine_name, ref executable_path } => {
+            connections.execute(params![received, pid as i64, version, machine_name, executable_path])?;
+        },
+        Row::Text { timestamp, pid, ref severity, ref module, ref channel, ref message, truncated } => {
+            messages.execute(params![timestamp, pid as i64, severity, module, channel, message, truncated])?;
+        },
+        Row::Texts(ref rows) => {
+            for row in rows {
+                insert_row(connections, messages, sessions, row)?;
+            }
+        },
+        Row::Close { closed, ref close } => {
+            sessions.execute(params![closed, close.pid as i64, close.duration_ms as i64, close.messages as i64, close.malformed as i64, close.reason])?;
+        },
+        Row::Flush(_) => ()
+    }
+
+    Ok(())
+}
+
+pub struct SqliteSink {
+    sender: Sender<Row>
+}
+
+impl SqliteSink {
+    fn send(&self, row: Row) -> io::Result<()> {
+        self.sender.send(row).map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "The SQLite writer has stopped"))
+    }
+}
+
+fn text_row(pid: u64, timestamp: &NaiveDateTime, message: &TextMessage) -> Row {
+    Row::Text {
+        timestamp: timestamp.timestamp(),
+        pid,
+        severity: format!("{:?}", message.severity),
+        module: message.module.to_string(),
+        channel: message.channel.to_string(),
+        message: message.message.clone(),
+        truncated: message.truncated
+    }
+}
+
+impl Sink for SqliteSink {
+    fn connection(&mut self, message: &ConnectionMessage, _: Option<&str>) -> io::Result<()> {
+        self.send(Row::Connection {
+            received: UTC::now().timestamp(),
+            pid: message.pid,
+            version: message.version,
+            machine_name: message.machine_name.clone(),
+            executable_path: message.executable_path.clone()
+        })
+    }
+
+    fn text(&mut self, pid: u64, _: Option<u64>, timestamp: &NaiveDateTime, _: &NaiveDateTime, message: &TextMessage) -> io::Result<()> {
+        self.send(text_row(pid, timestamp, message))
+    }
+
+    fn batches(&self) -> bool {
+        true
+    }
+
+    /// Queues the whole batch as one, to be inserted in the same transaction.
+    fn write_batch(&mut self, batch: &[Batched]) -> io::Result<()> {
+        self.send(Row::Texts(batch.iter().map(|entry| text_row(entry.pid, &entry.timestamp, &entry.message)).collect()))
+    }
+
+    fn close_session(&mut self, close: &SessionClose) -> io::Result<()> {
+        self.send(Row::Close { closed: UTC::now().timestamp(), close: close.clone() })
+    }
+
+    /// Waits for the writer to commit what the client sent so far.
+    fn flush(&mut self) -> io::Result<()> {
+        let (done, waiting) = mpsc::channel();
+        self.send(Row::Flush(done))?;
+
+        waiting.recv().map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "The SQLite writer has stopped"))
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Mutex;
+
+use eve_logger::Severity;
+
+use signal::{self, Signal};
+use sink;
+
+/// The names of the severity slots, every unknown severity sharing the last.
+const SEVERITIES: [&str; 5] = ["Info", "Notice", "Warn", "Error", "Unknown"];
+
+/// Counters over the lifetime of the server, dumped to stderr on SIGUSR1.
+///
+/// Everything sits behind one lock, so a dump never shows a message counted in the total but not
+/// yet in its severity or pid. A pid is only counted while a client is connected as it, so a long
+/// running server doesn't keep every pid it has ever seen.
+pub struct Stats {
+    counts: Mutex<Counts>
+}
+
+#[derive(Clone)]
+struct Counts {
+    messages: u64,
+    severities: [u64; 5],
+    pids: BTreeMap<u64, Pid>,
+    /// The length of every message body as received, before `--max-line-bytes` cuts lines short.
+    message_bytes: u64,
+    bytes_written: u64,
+    connections: u64,
+    /// `--kafka-brokers` records that didn't fit the queue or that librdkafka gave up on, and
+    /// `--otlp` records the collector didn't take.
+    dropped_records: u64,
+    /// Messages `--sample` dropped.
+    sampled_out: u64,
+    /// Timestamps `--monotonic-timestamps` moved forward.
+    timestamps_adjusted: u64
+}
+
+/// The messages of one pid, and how many sessions are writing as it.
+#[derive(Clone, Default)]
+struct Pid {
+    messages: u64,
+    sessions: usize
+}
+
+pub static STATS: Stats = Stats {
+    counts: Mutex::new(Counts {
+        messages: 0,
+        severities: [0; 5],
+        pids: BTreeMap::new(),
+        message_bytes: 0,
+        bytes_written: 0,
+        connections: 0,
+        dropped_records: 0,
+        sampled_out: 0,
+        timestamps_adjusted: 0
+    })
+};
+
+impl Stats {
+    pub fn message(&self, pid: u64, severity: Severity, bytes: usize) {
+        let mut counts = self.counts.lock().unwrap();
+
+        counts.messages += 1;
+        counts.message_bytes += bytes as u64;
+        counts.severities[sink::style_index(severity)] += 1;
+        counts.pids.entry(pid).or_default().messages += 1;
+    }
+
+    /// A session started writing as `pid`.
+    pub fn joined(&self, pid: u64) {
+        self.counts.lock().unwrap().pids.entry(pid).or_default().sessions += 1;
+    }
+
+    /// A session writing as `pid` ended, which forgets the pid once it was the last.
+    pub fn left(&self, pid: u64) {
+        let mut counts = self.counts.lock().unwrap();
+
+        if let Some(entry) = counts.pids.get_mut(&pid) {
+            entry.sessions = entry.sessions.saturating_sub(1);
+
+            if entry.sessions == 0 {
+                counts.pids.remove(&pid);
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub fn counted(&self, pid: u64) -> Option<u64> {
+        self.counts.lock().unwrap().pids.get(&pid).map(|entry| entry.messages)
+    }
+
+    pub fn wrote(&self, bytes: usize) {
+        self.counts.lock().unwrap().bytes_written += bytes as u64;
+    }
+
+    #[cfg(any(feature = "kafka", feature = "otlp"))]
+    pub fn dropped_record(&self) {
+        self.counts.lock().unwrap().dropped_records += 1;
+    }
+
+    pub fn sampled_out(&self) {
+        self.counts.lock().unwrap().sampled_out += 1;
+    }
+
+    pub fn timestamp_adjusted(&self) {
+        self.counts.lock().unwrap().timestamps_adjusted += 1;
+    }
+
+    pub fn connected(&self) {
+        self.counts.lock().unwrap().connections += 1;
+    }
+
+    pub fn disconnected(&self) {
+        self.counts.lock().unwrap().connections -= 1;
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot(self.counts.lock().unwrap().clone())
+    }
+}
+
+pub struct Snapshot(Counts);
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let counts = &self.0;
+
+        writeln!(f, "Stats: messages={} message_bytes={} bytes_written={} active_connections={}", counts.messages, counts.message_bytes, counts.bytes_written, counts.connections)?;
+
+        if counts.dropped_records > 0 {
+            writeln!(f, "  dropped records: {}", counts.dropped_records)?;
+        }
+
+        if counts.sampled_out > 0 {
+            writeln!(f, "  sampled out: {}", counts.sampled_out)?;
+        }
+
+        if counts.timestamps_adjusted > 0 {
+            writeln!(f, "  timestamps adjusted: {}", counts.timestamps_adjusted)?;
+        }
+
+        for (severity, count) in SEVERITIES.iter().zip(&counts.severities).filter(|(_, count)| **count > 0) {
+            writeln!(f, "  severity {}: {}", severity, count)?;
+        }
+
+        for (pid, entry) in &counts.pids {
+            writeln!(f, "  pid {}: {}", pid, entry.messages)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Dumps a snapshot of `STATS` to stderr every time the process receives SIGUSR1, as in
+/// `kill -USR1 <pid>`.
+pub fn dump_on_sigusr1() {
+    if let Err(e) = signal::on_signal(Signal::Usr1, || eprint!("{} {}", ::local_time(), STATS.snapshot())) {
+        warn!("Could not set up the SIGUSR1 stats dump: {}", e);
+    }
+}
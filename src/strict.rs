@@ -0,0 +1,71 @@
+use std::{cmp, io, process};
+
+use std::io::Read;
+
+use eve_logger::{hexdump, Message, MessagesWithBytes, ParseError};
+
+use {control, Context};
+
+/// The bytes before a packet that failed that are dumped along with it.
+const BEFORE: usize = 32;
+
+/// The bytes of a packet that failed that are dumped at most, a Large message can be far longer.
+const DUMPED: usize = 512;
+
+/// `--strict-parse`, the messages of a client that end the server on the first one that fails to
+/// parse, malformed packets and a stream cut short partway through a packet included.
+///
+/// The error is printed with the pid of the client and a hex dump of the packet, with the bytes of
+/// the message before it for context. Files and background sinks get written out like on a
+/// shutdown, then the server exits with 1 without waiting for its other clients.
+pub struct Strict<'a, R> {
+    context: &'a Context,
+    messages: MessagesWithBytes<R>,
+    /// The last bytes of the message before.
+    before: Vec<u8>,
+    pid: u64
+}
+
+impl<'a, R: Read> Strict<'a, R> {
+    pub fn new(context: &'a Context, messages: MessagesWithBytes<R>) -> Strict<'a, R> {
+        Strict { context, messages, before: Vec::new(), pid: 0 }
+    }
+
+    fn fail(&self, error: &io::Error) -> ! {
+        let offset = ParseError::of(error).map_or(self.messages.position(), |parse_error| parse_error.offset);
+        let rejected = self.messages.rejected();
+
+        let mut bytes = self.before.clone();
+        bytes.extend_from_slice(&rejected[..cmp::min(rejected.len(), DUMPED)]);
+
+        warn!("Option --strict-parse stops at the first parse error, from pid={}: {}", self.pid, error);
+        eprint!("{}", hexdump(offset - self.before.len() as u64, &bytes));
+
+        if rejected.len() > DUMPED {
+            eprintln!("({} more bytes of the packet left out)", rejected.len() - DUMPED);
+        }
+
+        control::flush_idle_files();
+        self.context.close();
+        process::exit(1)
+    }
+}
+
+impl<'a, R: Read> Iterator for Strict<'a, R> {
+    type Item = io::Result<Message>;
+
+    fn next(&mut self) -> Option<io::Result<Message>> {
+        match self.messages.next() {
+            Some(Ok((message, bytes))) => {
+                if let Message::Connection(ref connection) = message {
+                    self.pid = connection.pid;
+                }
+
+                self.before = bytes[bytes.len().saturating_sub(BEFORE)..].to_vec();
+                Some(Ok(message))
+            },
+            Some(Err(e)) => self.fail(&e),
+            None => None
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use std::{io, mem};
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use chrono::naive::datetime::NaiveDateTime;
+
+use eve_logger::{ConnectionMessage, TextMessage};
+
+use sink::{self, Sink};
+
+use {create_dir, open_log, output_root, Config};
+
+/// The messages of one module by channel and severity.
+///
+/// Only the first `max` channels are counted on their own, as a client can make up any number of
+/// them. Messages of later ones go into `(other)`.
+pub struct ChannelCounts {
+    max: usize,
+    channels: BTreeMap<String, BTreeMap<String, u64>>,
+    other: BTreeMap<String, u64>
+}
+
+impl ChannelCounts {
+    pub fn new(max: usize) -> ChannelCounts {
+        ChannelCounts { max, channels: BTreeMap::new(), other: BTreeMap::new() }
+    }
+
+    pub fn record(&mut self, channel: &str, severity: &str) {
+        let severities = if self.channels.contains_key(channel) || self.channels.len() < self.max {
+            self.channels.entry(channel.to_string()).or_default()
+        } else {
+            &mut self.other
+        };
+
+        *severities.entry(severity.to_string()).or_insert(0) += 1;
+    }
+
+    /// A line per channel, as in `    channel combat: Error=2 Info=5`, with `(other)` last.
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        let other = Some(("(other)", &self.other)).filter(|_| !self.other.is_empty());
+
+        for (channel, severities) in self.channels.iter().map(|(channel, severities)| (&channel[..], severities)).chain(other) {
+            let counts: Vec<String> = severities.iter().map(|(severity, count)| format!("{}={}", severity, count)).collect();
+            report.push_str(&format!("    channel {}: {}\n", channel, counts.join(" ")));
+        }
+
+        report
+    }
+}
+
+/// What one pid logged over a period.
+struct Summary {
+    first: NaiveDateTime,
+    last: NaiveDateTime,
+    messages: u64,
+    severities: BTreeMap<String, u64>,
+    modules: BTreeMap<String, (u64, ChannelCounts)>
+}
+
+impl Summary {
+    fn new(timestamp: &NaiveDateTime) -> Summary {
+        Summary { first: *timestamp, last: *timestamp, messages: 0, severities: BTreeMap::new(), modules: BTreeMap::new() }
+    }
+
+    fn record(&mut self, config: &Config, timestamp: &NaiveDateTime, severity: String, message: &TextMessage) {
+        self.first = self.first.min(*timestamp);
+        self.last = self.last.max(*timestamp);
+        self.messages += 1;
+
+        let module = self.modules.entry(message.module.to_string()).or_insert_with(|| (0, ChannelCounts::new(config.summary_channels)));
+        module.0 += 1;
+        module.1.record(&message.channel, &severity);
+
+        *self.severities.entry(severity).or_insert(0) += 1;
+    }
+}
+
+/// `--summary`, appends a report of message counts by severity and module to `{pid}/summary.txt`
+/// when the client disconnects. Each module is broken down by channel and severity, for up to
+/// `--summary-channels` channels.
+///
+/// With `--date-dir` every day gets its own report in `{YYYY-MM-DD}/{pid}/summary.txt`, written as
+/// soon as the pid logs its first message of the next day. A module called `summary` shares the
+/// file in text output.
+pub struct SummarySink<'a> {
+    config: &'a Config,
+    /// The period being counted for each pid, keyed by its date with `--date-dir`.
+    summaries: BTreeMap<u64, (Option<String>, Summary)>
+}
+
+impl<'a> SummarySink<'a> {
+    pub fn new(config: &'a Config) -> SummarySink<'a> {
+        SummarySink { config, summaries: BTreeMap::new() }
+    }
+
+    fn write(&self, pid: u64, date: &Option<String>, summary: &Summary) -> io::Result<()> {
+        let mut directory = output_root(self.config);
+
+        if let Some(ref date) = *date {
+            directory.push(date);
+        }
+
+        directory.push(pid.to_string());
+        create_dir(self.config, &directory)?;
+
+        let mut report = format!("{} summary for pid {}\n", ::local_time(), pid);
+        report.push_str(&format!("  first: {}\n", sink::format_timestamp(self.config, &summary.first)));
+        report.push_str(&format!("  last: {}\n", sink::format_timestamp(self.config, &summary.last)));
+        report.push_str(&format!("  messages: {}\n", summary.messages));
+
+        for (severity, count) in &summary.severities {
+            report.push_str(&format!("  severity {}: {}\n", severity, count));
+        }
+
+        for (module, (count, channels)) in &summary.modules {
+            report.push_str(&format!("  module {}: {}\n", module, count));
+            report.push_str(&channels.report());
+        }
+
+        open_log(self.config, &directory.join("summary.txt"))?.write_all(report.as_bytes())
+    }
+}
+
+impl<'a> Sink for SummarySink<'a> {
+    fn connection(&mut self, _: &ConnectionMessage, _: Option<&str>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn text(&mut self, pid: u64, _: Option<u64>, timestamp: &NaiveDateTime, _: &NaiveDateTime, message: &TextMessage) -> io::Result<()> {
+        let date = if self.config.date_dir { Some(timestamp.format("%Y-%m-%d").to_string()) } else { None };
+
+        let finished = match self.summaries.get(&pid) {
+            Some((current, _)) if *current != date => self.summaries.remove(&pid),
+            _ => None
+        };
+
+        if let Some((date, summary)) = finished {
+            self.write(pid, &date, &summary)?;
+        }
+
+        let period = self.summaries.entry(pid).or_insert_with(|| (date, Summary::new(timestamp)));
+        period.1.record(self.config, timestamp, self.config.severity_style.label(message.severity), message);
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let mut result = Ok(());
+
+        for (pid, (date, summary)) in mem::take(&mut self.summaries) {
+            if let Err(e) = self.write(pid, &date, &summary) {
+                result = Err(e);
+            }
+        }
+
+        result
+    }
+}
@@ -0,0 +1,132 @@
+use std::{fs, io};
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+
+use regex::Regex;
+
+use tar;
+
+use {output_root, Config};
+
+/// How many connected clients announced each pid, so `--archive-on-disconnect` waits for the last
+/// of them when a client reconnects before the previous connection has gone.
+///
+/// Archiving happens with the lock held, so a client announcing the pid again meanwhile waits
+/// until its directory has been packed and removed, rather than writing into it halfway through.
+pub struct Pids {
+    active: Mutex<HashMap<u64, usize>>
+}
+
+impl Pids {
+    pub fn new() -> Pids {
+        Pids { active: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn enter(&self, pid: u64) {
+        *self.active.lock().unwrap().entry(pid).or_insert(0) += 1;
+    }
+
+    /// Archives the directories of `pid` if this was the last client with it.
+    pub fn leave(&self, config: &Config, pid: u64) -> io::Result<()> {
+        let mut active = self.active.lock().unwrap();
+
+        match active.get_mut(&pid) {
+            Some(count) if *count > 1 => {
+                *count -= 1;
+                Ok(())
+            },
+            _ => {
+                active.remove(&pid);
+                archive_pid(config, pid)
+            }
+        }
+    }
+}
+
+/// Packs every directory of `pid` into a `.tar.gz` next to it, removing the directory afterwards
+/// with `--archive-remove`.
+///
+/// That is `{pid}/` into `{pid}.tar.gz`, or with `--date-dir` each `{YYYY-MM-DD}/{pid}/` into
+/// `{YYYY-MM-DD}/{pid}.tar.gz`. An archive that already exists from an earlier disconnect is never
+/// replaced, the new one is numbered `{pid}.1.tar.gz`, `{pid}.2.tar.gz` and so on.
+fn archive_pid(config: &Config, pid: u64) -> io::Result<()> {
+    for directory in pid_directories(config, pid)? {
+        let archive = archive_path(&directory, pid);
+        let mut temporary = archive.clone().into_os_string();
+        temporary.push(".tmp");
+        let temporary = PathBuf::from(temporary);
+
+        let result = write_archive(&directory, pid, &temporary).and_then(|_| fs::rename(&temporary, &archive));
+
+        if let Err(e) = result {
+            let _ = fs::remove_file(&temporary);
+            return Err(e);
+        }
+
+        info!("Archived {} to {}", directory.display(), archive.display());
+
+        if config.archive_remove {
+            fs::remove_dir_all(&directory)?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn pid_directories(config: &Config, pid: u64) -> io::Result<Vec<PathBuf>> {
+    let root = output_root(config);
+
+    if !config.date_dir {
+        let directory = root.join(pid.to_string());
+        return Ok(if directory.is_dir() { vec![directory] } else { Vec::new() });
+    }
+
+    // Nothing has been written under --prefix-hostname yet.
+    if config.hostname.is_some() && !root.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let date = Regex::new(r"^\d{4}-\d{2}-\d{2}$").unwrap();
+    let mut directories = Vec::new();
+
+    for entry in fs::read_dir(if config.hostname.is_some() { &root } else { Path::new(".") })? {
+        let entry = entry?;
+        let directory = entry.path().join(pid.to_string());
+
+        if entry.file_name().to_str().is_some_and(|name| date.is_match(name)) && directory.is_dir() {
+            directories.push(directory);
+        }
+    }
+
+    directories.sort();
+    Ok(directories)
+}
+
+fn archive_path(directory: &Path, pid: u64) -> PathBuf {
+    let parent = directory.parent().unwrap_or(Path::new(""));
+    let mut version = 0;
+
+    loop {
+        let name = if version == 0 { format!("{}.tar.gz", pid) } else { format!("{}.{}.tar.gz", pid, version) };
+        let path = parent.join(name);
+
+        if fs::symlink_metadata(&path).is_err() {
+            return path;
+        }
+
+        version += 1;
+    }
+}
+
+fn write_archive(directory: &Path, pid: u64, path: &Path) -> io::Result<()> {
+    let file = fs::OpenOptions::new().write(true).create_new(true).open(path)?;
+    let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+
+    builder.append_dir_all(pid.to_string(), directory)?;
+    builder.into_inner()?.finish()?.sync_all()
+}
@@ -0,0 +1,49 @@
+use std::{fs, io};
+
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::Arc;
+
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use rustls::crypto::ring;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::pki_types::pem::PemObject;
+
+/// A client connected with `--tls-cert`, read through `Messages` like a plain `TcpStream`.
+pub type TlsStream = StreamOwned<ServerConnection, TcpStream>;
+
+/// Loads the PEM certificate chain at `cert` and the private key at `key` for `--tls-cert` and
+/// `--tls-key`.
+pub fn server_config(cert: &Path, key: &Path) -> Result<Arc<ServerConfig>, String> {
+    let cert_pem = fs::read(cert).map_err(|e| format!("Could not read {}: {}", cert.display(), e))?;
+    let key_pem = fs::read(key).map_err(|e| format!("Could not read {}: {}", key.display(), e))?;
+
+    server_config_from_pem(&cert_pem, &key_pem)
+}
+
+/// Like `server_config`, from PEM already in memory.
+pub fn server_config_from_pem(cert_pem: &[u8], key_pem: &[u8]) -> Result<Arc<ServerConfig>, String> {
+    let chain = CertificateDer::pem_slice_iter(cert_pem).collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Could not parse the certificate: {}", e))?;
+
+    if chain.is_empty() {
+        return Err("The certificate file holds no certificates".to_string());
+    }
+
+    let key = PrivateKeyDer::from_pem_slice(key_pem).map_err(|e| format!("Could not parse the private key: {}", e))?;
+
+    let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| builder.with_no_client_auth().with_single_cert(chain, key))
+        .map_err(|e| format!("Could not set up TLS: {}", e))?;
+
+    Ok(Arc::new(config))
+}
+
+/// Wraps an accepted client in a server connection. The handshake happens on the first read, so
+/// a client that never completes it fails like any other broken connection.
+pub fn accept(config: &Arc<ServerConfig>, stream: TcpStream) -> io::Result<TlsStream> {
+    let connection = ServerConnection::new(config.clone()).map_err(io::Error::other)?;
+
+    Ok(StreamOwned::new(connection, stream))
+}
@@ -0,0 +1,67 @@
+use std::{io, thread};
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Messages received per (pid, module) from the clients connected right now, for `--top`.
+pub struct Counters {
+    counts: Mutex<HashMap<(u64, String), u64>>
+}
+
+impl Counters {
+    pub fn new() -> Counters {
+        Counters { counts: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn record(&self, pid: u64, module: &str) {
+        *self.counts.lock().unwrap().entry((pid, module.to_string())).or_insert(0) += 1;
+    }
+
+    /// Forgets a pid whose client has gone away.
+    pub fn remove(&self, pid: u64) {
+        self.counts.lock().unwrap().retain(|&(counted, _), _| counted != pid);
+    }
+
+    fn snapshot(&self) -> HashMap<(u64, String), u64> {
+        self.counts.lock().unwrap().clone()
+    }
+}
+
+/// Redraws the table once a second, busiest modules first.
+///
+/// The counters are only locked for as long as it takes to copy them, so drawing never holds up a
+/// client.
+pub fn run(counters: &Counters) {
+    let mut previous = HashMap::new();
+    let mut last = Instant::now();
+
+    loop {
+        thread::sleep(Duration::from_secs(1));
+
+        let counts = counters.snapshot();
+        let elapsed = last.elapsed().as_secs_f64();
+        last = Instant::now();
+
+        let mut rows: Vec<(f64, &(u64, String), u64)> = counts.iter().map(|(key, &total)| {
+            let rate = (total - previous.get(key).copied().unwrap_or(0).min(total)) as f64 / elapsed;
+            (rate, key, total)
+        }).collect();
+
+        rows.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+
+        let mut screen = String::from("\x1b[2J\x1b[H");
+        screen.push_str(&format!("{:>10}  {:<32}  {:>10}  {:>12}\n", "PID", "MODULE", "MSG/S", "TOTAL"));
+
+        for (rate, &(pid, ref module), total) in rows {
+            screen.push_str(&format!("{:>10}  {:<32}  {:>10.1}  {:>12}\n", pid, module, rate, total));
+        }
+
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        let _ = stdout.write_all(screen.as_bytes()).and_then(|_| stdout.flush());
+
+        previous = counts;
+    }
+}
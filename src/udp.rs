@@ -0,0 +1,90 @@
+use std::{io, thread};
+
+use std::collections::HashMap;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use eve_logger::{is_malformed, parse_packet, Limits, Message};
+
+/// The largest payload a UDP datagram can carry.
+const MAX_DATAGRAM: usize = 65507;
+
+/// A source address that has sent datagrams, with the session its messages go to.
+struct Source {
+    messages: Sender<io::Result<Message>>,
+    /// The protocol version its last Connection message announced.
+    version: u32
+}
+
+/// Serves `--udp`, reading every datagram on `socket` as one or more whole packets. Runs for the
+/// lifetime of the server.
+///
+/// Each source address is a client of its own, `session` is called on a new thread with the
+/// messages of the first datagram from an address and of every later one. There is no disconnect,
+/// a source stays until its session ends, after which its next datagram starts a new one.
+///
+/// A datagram can't continue in the next, so a Large message has to arrive in one datagram along
+/// with all of its continuations, which allows for about 190 packets. One that doesn't is dropped
+/// with a warning, as is the rest of a datagram with a malformed packet, which also counts towards
+/// `--max-errors` for its source.
+pub fn serve<F>(socket: UdpSocket, limits: Limits, session: F) -> io::Result<()>
+    where F: Fn(SocketAddr, Receiver<io::Result<Message>>) + Send + Sync + 'static {
+    let session = Arc::new(session);
+    let mut sources: HashMap<SocketAddr, Source> = HashMap::new();
+    let mut buffer = vec![0; MAX_DATAGRAM];
+
+    loop {
+        let (size, from) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e)
+        };
+
+        let source = sources.entry(from).or_insert_with(|| {
+            let (sender, receiver) = mpsc::channel();
+            let session = session.clone();
+
+            thread::spawn(move|| session(from, receiver));
+
+            Source { messages: sender, version: 1 }
+        });
+
+        if !datagram(source, &buffer[..size], &limits, from) {
+            sources.remove(&from);
+        }
+    }
+}
+
+/// Passes on the packets of one datagram, returning whether the session of `source` is still
+/// taking them.
+fn datagram(source: &mut Source, mut bytes: &[u8], limits: &Limits, from: SocketAddr) -> bool {
+    while !bytes.is_empty() {
+        let message = match parse_packet(bytes, source.version, limits) {
+            Ok((message, used)) => {
+                bytes = &bytes[used..];
+                Ok(message)
+            },
+            Err(e) => {
+                warn!("Dropped {} bytes of a datagram from {}: {}", bytes.len(), from, e);
+                bytes = &[];
+
+                if !is_malformed(&e) {
+                    continue;
+                }
+
+                Err(e)
+            }
+        };
+
+        if let Ok(Message::Connection(ref connection)) = message {
+            source.version = connection.version;
+        }
+
+        if source.messages.send(message).is_err() {
+            return false;
+        }
+    }
+
+    true
+}
@@ -0,0 +1,151 @@
+use std::{fmt, fs, io};
+
+use std::io::Read;
+
+use eve_logger::{is_malformed, read_packet_with_chunks, CountingReader, Limits, Message};
+
+use {option_value, parse_encoding, parse_endianness, parse_header_layout};
+
+/// What `verify` found in a capture.
+#[derive(Debug, Default, PartialEq)]
+pub struct Report {
+    pub bytes: u64,
+    pub connection: u64,
+    pub simple: u64,
+    pub large: u64,
+    /// The Continuation and ContinuationEnd packets of the Large messages.
+    pub continuation: u64,
+    /// The offset of every packet that was skipped as malformed, after which reading got back in
+    /// sync, with the reason.
+    pub malformed: Vec<(u64, String)>,
+    /// The offset of the packet the capture ended partway through and why, `None` when it ended on
+    /// a packet boundary.
+    pub unclean_end: Option<(u64, String)>
+}
+
+impl Report {
+    pub fn is_clean(&self) -> bool {
+        self.malformed.is_empty() && self.unclean_end.is_none()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} bytes", self.bytes)?;
+        writeln!(f, "  packets: connection={} simple={} large={} continuation={}", self.connection, self.simple, self.large, self.continuation)?;
+        writeln!(f, "  malformed packets skipped: {}", self.malformed.len())?;
+
+        for (offset, reason) in &self.malformed {
+            writeln!(f, "    at byte {}: {}", offset, reason)?;
+        }
+
+        match self.unclean_end {
+            Some((offset, ref reason)) => writeln!(f, "  ended partway through the packet at byte {}: {}", offset, reason),
+            None => writeln!(f, "  ended cleanly")
+        }
+    }
+}
+
+/// Parses all of `reader` the way a client connection is read, counting its packets.
+///
+/// Reading goes on after a malformed packet like it does for a client, and stops at the first other
+/// error, which leaves the stream out of sync. A capture that ends halfway through a Large message,
+/// with continuations still to come, ends uncleanly at the offset of its Large packet.
+pub fn verify<R: Read>(reader: R, limits: &Limits) -> Report {
+    let mut reader = CountingReader::new(reader);
+    let mut report = Report::default();
+    let mut version = 1;
+
+    loop {
+        let start = reader.position();
+        let mut chunks = 0;
+        let result = read_packet_with_chunks(&mut reader, version, limits, &mut |chunk| chunks = chunk.index as u64 + 1);
+
+        match result {
+            Ok(Message::Connection(connection)) => {
+                version = connection.version;
+                report.connection += 1;
+            },
+            Ok(Message::Text(_)) if chunks == 1 => report.simple += 1,
+            Ok(Message::Text(_)) => {
+                report.large += 1;
+                report.continuation += chunks - 1;
+            },
+            Err(ref e) if is_malformed(e) => report.malformed.push((start, e.to_string())),
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof && reader.position() == start => break,
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof && chunks > 0 => {
+                report.unclean_end = Some((start, format!("the capture ends after chunk {} of a Large message, before its ContinuationEnd", chunks)));
+                break;
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                report.unclean_end = Some((start, format!("the capture ends {} bytes into the packet", reader.position() - start)));
+                break;
+            },
+            Err(e) => {
+                report.unclean_end = Some((start, e.to_string()));
+                break;
+            }
+        }
+    }
+
+    report.bytes = reader.position();
+    report
+}
+
+/// `eve-logger verify [--encoding <utf8|utf16|auto>] [--endianness <little|big>]
+/// [--header-layout <type-u32|type-u64|swapped>] <file>...`,
+/// checks captures as given to `--replay` without writing anything.
+///
+/// Prints a report for each file and returns 1 if any of them had malformed packets or ended
+/// partway through a packet, 0 otherwise. A file that can't be read is reported and counts as
+/// failed.
+pub fn run<I: Iterator<Item = String>>(args: I) -> i32 {
+    match verify_files(args) {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(e) => {
+            eprintln!("Verify failed: {}", e);
+            2
+        }
+    }
+}
+
+/// Whether every file was clean.
+fn verify_files<I: Iterator<Item = String>>(args: I) -> Result<bool, String> {
+    let mut args = args;
+    let mut limits = Limits::default();
+    let mut paths = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_ref() {
+            "--encoding" => limits.encoding = parse_encoding(&arg, &option_value(&arg, &mut args)?)?,
+            "--endianness" => limits.endianness = parse_endianness(&arg, &option_value(&arg, &mut args)?)?,
+            "--header-layout" => limits.header_layout = parse_header_layout(&arg, &option_value(&arg, &mut args)?)?,
+            e if e.starts_with("--") => return Err(format!("Unknown option {}", e)),
+            _ => paths.push(arg)
+        }
+    }
+
+    if paths.is_empty() {
+        return Err("expected a capture file".to_string());
+    }
+
+    let mut clean = true;
+
+    for path in &paths {
+        match fs::File::open(path) {
+            Ok(file) => {
+                let report = verify(io::BufReader::new(file), &limits);
+                clean &= report.is_clean();
+
+                print!("{}: {}", path, report);
+            },
+            Err(e) => {
+                eprintln!("Could not open {}: {}", path, e);
+                clean = false;
+            }
+        }
+    }
+
+    Ok(clean)
+}
@@ -0,0 +1,84 @@
+extern crate eve_logger;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use eve_logger::{write_packet, Encoding, Limits, Message, Messages, Severity, TextMessage};
+
+/// Counts the allocations and reallocations of the current thread, so tests running in parallel
+/// don't add to each other's.
+struct Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|allocations| allocations.set(allocations.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, pointer: *mut u8, layout: Layout) {
+        System.dealloc(pointer, layout)
+    }
+
+    unsafe fn realloc(&self, pointer: *mut u8, layout: Layout, size: usize) -> *mut u8 {
+        ALLOCATIONS.with(|allocations| allocations.set(allocations.get() + 1));
+        System.realloc(pointer, layout, size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// Parses 1000 messages with a 120 unit body of one and two unit characters, as UTF-8 and as
+/// UTF-16LE. The module and channel are shared by every message of a stream, so after the first
+/// each message may only allocate its payload and its body. UTF-8 keeps the bytes of the body as
+/// its string, UTF-16LE allocates the string once more, at its final size.
+#[test]
+fn decode() -> Result<(), String> {
+    const MESSAGES: usize = 1000;
+
+    let body = "b\u{f6}dy \u{1f680} ".repeat(15);
+
+    for (encoding, each) in [(Encoding::Utf8, 2), (Encoding::Utf16Le, 3)] {
+        let message = Message::Text(TextMessage {
+            timestamp: 1500000000,
+            severity: Severity::Warn,
+            module: "module".into(),
+            channel: "channel".into(),
+            message: if encoding == Encoding::Utf8 { body.clone() } else { String::new() },
+            truncated: false
+        });
+
+        let mut packet = Vec::new();
+        write_packet(&mut packet, &message).map_err(|e| e.to_string())?;
+
+        if encoding == Encoding::Utf16Le {
+            // Header, timestamp, severity, module and channel come before the body.
+            let units: Vec<u8> = body.encode_utf16().flat_map(|unit| unit.to_le_bytes()).collect();
+            packet[84..84 + units.len()].copy_from_slice(&units);
+        }
+
+        let bytes = packet.repeat(MESSAGES);
+        let mut messages = Messages::with_limits(&bytes[..], Limits { encoding, ..Limits::default() });
+
+        // The first message decodes the module and channel the others share.
+        messages.next().ok_or("no first message")?.map_err(|e| e.to_string())?;
+
+        let before = ALLOCATIONS.with(Cell::get);
+        let mut parsed = Vec::with_capacity(MESSAGES);
+        parsed.extend(messages);
+        let allocations = ALLOCATIONS.with(Cell::get) - before;
+
+        let mismatched = parsed.iter().filter(|parsed| !matches!(parsed, Ok(Message::Text(ref text)) if text.message == body)).count();
+
+        // A few more for the buffers of the stream growing.
+        if mismatched > 0 || allocations > each * (MESSAGES - 1) + 8 {
+            return Err(format!("{:?}: {} allocations for {} messages, {} of them not decoded", encoding, allocations, MESSAGES - 1, mismatched));
+        }
+    }
+
+    Ok(())
+}
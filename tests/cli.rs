@@ -0,0 +1,1784 @@
+extern crate eve_logger;
+extern crate flate2;
+extern crate tar;
+
+use eve_logger::{write_packet, ConnectionMessage, Message, Messages, Severity, TextMessage};
+use eve_logger::{HEADER_SIZE, TEXT_PAYLOAD_SIZE};
+
+/// Runs `dump-struct-layout`, which has to print the layout of the wire format: the fields of both
+/// payloads at their offsets, with the padding after the version and at the end of each payload.
+#[test]
+fn dump_struct_layout() -> Result<(), String> {
+    use std::{env, process};
+
+    let output = process::Command::new(env!("CARGO_BIN_EXE_eve-logger"))
+        .arg("dump-struct-layout")
+        .output()
+        .map_err(|e| e.to_string())?;
+
+    let expected = "RawConnectionMessage: 312 bytes\n\
+                    \x20 version          offset   0 size   4\n\
+                    \x20 pid              offset   8 size   8\n\
+                    \x20 machine_name     offset  16 size  32\n\
+                    \x20 executable_path  offset  48 size 260\n\
+                    RawTextMessage: 336 bytes\n\
+                    \x20 timestamp        offset   0 size   8\n\
+                    \x20 severity         offset   8 size   4\n\
+                    \x20 module           offset  12 size  32\n\
+                    \x20 channel          offset  44 size  32\n\
+                    \x20 message          offset  76 size 256\n";
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    if !output.status.success() || stdout != expected {
+        return Err(format!("exited with {} printing {:?}", output.status, stdout));
+    }
+
+    Ok(())
+}
+
+/// Runs this binary with `--print-config` and a `--config` file giving `min-severity error`,
+/// `format json` and `idle-flush 5`, with `--min-severity warn` and an `--anon-salt` on the command
+/// line. The dump has to show the command line winning and the rest taken from the file, and leave
+/// out the salt.
+#[test]
+fn print_config() -> Result<(), String> {
+    use std::{env, fs, process};
+
+    let path = env::temp_dir().join(format!("eve-logger-test-{}-print.conf", process::id()));
+
+    let result = (|| {
+        fs::write(&path, "min-severity error\nformat json\nidle-flush 5\n").map_err(|e| e.to_string())?;
+
+        let output = process::Command::new(env!("CARGO_BIN_EXE_eve-logger"))
+            .args(["--config", &path.display().to_string(), "--min-severity", "warn", "--anon-salt", "s3cr3t-salt", "--print-config"])
+            .output()
+            .map_err(|e| e.to_string())?;
+
+        let printed = String::from_utf8_lossy(&output.stdout);
+        let compact: String = printed.split_whitespace().collect();
+
+        for expected in ["min_severity:Some(Warn,)", "format:Json,", "idle_flush:Some(5s,)", "anon_salt:(redacted),"] {
+            if !output.status.success() || !compact.contains(expected) || printed.contains("s3cr3t") {
+                return Err(format!("exited with {} and printed {:?}, expected it to contain {}", output.status, printed, expected));
+            }
+        }
+
+        Ok(())
+    })();
+
+    let _ = fs::remove_file(&path);
+    result
+}
+
+/// Replays a message to each of two modules with `--mirror-dir`, then again to append to the same
+/// files. Every file has to turn up under the mirror with the same content.
+#[test]
+fn mirror_dir() -> Result<(), String> {
+    use std::{env, fs, process};
+
+    let directory = env::temp_dir().join(format!("eve-logger-test-{}-mirror", process::id()));
+
+    let result = (|| {
+        let mut capture = Vec::new();
+        write_packet(&mut capture, &connection()).map_err(|e| e.to_string())?;
+        write_packet(&mut capture, &text("first")).map_err(|e| e.to_string())?;
+
+        if let Message::Text(mut message) = text("second") {
+            message.module = "other".into();
+            write_packet(&mut capture, &Message::Text(message)).map_err(|e| e.to_string())?;
+        }
+
+        fs::create_dir_all(&directory).map_err(|e| e.to_string())?;
+        fs::write(directory.join("capture.bin"), capture).map_err(|e| e.to_string())?;
+
+        for _ in 0..2 {
+            let status = process::Command::new(env!("CARGO_BIN_EXE_eve-logger"))
+                .args(["--replay", "capture.bin", "--mirror-dir", "mirror"])
+                .current_dir(&directory)
+                .output()
+                .map_err(|e| e.to_string())?
+                .status;
+
+            if !status.success() {
+                return Err(format!("exited with {}", status));
+            }
+        }
+
+        for file in ["1234/module.txt", "1234/other.txt"] {
+            let primary = fs::read_to_string(directory.join(file)).map_err(|e| format!("{}: {}", file, e))?;
+            let mirrored = fs::read_to_string(directory.join("mirror").join(file)).map_err(|e| format!("mirror/{}: {}", file, e))?;
+
+            if primary != mirrored || primary.lines().count() != 2 {
+                return Err(format!("{} has {:?}, the mirror {:?}", file, primary, mirrored));
+            }
+        }
+
+        Ok(())
+    })();
+
+    let _ = fs::remove_dir_all(&directory);
+    result
+}
+
+/// Replays a client of pid 1234 where a file named `1234` is in the way of its directory. The
+/// replay has to fail with the pid and the reason, rather than panic, and leave the file alone.
+#[test]
+fn directory_in_the_way() -> Result<(), String> {
+    use std::{env, fs, process};
+
+    let directory = env::temp_dir().join(format!("eve-logger-test-{}-in-the-way", process::id()));
+
+    let result = (|| {
+        let mut capture = Vec::new();
+        write_packet(&mut capture, &connection()).map_err(|e| e.to_string())?;
+        write_packet(&mut capture, &text("body")).map_err(|e| e.to_string())?;
+
+        fs::create_dir_all(&directory).map_err(|e| e.to_string())?;
+        fs::write(directory.join("capture.bin"), capture).map_err(|e| e.to_string())?;
+        fs::write(directory.join("1234"), "in the way").map_err(|e| e.to_string())?;
+
+        let output = process::Command::new(env!("CARGO_BIN_EXE_eve-logger"))
+            .args(["--replay", "capture.bin"])
+            .current_dir(&directory)
+            .output()
+            .map_err(|e| e.to_string())?;
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        if output.status.code() != Some(1) || !stderr.contains("Could not start writing pid 1234: Could not create the directory 1234: ") || stderr.contains("panicked") {
+            return Err(format!("exited with {} saying {:?}", output.status, stderr));
+        }
+
+        if fs::read_to_string(directory.join("1234")).map_err(|e| e.to_string())? != "in the way" {
+            return Err("the file in the way was changed".to_string());
+        }
+
+        Ok(())
+    })();
+
+    let _ = fs::remove_dir_all(&directory);
+    result
+}
+
+/// Replays a capture of pid 1234 with `--pid 42`, which has to reject the connection without
+/// creating its directory, then with `--pid 42 --pid 1234`, which has to write its file.
+#[test]
+fn pid_allowlist() -> Result<(), String> {
+    use std::{env, fs, process};
+
+    let directory = env::temp_dir().join(format!("eve-logger-test-{}-pid", process::id()));
+
+    let result = (|| {
+        let mut capture = Vec::new();
+        write_packet(&mut capture, &connection()).map_err(|e| e.to_string())?;
+        write_packet(&mut capture, &text("body")).map_err(|e| e.to_string())?;
+
+        fs::create_dir_all(&directory).map_err(|e| e.to_string())?;
+        fs::write(directory.join("capture.bin"), capture).map_err(|e| e.to_string())?;
+
+        let replay = |pids: &[&str]| -> Result<process::Output, String> {
+            let mut args = vec!["--replay", "capture.bin"];
+
+            for pid in pids {
+                args.extend(["--pid", pid]);
+            }
+
+            process::Command::new(env!("CARGO_BIN_EXE_eve-logger"))
+                .args(args)
+                .current_dir(&directory)
+                .output()
+                .map_err(|e| e.to_string())
+        };
+
+        let output = replay(&["42"])?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        if output.status.code() != Some(1) || !stderr.contains("Rejecting pid 1234, not one of the --pid given") || directory.join("1234").exists() {
+            return Err(format!("--pid 42 exited with {} saying {:?}", output.status, stderr));
+        }
+
+        let output = replay(&["42", "1234"])?;
+
+        if !output.status.success() || !directory.join("1234/module.txt").exists() {
+            return Err(format!("--pid 42 --pid 1234 exited with {} without writing 1234/module.txt", output.status));
+        }
+
+        Ok(())
+    })();
+
+    let _ = fs::remove_dir_all(&directory);
+    result
+}
+
+/// Replays two messages of a module holding a control byte and one of a plain module with
+/// `--audit-names`. `name-audit.log` has to hold the odd module once, with the hex of its bytes,
+/// and the messages still have to be written.
+#[test]
+fn audit_names() -> Result<(), String> {
+    use std::{env, fs, process};
+
+    let directory = env::temp_dir().join(format!("eve-logger-test-{}-audit-names", process::id()));
+
+    let result = (|| {
+        let odd = |body: &str| match text(body) {
+            Message::Text(message) => Message::Text(TextMessage { module: "m\u{1}d".into(), ..message }),
+            _ => unreachable!()
+        };
+
+        let mut capture = Vec::new();
+
+        for message in &[connection(), odd("first"), odd("second"), text("plain")] {
+            write_packet(&mut capture, message).map_err(|e| e.to_string())?;
+        }
+
+        fs::create_dir_all(&directory).map_err(|e| e.to_string())?;
+        fs::write(directory.join("capture.bin"), capture).map_err(|e| e.to_string())?;
+
+        let status = process::Command::new(env!("CARGO_BIN_EXE_eve-logger"))
+            .args(["--replay", "capture.bin", "--audit-names"])
+            .current_dir(&directory)
+            .stdout(process::Stdio::null())
+            .stderr(process::Stdio::null())
+            .status()
+            .map_err(|e| e.to_string())?;
+
+        if !status.success() {
+            return Err(format!("the replay exited with {}", status));
+        }
+
+        let audit = fs::read_to_string(directory.join("name-audit.log")).map_err(|e| e.to_string())?;
+        let lines: Vec<&str> = audit.lines().collect();
+
+        if lines.len() != 1 || !lines[0].ends_with(" pid=1234 module=\"m\\u{1}d\" bytes=6d 01 64") {
+            return Err(format!("audited {:?}", audit));
+        }
+
+        let written = fs::read_dir(directory.join("1234")).map_err(|e| e.to_string())?.count();
+
+        if written != 2 {
+            return Err(format!("wrote {} files for the two modules", written));
+        }
+
+        Ok(())
+    })();
+
+    let _ = fs::remove_dir_all(&directory);
+    result
+}
+
+/// Replays two modules that only differ at their end with `--max-name-len 32`. Both have to be cut
+/// to fit, keeping their extension, and still be written to files of their own.
+#[test]
+fn max_name_len() -> Result<(), String> {
+    use std::{env, fs, process};
+
+    let directory = env::temp_dir().join(format!("eve-logger-test-{}-max-name-len", process::id()));
+
+    let result = (|| {
+        fs::create_dir_all(&directory).map_err(|e| e.to_string())?;
+
+        let mut capture = Vec::new();
+        write_packet(&mut capture, &connection()).map_err(|e| e.to_string())?;
+
+        for (module, body) in [("a-rather-long-module-name-one", "first"), ("a-rather-long-module-name-two", "second")].iter() {
+            let message = match text(body) {
+                Message::Text(message) => Message::Text(TextMessage { module: (*module).into(), ..message }),
+                _ => unreachable!()
+            };
+
+            write_packet(&mut capture, &message).map_err(|e| e.to_string())?;
+        }
+
+        fs::write(directory.join("capture.bin"), capture).map_err(|e| e.to_string())?;
+
+        let status = process::Command::new(env!("CARGO_BIN_EXE_eve-logger"))
+            .arg("--replay")
+            .arg(directory.join("capture.bin"))
+            .args(["--max-name-len", "32"])
+            .current_dir(&directory)
+            .stdout(process::Stdio::null())
+            .stderr(process::Stdio::null())
+            .status()
+            .map_err(|e| e.to_string())?;
+
+        if !status.success() {
+            return Err(format!("the replay exited with {}", status));
+        }
+
+        let mut files = Vec::new();
+
+        for entry in fs::read_dir(directory.join("1234")).map_err(|e| e.to_string())? {
+            let entry = entry.map_err(|e| e.to_string())?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+
+            if name.len() > 32 || !name.ends_with(".txt") {
+                return Err(format!("wrote {:?}", name));
+            }
+
+            files.push(fs::read_to_string(entry.path()).map_err(|e| e.to_string())?);
+        }
+
+        files.sort();
+
+        if files.len() != 2 || !files[0].trim_end().ends_with("> first") || !files[1].trim_end().ends_with("> second") {
+            return Err(format!("wrote files {:?}", files));
+        }
+
+        Ok(())
+    })();
+
+    let _ = fs::remove_dir_all(&directory);
+    result
+}
+
+/// Replays six messages on two channels with `--shards 3`. Round-robin has to put two in each
+/// shard in the order they were sent, `--shard-by hash` all three of a channel in one shard.
+#[test]
+fn shards() -> Result<(), String> {
+    use std::{env, fs, process};
+
+    let directory = env::temp_dir().join(format!("eve-logger-test-{}-shards", process::id()));
+
+    let result = (|| {
+        let mut capture = Vec::new();
+        write_packet(&mut capture, &connection()).map_err(|e| e.to_string())?;
+
+        for (i, body) in ["a", "b", "c", "d", "e", "f"].iter().enumerate() {
+            let channel = if i % 2 == 0 { "even" } else { "odd" };
+
+            let message = match text(body) {
+                Message::Text(message) => Message::Text(TextMessage { channel: channel.into(), ..message }),
+                _ => unreachable!()
+            };
+
+            write_packet(&mut capture, &message).map_err(|e| e.to_string())?;
+        }
+
+        fs::create_dir_all(&directory).map_err(|e| e.to_string())?;
+        fs::write(directory.join("capture.bin"), capture).map_err(|e| e.to_string())?;
+
+        let shards = |shard_by: &str| -> Result<Vec<String>, String> {
+            let output = directory.join(shard_by);
+            fs::create_dir_all(&output).map_err(|e| e.to_string())?;
+
+            let status = process::Command::new(env!("CARGO_BIN_EXE_eve-logger"))
+                .arg("--replay")
+                .arg(directory.join("capture.bin"))
+                .args(["--shards", "3", "--shard-by", shard_by])
+                .current_dir(&output)
+                .stdout(process::Stdio::null())
+                .stderr(process::Stdio::null())
+                .status()
+                .map_err(|e| e.to_string())?;
+
+            if !status.success() {
+                return Err(format!("the replay with --shard-by {} exited with {}", shard_by, status));
+            }
+
+            if output.join("1234/module.txt").exists() {
+                return Err(format!("--shard-by {} still wrote module.txt", shard_by));
+            }
+
+            // The bodies of each shard, a missing shard being an empty one.
+            Ok((0..3).map(|shard| {
+                let file = fs::read_to_string(output.join(format!("1234/module.{}.txt", shard))).unwrap_or_default();
+                file.lines().map(|line| line.rsplit("> ").next().unwrap_or("")).collect()
+            }).collect())
+        };
+
+        let round_robin = shards("round-robin")?;
+
+        if round_robin != ["ad", "be", "cf"] {
+            return Err(format!("round-robin wrote shards {:?}", round_robin));
+        }
+
+        let hash = shards("hash")?;
+        let mut bodies: Vec<&str> = hash.iter().filter(|shard| !shard.is_empty()).map(|shard| &shard[..]).collect();
+        bodies.sort();
+
+        if bodies != ["ace", "bdf"] && bodies != ["abcdef"] {
+            return Err(format!("hash wrote shards {:?}", hash));
+        }
+
+        Ok(())
+    })();
+
+    let _ = fs::remove_dir_all(&directory);
+    result
+}
+
+/// Replays a capture of one message with `--also-format json`. The message has to be written both
+/// as a text line to `1234/module.txt` and as a JSON line to `1234/module.jsonl`.
+#[test]
+fn also_format() -> Result<(), String> {
+    use std::{env, fs, process};
+
+    let directory = env::temp_dir().join(format!("eve-logger-test-{}-also-format", process::id()));
+
+    let result = (|| {
+        let mut capture = Vec::new();
+        write_packet(&mut capture, &connection()).map_err(|e| e.to_string())?;
+        write_packet(&mut capture, &text("body")).map_err(|e| e.to_string())?;
+
+        fs::create_dir_all(&directory).map_err(|e| e.to_string())?;
+        fs::write(directory.join("capture.bin"), capture).map_err(|e| e.to_string())?;
+
+        let status = process::Command::new(env!("CARGO_BIN_EXE_eve-logger"))
+            .args(["--replay", "capture.bin", "--also-format", "json"])
+            .current_dir(&directory)
+            .stdout(process::Stdio::null())
+            .stderr(process::Stdio::null())
+            .status()
+            .map_err(|e| e.to_string())?;
+
+        if !status.success() {
+            return Err(format!("the replay exited with {}", status));
+        }
+
+        let text = fs::read_to_string(directory.join("1234/module.txt")).map_err(|e| e.to_string())?;
+        let json = fs::read_to_string(directory.join("1234/module.jsonl")).map_err(|e| e.to_string())?;
+
+        if text != "2017-07-14 02:40:00 Warn [channel] > body\n" || !json.contains("\"message\":\"body\"") || json.lines().count() != 1 {
+            return Err(format!("wrote {:?} and {:?}", text, json));
+        }
+
+        Ok(())
+    })();
+
+    let _ = fs::remove_dir_all(&directory);
+    result
+}
+
+/// Pipes a client into `--stdin` with `--save-raw`, then replays the saved capture in a directory
+/// of its own. The capture has to hold the bytes as they were sent, and replaying it has to write
+/// the same files.
+#[test]
+fn save_raw() -> Result<(), String> {
+    use std::{env, fs, process};
+
+    use std::io::Write;
+
+    let directory = env::temp_dir().join(format!("eve-logger-test-{}-raw", process::id()));
+
+    let result = (|| {
+        let mut capture = Vec::new();
+        write_packet(&mut capture, &connection()).map_err(|e| e.to_string())?;
+        write_packet(&mut capture, &text("first")).map_err(|e| e.to_string())?;
+        write_packet(&mut capture, &text(&"large ".repeat(20000))).map_err(|e| e.to_string())?;
+
+        let received = directory.join("received");
+        let replayed = directory.join("replayed");
+        fs::create_dir_all(&received).map_err(|e| e.to_string())?;
+        fs::create_dir_all(&replayed).map_err(|e| e.to_string())?;
+
+        let mut server = process::Command::new(env!("CARGO_BIN_EXE_eve-logger"))
+            .args(["--stdin", "--save-raw", "../raw"])
+            .current_dir(&received)
+            .stdin(process::Stdio::piped())
+            .stdout(process::Stdio::null())
+            .spawn()
+            .map_err(|e| e.to_string())?;
+
+        server.stdin.take().ok_or("no stdin")?.write_all(&capture).map_err(|e| e.to_string())?;
+        let status = server.wait().map_err(|e| e.to_string())?;
+
+        if !status.success() {
+            return Err(format!("--stdin exited with {}", status));
+        }
+
+        let saved = fs::read(directory.join("raw/1234-0.bin")).map_err(|e| format!("raw/1234-0.bin: {}", e))?;
+
+        if saved != capture {
+            return Err(format!("saved {} bytes of {}", saved.len(), capture.len()));
+        }
+
+        let status = process::Command::new(env!("CARGO_BIN_EXE_eve-logger"))
+            .args(["--replay", "../raw/1234-0.bin"])
+            .current_dir(&replayed)
+            .output()
+            .map_err(|e| e.to_string())?
+            .status;
+
+        if !status.success() {
+            return Err(format!("--replay exited with {}", status));
+        }
+
+        let original = fs::read_to_string(received.join("1234/module.txt")).map_err(|e| e.to_string())?;
+        let again = fs::read_to_string(replayed.join("1234/module.txt")).map_err(|e| e.to_string())?;
+
+        if original != again || original.lines().count() != 2 {
+            return Err(format!("wrote {:?}, the replay {:?}", original, again));
+        }
+
+        Ok(())
+    })();
+
+    let _ = fs::remove_dir_all(&directory);
+    result
+}
+
+/// Pipes a client sending a Large message between Simple ones into `--stdin` with `--save-raw` and
+/// `--checkpoint-interval 0`. The index has to hold the offset after every message, each the start
+/// of a packet a replay can begin with, and nothing in between.
+#[test]
+fn checkpoints() -> Result<(), String> {
+    use std::{env, fs, process};
+
+    use std::io::Write;
+
+    let directory = env::temp_dir().join(format!("eve-logger-test-{}-checkpoints", process::id()));
+
+    let result = (|| {
+        let mut capture = Vec::new();
+        let mut boundaries = Vec::new();
+
+        for message in &[connection(), text("first"), text(&"large ".repeat(20000)), text("last")] {
+            write_packet(&mut capture, message).map_err(|e| e.to_string())?;
+            boundaries.push(capture.len() as u64);
+        }
+
+        let received = directory.join("received");
+        fs::create_dir_all(&received).map_err(|e| e.to_string())?;
+
+        let mut server = process::Command::new(env!("CARGO_BIN_EXE_eve-logger"))
+            .args(["--stdin", "--save-raw", "../raw", "--checkpoint-interval", "0"])
+            .current_dir(&received)
+            .stdin(process::Stdio::piped())
+            .stdout(process::Stdio::null())
+            .spawn()
+            .map_err(|e| e.to_string())?;
+
+        server.stdin.take().ok_or("no stdin")?.write_all(&capture).map_err(|e| e.to_string())?;
+        let status = server.wait().map_err(|e| e.to_string())?;
+
+        if !status.success() {
+            return Err(format!("--stdin exited with {}", status));
+        }
+
+        let index = fs::read(directory.join("raw/1234-0.idx")).map_err(|e| format!("raw/1234-0.idx: {}", e))?;
+
+        if index.len() % 16 != 0 {
+            return Err(format!("wrote an index of {} bytes", index.len()));
+        }
+
+        let records: Vec<(u64, u64)> = index.chunks(16).map(|record| {
+            let mut offset = [0; 8];
+            let mut millis = [0; 8];
+            offset.copy_from_slice(&record[..8]);
+            millis.copy_from_slice(&record[8..]);
+
+            (u64::from_le_bytes(offset), u64::from_le_bytes(millis))
+        }).collect();
+
+        let offsets: Vec<u64> = records.iter().map(|&(offset, _)| offset).collect();
+
+        if offsets != boundaries {
+            return Err(format!("indexed offsets {:?}, the packets end at {:?}", offsets, boundaries));
+        }
+
+        if records.windows(2).any(|pair| pair[1].1 < pair[0].1) || records[0].1 < 1500000000000 {
+            return Err(format!("indexed times {:?}", records));
+        }
+
+        // A replay from any checkpoint but the end has to start with a whole message.
+        for &offset in &offsets[..offsets.len() - 1] {
+            match Messages::new(&capture[offset as usize..]).next() {
+                Some(Ok(Message::Text(_))) => (),
+                next => return Err(format!("read {:?} from offset {}", next, offset))
+            }
+        }
+
+        Ok(())
+    })();
+
+    let _ = fs::remove_dir_all(&directory);
+    result
+}
+
+/// Replays a capture that switches between pids 1 and 2, their messages a few seconds out of order,
+/// with `--merge-all`. Without `--sort-by-time` the one file has to have them as they were read,
+/// with `--sort-by-time 30` in timestamp order, except for one that arrives after newer ones were
+/// written, which has to stay where it arrived.
+#[test]
+fn merge_all() -> Result<(), String> {
+    use std::{env, fs, process};
+
+    let directory = env::temp_dir().join(format!("eve-logger-test-{}-merge", process::id()));
+
+    let result = (|| {
+        let mut capture = Vec::new();
+        let sent = [(1, 10, "a"), (2, 5, "b"), (1, 20, "c"), (2, 15, "d"), (1, 100, "e"), (2, 1, "late"), (2, 90, "f")];
+
+        for &(pid, seconds, body) in &sent {
+            let announce = ConnectionMessage { version: 1, pid, machine_name: "HOST".to_string(), executable_path: "exefile.exe".to_string() };
+            write_packet(&mut capture, &Message::Connection(announce)).map_err(|e| e.to_string())?;
+
+            if let Message::Text(message) = text(body) {
+                let message = TextMessage { timestamp: message.timestamp + seconds, ..message };
+                write_packet(&mut capture, &Message::Text(message)).map_err(|e| e.to_string())?;
+            }
+        }
+
+        fs::create_dir_all(&directory).map_err(|e| e.to_string())?;
+        fs::write(directory.join("capture.bin"), capture).map_err(|e| e.to_string())?;
+
+        // The pids and bodies, as in `1/module: 2017-07-14 02:40:10 Warn [channel] > a`.
+        let cases = [(&[][..], "1a 2b 1c 2d 1e 2late 2f"), (&["--sort-by-time", "30"][..], "2b 1a 2d 1c 2late 2f 1e")];
+
+        for (sort, expected) in cases {
+            let mut args = vec!["--replay", "capture.bin", "--merge-all", "merged.txt"];
+            args.extend(sort);
+
+            let status = process::Command::new(env!("CARGO_BIN_EXE_eve-logger"))
+                .args(&args)
+                .current_dir(&directory)
+                .output()
+                .map_err(|e| e.to_string())?
+                .status;
+
+            if !status.success() {
+                return Err(format!("{:?} exited with {}", args, status));
+            }
+
+            let merged = fs::read_to_string(directory.join("merged.txt")).map_err(|e| e.to_string())?;
+            let order = merged.lines()
+                .map(|line| format!("{}{}", &line[..line.find('/').unwrap_or(0)], line.rsplit("> ").next().unwrap_or("")))
+                .collect::<Vec<_>>().join(" ");
+
+            if order != expected {
+                return Err(format!("{:?} wrote {:?}", args, merged));
+            }
+
+            if directory.join("1").exists() {
+                return Err(format!("{:?} wrote pid directories as well", args));
+            }
+        }
+
+        Ok(())
+    })();
+
+    let _ = fs::remove_dir_all(&directory);
+    result
+}
+
+/// Replays messages sent a minute apart with `--since` and `--until` around the middle ones. Only
+/// those from the start of the window up to, but not including, its end may be written.
+#[test]
+fn time_window() -> Result<(), String> {
+    use std::{env, fs, process};
+
+    let directory = env::temp_dir().join(format!("eve-logger-test-{}-window", process::id()));
+
+    let result = (|| {
+        let mut capture = Vec::new();
+        write_packet(&mut capture, &connection()).map_err(|e| e.to_string())?;
+
+        for (minute, body) in ["before", "since", "within", "until", "after"].iter().enumerate() {
+            if let Message::Text(mut message) = text(body) {
+                message.timestamp = 1500000000 + 60 * minute as u64;
+                write_packet(&mut capture, &Message::Text(message)).map_err(|e| e.to_string())?;
+            }
+        }
+
+        fs::create_dir_all(&directory).map_err(|e| e.to_string())?;
+        fs::write(directory.join("capture.bin"), capture).map_err(|e| e.to_string())?;
+
+        // 1500000000 is 2017-07-14T02:40:00Z, here given in another offset.
+        let status = process::Command::new(env!("CARGO_BIN_EXE_eve-logger"))
+            .args(["--replay", "capture.bin", "--since", "2017-07-14T04:41:00+02:00", "--until", "2017-07-14T02:43:00Z"])
+            .current_dir(&directory)
+            .output()
+            .map_err(|e| e.to_string())?
+            .status;
+
+        if !status.success() {
+            return Err(format!("exited with {}", status));
+        }
+
+        let written = fs::read_to_string(directory.join("1234/module.txt")).map_err(|e| e.to_string())?;
+        let bodies: Vec<&str> = written.lines().filter_map(|line| line.rsplit(' ').next()).collect();
+
+        if bodies != ["since", "within"] {
+            return Err(format!("wrote {:?}", written));
+        }
+
+        Ok(())
+    })();
+
+    let _ = fs::remove_dir_all(&directory);
+
+    result
+}
+
+/// Replays a capture that ends partway through the payload of its second message. The replay has
+/// to end cleanly with the first message written, saying how much of the last packet arrived.
+#[test]
+fn truncated_final_packet() -> Result<(), String> {
+    use std::{env, fs, process};
+
+    let directory = env::temp_dir().join(format!("eve-logger-test-{}-truncated", process::id()));
+
+    let result = (|| {
+        let mut capture = Vec::new();
+        write_packet(&mut capture, &connection()).map_err(|e| e.to_string())?;
+        write_packet(&mut capture, &text("complete")).map_err(|e| e.to_string())?;
+        write_packet(&mut capture, &text("cut short")).map_err(|e| e.to_string())?;
+        capture.truncate(capture.len() - 100);
+
+        fs::create_dir_all(&directory).map_err(|e| e.to_string())?;
+        fs::write(directory.join("capture.bin"), capture).map_err(|e| e.to_string())?;
+
+        let output = process::Command::new(env!("CARGO_BIN_EXE_eve-logger"))
+            .args(["--replay", "capture.bin"])
+            .current_dir(&directory)
+            .output()
+            .map_err(|e| e.to_string())?;
+
+        let written = fs::read_to_string(directory.join("1234/module.txt")).map_err(|e| e.to_string())?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let expected = format!("truncated final packet from pid=1234 (got {} of {} bytes)", HEADER_SIZE + TEXT_PAYLOAD_SIZE - 100, HEADER_SIZE + TEXT_PAYLOAD_SIZE);
+
+        if !output.status.success() || written.lines().count() != 1 || !written.contains("complete") || !stderr.contains(&expected) {
+            return Err(format!("exited with {} having written {:?} and said {:?}", output.status, written, stderr));
+        }
+
+        Ok(())
+    })();
+
+    let _ = fs::remove_dir_all(&directory);
+    result
+}
+
+/// Replays a capture whose second text message has a bad type with `--strict-parse`. The replay has
+/// to exit with 1 right there, having written the first message only, and print the error with a
+/// dump starting in the message before.
+#[test]
+fn strict_parse() -> Result<(), String> {
+    use std::{env, fs, process};
+
+    let directory = env::temp_dir().join(format!("eve-logger-test-{}-strict", process::id()));
+
+    let result = (|| {
+        let mut capture = Vec::new();
+        write_packet(&mut capture, &connection()).map_err(|e| e.to_string())?;
+        write_packet(&mut capture, &text("first")).map_err(|e| e.to_string())?;
+        let bad = capture.len();
+        write_packet(&mut capture, &text("second")).map_err(|e| e.to_string())?;
+        write_packet(&mut capture, &text("third")).map_err(|e| e.to_string())?;
+        capture[bad] = 9;
+
+        fs::create_dir_all(&directory).map_err(|e| e.to_string())?;
+        fs::write(directory.join("capture.bin"), capture).map_err(|e| e.to_string())?;
+
+        let output = process::Command::new(env!("CARGO_BIN_EXE_eve-logger"))
+            .args(["--replay", "capture.bin", "--strict-parse", "--max-errors", "10"])
+            .current_dir(&directory)
+            .output()
+            .map_err(|e| e.to_string())?;
+
+        let written = fs::read_to_string(directory.join("1234/module.txt")).map_err(|e| e.to_string())?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let error = format!("from pid=1234: Malformed packet: Unknown message type 9 at byte {}", bad);
+        let dump = format!("\n{:08x}  ", bad - 32);
+
+        if output.status.code() != Some(1) || written.lines().count() != 1 || !stderr.contains(&error) || !stderr.contains(&dump) {
+            return Err(format!("exited with {} having written {:?} and said {:?}", output.status, written, stderr));
+        }
+
+        Ok(())
+    })();
+
+    let _ = fs::remove_dir_all(&directory);
+    result
+}
+
+/// Replays a client whose only message `--min-severity error` filters out, in a directory of its
+/// own. With `--no-create-empty` nothing but the capture may be left in the directory, without it
+/// the pid directory is created at connection time.
+#[test]
+fn no_create_empty() -> Result<(), String> {
+    use std::{env, fs, process};
+
+    let directory = env::temp_dir().join(format!("eve-logger-test-{}-empty", process::id()));
+
+    let result = (|| {
+        let mut capture = Vec::new();
+        write_packet(&mut capture, &connection()).map_err(|e| e.to_string())?;
+        write_packet(&mut capture, &text("filtered")).map_err(|e| e.to_string())?;
+
+        fs::create_dir_all(&directory).map_err(|e| e.to_string())?;
+        fs::write(directory.join("capture.bin"), capture).map_err(|e| e.to_string())?;
+
+        for (lazy, expected) in [(true, vec!["capture.bin"]), (false, vec!["1234", "capture.bin"])] {
+            let mut args = vec!["--replay", "capture.bin", "--min-severity", "error"];
+
+            if lazy {
+                args.push("--no-create-empty");
+            }
+
+            let status = process::Command::new(env!("CARGO_BIN_EXE_eve-logger"))
+                .args(&args)
+                .current_dir(&directory)
+                .output()
+                .map_err(|e| e.to_string())?
+                .status;
+
+            let mut left: Vec<String> = fs::read_dir(&directory).map_err(|e| e.to_string())?
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .collect();
+            left.sort();
+
+            if !status.success() || left != expected {
+                return Err(format!("{:?} exited with {} and left {:?}", args, status, left));
+            }
+        }
+
+        Ok(())
+    })();
+
+    let _ = fs::remove_dir_all(&directory);
+    result
+}
+
+/// Runs a server with `--max-runtime 1` and a client that stays connected, its message held in the
+/// buffer by `--idle-flush`. The server has to exit cleanly by itself with the message written.
+#[test]
+fn max_runtime() -> Result<(), String> {
+    use std::{env, fs, process, thread};
+
+    use std::io::Write;
+    use std::net::{TcpListener, TcpStream};
+    use std::time::{Duration, Instant};
+
+    let directory = env::temp_dir().join(format!("eve-logger-test-{}-runtime", process::id()));
+
+    let result = (|| {
+        fs::create_dir_all(&directory).map_err(|e| e.to_string())?;
+
+        let address = TcpListener::bind("127.0.0.1:0").and_then(|listener| listener.local_addr()).map_err(|e| e.to_string())?;
+        let mut server = process::Command::new(env!("CARGO_BIN_EXE_eve-logger"))
+            .args(["--bind", &address.to_string(), "--max-runtime", "1", "--idle-flush", "60"])
+            .current_dir(&directory)
+            .stdout(process::Stdio::null())
+            .spawn()
+            .map_err(|e| e.to_string())?;
+
+        let started = Instant::now();
+        let mut stream = loop {
+            match TcpStream::connect(address) {
+                Ok(stream) => break stream,
+                Err(_) if started.elapsed() < Duration::from_millis(500) => thread::sleep(Duration::from_millis(10)),
+                Err(e) => {
+                    let _ = server.kill();
+                    return Err(format!("could not connect: {}", e));
+                }
+            }
+        };
+
+        let mut capture = Vec::new();
+        write_packet(&mut capture, &connection()).map_err(|e| e.to_string())?;
+        write_packet(&mut capture, &text("kept")).map_err(|e| e.to_string())?;
+        stream.write_all(&capture).map_err(|e| e.to_string())?;
+
+        let status = loop {
+            match server.try_wait().map_err(|e| e.to_string())? {
+                Some(status) => break status,
+                None if started.elapsed() < Duration::from_secs(10) => thread::sleep(Duration::from_millis(50)),
+                None => {
+                    let _ = server.kill();
+                    return Err("still running after 10s".to_string());
+                }
+            }
+        };
+
+        let written = fs::read_to_string(directory.join("1234/module.txt")).map_err(|e| e.to_string())?;
+
+        if !status.success() || !written.contains("kept") || started.elapsed() < Duration::from_secs(1) {
+            return Err(format!("exited with {} after {:?} having written {:?}", status, started.elapsed(), written));
+        }
+
+        Ok(())
+    })();
+
+    let _ = fs::remove_dir_all(&directory);
+    result
+}
+
+/// Replays four messages of two modules with `--summary`, then the same with `--date-dir`, the
+/// last of them a day later. The report has to count them by severity and by module with the first
+/// and last timestamps, and with `--date-dir` be split into one for each day.
+#[test]
+fn summary() -> Result<(), String> {
+    use std::{env, fs, process};
+
+    let directory = env::temp_dir().join(format!("eve-logger-test-{}-summary", process::id()));
+
+    let result = (|| {
+        let mut capture = Vec::new();
+        write_packet(&mut capture, &connection()).map_err(|e| e.to_string())?;
+
+        let sent = [(1500000000, Severity::Info, "net"), (1500000060, Severity::Error, "net"), (1500000030, Severity::Info, "ui"), (1500086400, Severity::Warn, "net")];
+
+        for &(timestamp, severity, module) in &sent {
+            if let Message::Text(message) = text("body") {
+                write_packet(&mut capture, &Message::Text(TextMessage { timestamp, severity, module: module.into(), ..message })).map_err(|e| e.to_string())?;
+            }
+        }
+
+        fs::create_dir_all(&directory).map_err(|e| e.to_string())?;
+        fs::write(directory.join("capture.bin"), capture).map_err(|e| e.to_string())?;
+
+        // The reports of each run, by file and the lines expected in them.
+        type Reports = &'static [(&'static str, &'static [&'static str])];
+
+        let cases: [(&str, &[&str], Reports); 2] = [
+            ("pid", &[], &[("1234/summary.txt", &[
+                "  first: 2017-07-14 02:40:00", "  last: 2017-07-15 02:40:00", "  messages: 4",
+                "  severity Error: 1", "  severity Info: 2", "  severity Warn: 1",
+                "  module net: 3", "    channel channel: Error=1 Info=1 Warn=1", "  module ui: 1", "    channel channel: Info=1"
+            ])]),
+            ("date-dir", &["--date-dir"], &[
+                ("2017-07-14/1234/summary.txt", &[
+                    "  first: 2017-07-14 02:40:00", "  last: 2017-07-14 02:41:00", "  messages: 3",
+                    "  severity Error: 1", "  severity Info: 2",
+                    "  module net: 2", "    channel channel: Error=1 Info=1", "  module ui: 1", "    channel channel: Info=1"
+                ]),
+                ("2017-07-15/1234/summary.txt", &[
+                    "  first: 2017-07-15 02:40:00", "  last: 2017-07-15 02:40:00", "  messages: 1",
+                    "  severity Warn: 1", "  module net: 1", "    channel channel: Warn=1"
+                ])
+            ])
+        ];
+
+        for (run, args, reports) in cases {
+            let output = directory.join(run);
+            fs::create_dir_all(&output).map_err(|e| e.to_string())?;
+
+            let status = process::Command::new(env!("CARGO_BIN_EXE_eve-logger"))
+                .args(["--replay", "../capture.bin", "--summary"])
+                .args(args)
+                .current_dir(&output)
+                .output()
+                .map_err(|e| e.to_string())?
+                .status;
+
+            if !status.success() {
+                return Err(format!("--replay {:?} exited with {}", args, status));
+            }
+
+            for &(file, expected) in reports {
+                let written = fs::read_to_string(output.join(file)).map_err(|e| format!("{}: {}", file, e))?;
+                // The first line is when the report was written.
+                let lines: Vec<&str> = written.lines().skip(1).collect();
+
+                if lines != expected {
+                    return Err(format!("{:?} wrote {:?} to {}", args, written, file));
+                }
+            }
+        }
+
+        Ok(())
+    })();
+
+    let _ = fs::remove_dir_all(&directory);
+    result
+}
+
+/// Replays a message with an empty module next to one with a module, with the default
+/// `--default-module` and with `--default-module unnamed`. The empty one has to be written to the
+/// file of the default module rather than the hidden `1234/.txt`.
+#[test]
+fn default_module() -> Result<(), String> {
+    use std::{env, fs, process};
+
+    let directory = env::temp_dir().join(format!("eve-logger-test-{}-default-module", process::id()));
+
+    let result = (|| {
+        let mut capture = Vec::new();
+        write_packet(&mut capture, &connection()).map_err(|e| e.to_string())?;
+        write_packet(&mut capture, &text("named")).map_err(|e| e.to_string())?;
+
+        if let Message::Text(message) = text("empty") {
+            write_packet(&mut capture, &Message::Text(TextMessage { module: "".into(), ..message })).map_err(|e| e.to_string())?;
+        }
+
+        fs::create_dir_all(&directory).map_err(|e| e.to_string())?;
+        fs::write(directory.join("capture.bin"), capture).map_err(|e| e.to_string())?;
+
+        for (run, args, file) in [("default", &[][..], "default.txt"), ("unnamed", &["--default-module", "unnamed"][..], "unnamed.txt")] {
+            let output = directory.join(run);
+            fs::create_dir_all(&output).map_err(|e| e.to_string())?;
+
+            let status = process::Command::new(env!("CARGO_BIN_EXE_eve-logger"))
+                .args(["--replay", "../capture.bin"])
+                .args(args)
+                .current_dir(&output)
+                .output()
+                .map_err(|e| e.to_string())?
+                .status;
+
+            if !status.success() {
+                return Err(format!("--replay {:?} exited with {}", args, status));
+            }
+
+            let named = fs::read_to_string(output.join("1234/module.txt")).map_err(|e| format!("{}: {}", run, e))?;
+            let empty = fs::read_to_string(output.join("1234").join(file)).map_err(|e| format!("{}: {}", run, e))?;
+
+            if !named.contains("named") || named.contains("empty") || !empty.contains("empty") || output.join("1234/.txt").exists() {
+                return Err(format!("{:?} wrote {:?} to module.txt and {:?} to {}", args, named, empty, file));
+            }
+        }
+
+        Ok(())
+    })();
+
+    let _ = fs::remove_dir_all(&directory);
+    result
+}
+
+/// Runs a server with `--archive-on-disconnect --archive-remove` and connects two clients of the
+/// same pid one after the other. Each disconnect has to pack the pid's directory into a tarball
+/// holding what that client sent, the second one numbered rather than replacing the first, and
+/// leave no directory behind.
+#[test]
+fn archive_on_disconnect() -> Result<(), String> {
+    use std::{env, fs, process, thread};
+
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::path::Path;
+    use std::time::{Duration, Instant};
+
+    use flate2::read::GzDecoder;
+
+    let directory = env::temp_dir().join(format!("eve-logger-test-{}-archive-on-disconnect", process::id()));
+
+    let result = (|| {
+        fs::create_dir_all(&directory).map_err(|e| e.to_string())?;
+
+        let address = TcpListener::bind("127.0.0.1:0").and_then(|listener| listener.local_addr()).map_err(|e| e.to_string())?;
+        let mut server = process::Command::new(env!("CARGO_BIN_EXE_eve-logger"))
+            .args(["--bind", &address.to_string(), "--archive-on-disconnect", "--archive-remove"])
+            .current_dir(&directory)
+            .stdout(process::Stdio::null())
+            .spawn()
+            .map_err(|e| e.to_string())?;
+
+        let started = Instant::now();
+        let mut stream = loop {
+            match TcpStream::connect(address) {
+                Ok(stream) => break stream,
+                Err(_) if started.elapsed() < Duration::from_millis(500) => thread::sleep(Duration::from_millis(10)),
+                Err(e) => {
+                    let _ = server.kill();
+                    return Err(format!("could not connect: {}", e));
+                }
+            }
+        };
+
+        let mut archives = Vec::new();
+
+        for (name, body) in [("1234.tar.gz", "first"), ("1234.1.tar.gz", "second")] {
+            let mut capture = Vec::new();
+            write_packet(&mut capture, &connection()).map_err(|e| e.to_string())?;
+            write_packet(&mut capture, &text(body)).map_err(|e| e.to_string())?;
+            stream.write_all(&capture).map_err(|e| e.to_string())?;
+            drop(stream);
+
+            let archive = directory.join(name);
+            let sent = Instant::now();
+
+            let packed = || archive.exists() && !directory.join("1234").exists();
+
+            while !packed() && sent.elapsed() < Duration::from_secs(5) {
+                thread::sleep(Duration::from_millis(20));
+            }
+
+            archives.push((archive.clone(), body, directory.join("1234").exists()));
+            stream = TcpStream::connect(address).map_err(|e| e.to_string())?;
+        }
+
+        drop(stream);
+        let _ = server.kill();
+        let _ = server.wait();
+
+        for (archive, body, left) in archives {
+            let file = fs::File::open(&archive).map_err(|e| format!("{}: {}", archive.display(), e))?;
+            let mut tarball = tar::Archive::new(GzDecoder::new(file));
+            let mut contents = Vec::new();
+
+            for entry in tarball.entries().map_err(|e| e.to_string())? {
+                let mut entry = entry.map_err(|e| e.to_string())?;
+                let path = entry.path().map_err(|e| e.to_string())?.into_owned();
+                let mut written = String::new();
+                entry.read_to_string(&mut written).map_err(|e| e.to_string())?;
+                contents.push((path, written));
+            }
+
+            let module = contents.iter().find(|(path, _)| path == Path::new("1234/module.txt"));
+
+            if left || !module.is_some_and(|(_, written)| written.contains(body) && written.lines().count() == 1) {
+                return Err(format!("{} holds {:?}, the directory left behind: {}", archive.display(), contents, left));
+            }
+        }
+
+        Ok(())
+    })();
+
+    let _ = fs::remove_dir_all(&directory);
+    result
+}
+
+/// Runs a server with `--idle-flush 1` and a client that sends a message and stays connected. The
+/// message has to be held in the buffer at first, and be on disk once the file has been quiet for
+/// a second, while the client is still connected.
+#[test]
+fn idle_flush() -> Result<(), String> {
+    use std::{env, fs, process, thread};
+
+    use std::io::Write;
+    use std::net::{TcpListener, TcpStream};
+    use std::time::{Duration, Instant};
+
+    let directory = env::temp_dir().join(format!("eve-logger-test-{}-idle-flush", process::id()));
+
+    let result = (|| {
+        fs::create_dir_all(&directory).map_err(|e| e.to_string())?;
+
+        let address = TcpListener::bind("127.0.0.1:0").and_then(|listener| listener.local_addr()).map_err(|e| e.to_string())?;
+        let mut server = process::Command::new(env!("CARGO_BIN_EXE_eve-logger"))
+            .args(["--bind", &address.to_string(), "--idle-flush", "1"])
+            .current_dir(&directory)
+            .stdout(process::Stdio::null())
+            .spawn()
+            .map_err(|e| e.to_string())?;
+
+        let started = Instant::now();
+        let mut stream = loop {
+            match TcpStream::connect(address) {
+                Ok(stream) => break stream,
+                Err(_) if started.elapsed() < Duration::from_millis(500) => thread::sleep(Duration::from_millis(10)),
+                Err(e) => {
+                    let _ = server.kill();
+                    return Err(format!("could not connect: {}", e));
+                }
+            }
+        };
+
+        let mut capture = Vec::new();
+        write_packet(&mut capture, &connection()).map_err(|e| e.to_string())?;
+        write_packet(&mut capture, &text("quiet")).map_err(|e| e.to_string())?;
+        stream.write_all(&capture).map_err(|e| e.to_string())?;
+
+        let sent = Instant::now();
+        let written = || fs::read_to_string(directory.join("1234/module.txt")).unwrap_or_default().contains("quiet");
+
+        thread::sleep(Duration::from_millis(300));
+        let buffered = !written();
+
+        while !written() && sent.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        let flushed = sent.elapsed();
+        let _ = server.kill();
+        let _ = server.wait();
+
+        if !buffered || !written() || flushed < Duration::from_secs(1) {
+            return Err(format!("buffered: {}, on disk after {:?}", buffered, flushed));
+        }
+
+        Ok(())
+    })();
+
+    let _ = fs::remove_dir_all(&directory);
+    result
+}
+
+/// Replays module `module` writing to channels `net` and `ui/main` in turn with
+/// `--split-by-channel`. Each channel has to get a file of its own under the module's directory,
+/// the one with a `/` made safe, holding only its own lines.
+#[test]
+fn split_by_channel() -> Result<(), String> {
+    use std::{env, fs, process};
+
+    let directory = env::temp_dir().join(format!("eve-logger-test-{}-split", process::id()));
+
+    let result = (|| {
+        let mut capture = Vec::new();
+        write_packet(&mut capture, &connection()).map_err(|e| e.to_string())?;
+
+        for &(channel, body) in &[("net", "n1"), ("ui/main", "u1"), ("net", "n2")] {
+            if let Message::Text(message) = text(body) {
+                write_packet(&mut capture, &Message::Text(TextMessage { channel: channel.into(), ..message })).map_err(|e| e.to_string())?;
+            }
+        }
+
+        fs::create_dir_all(&directory).map_err(|e| e.to_string())?;
+        fs::write(directory.join("capture.bin"), capture).map_err(|e| e.to_string())?;
+
+        let status = process::Command::new(env!("CARGO_BIN_EXE_eve-logger"))
+            .args(["--replay", "capture.bin", "--split-by-channel"])
+            .current_dir(&directory)
+            .output()
+            .map_err(|e| e.to_string())?
+            .status;
+
+        if !status.success() {
+            return Err(format!("--replay exited with {}", status));
+        }
+
+        for &(file, bodies) in &[("net.txt", &["n1", "n2"][..]), ("ui_main.txt", &["u1"])] {
+            let written = fs::read_to_string(directory.join("1234/module").join(file)).map_err(|e| format!("{}: {}", file, e))?;
+            let lines: Vec<&str> = written.lines().collect();
+
+            if lines.len() != bodies.len() || lines.iter().zip(bodies).any(|(line, body)| !line.ends_with(body)) {
+                return Err(format!("wrote {:?} to {}", written, file));
+            }
+        }
+
+        Ok(())
+    })();
+
+    let _ = fs::remove_dir_all(&directory);
+    result
+}
+
+/// Replays the same two lines twice into one directory with `--truncate-existing`, then once more
+/// without it. The second run has to replace what the first wrote, keeping both of its own lines,
+/// and the third append to it.
+#[test]
+fn truncate_existing() -> Result<(), String> {
+    use std::{env, fs, process};
+
+    let directory = env::temp_dir().join(format!("eve-logger-test-{}-truncate", process::id()));
+
+    let result = (|| {
+        let mut capture = Vec::new();
+        write_packet(&mut capture, &connection()).map_err(|e| e.to_string())?;
+        write_packet(&mut capture, &text("first")).map_err(|e| e.to_string())?;
+        write_packet(&mut capture, &text("second")).map_err(|e| e.to_string())?;
+
+        fs::create_dir_all(&directory).map_err(|e| e.to_string())?;
+        fs::write(directory.join("capture.bin"), capture).map_err(|e| e.to_string())?;
+
+        for (args, lines) in [(&["--truncate-existing"][..], 2), (&["--truncate-existing"], 2), (&[], 4)] {
+            let status = process::Command::new(env!("CARGO_BIN_EXE_eve-logger"))
+                .args(["--replay", "capture.bin"])
+                .args(args)
+                .current_dir(&directory)
+                .output()
+                .map_err(|e| e.to_string())?
+                .status;
+
+            if !status.success() {
+                return Err(format!("--replay {:?} exited with {}", args, status));
+            }
+
+            let written = fs::read_to_string(directory.join("1234/module.txt")).map_err(|e| e.to_string())?;
+
+            if written.lines().count() != lines || !written.ends_with("second\n") {
+                return Err(format!("--replay {:?} left {:?}", args, written));
+            }
+        }
+
+        Ok(())
+    })();
+
+    let _ = fs::remove_dir_all(&directory);
+    result
+}
+
+/// Splits a capture in the middle of a Large message into two files, and replays them with
+/// `--replay part-*.bin`, the second written first. The files have to be read in the order of their
+/// names as one stream, the Large message reassembled across them.
+#[test]
+fn replay_parts() -> Result<(), String> {
+    use std::{env, fs, process};
+
+    let directory = env::temp_dir().join(format!("eve-logger-test-{}-parts", process::id()));
+
+    let result = (|| {
+        let large = "0123456789".repeat(100);
+        let mut capture = Vec::new();
+        write_packet(&mut capture, &connection()).map_err(|e| e.to_string())?;
+        write_packet(&mut capture, &text(&large)).map_err(|e| e.to_string())?;
+        write_packet(&mut capture, &text("after")).map_err(|e| e.to_string())?;
+
+        // Past the Connection message and the first chunk, inside a continuation.
+        let (first, second) = capture.split_at(capture.len() / 2);
+
+        fs::create_dir_all(&directory).map_err(|e| e.to_string())?;
+        fs::write(directory.join("part-2.bin"), second).map_err(|e| e.to_string())?;
+        fs::write(directory.join("part-1.bin"), first).map_err(|e| e.to_string())?;
+
+        let output = process::Command::new(env!("CARGO_BIN_EXE_eve-logger"))
+            .args(["--replay", "part-*.bin"])
+            .current_dir(&directory)
+            .output()
+            .map_err(|e| e.to_string())?;
+
+        if !output.status.success() {
+            return Err(format!("--replay exited with {}: {}", output.status, String::from_utf8_lossy(&output.stderr)));
+        }
+
+        let written = fs::read_to_string(directory.join("1234/module.txt")).map_err(|e| e.to_string())?;
+        let lines: Vec<&str> = written.lines().collect();
+
+        if lines.len() != 2 || !lines[0].ends_with(&large) || !lines[1].ends_with("after") {
+            return Err(format!("wrote {:?}", written));
+        }
+
+        Ok(())
+    })();
+
+    let _ = fs::remove_dir_all(&directory);
+    result
+}
+
+/// Replays three messages to module `a` interleaved with two to `b` with `--sequence-numbers`, as
+/// text and as JSON. Each module has to count its own lines from 1, in the order they were sent.
+#[test]
+fn sequence_numbers() -> Result<(), String> {
+    use std::{env, fs, process};
+
+    let directory = env::temp_dir().join(format!("eve-logger-test-{}-sequence", process::id()));
+
+    let result = (|| {
+        let mut capture = Vec::new();
+        write_packet(&mut capture, &connection()).map_err(|e| e.to_string())?;
+
+        for &(module, body) in &[("a", "a1"), ("b", "b1"), ("a", "a2"), ("a", "a3"), ("b", "b2")] {
+            if let Message::Text(message) = text(body) {
+                write_packet(&mut capture, &Message::Text(TextMessage { module: module.into(), ..message })).map_err(|e| e.to_string())?;
+            }
+        }
+
+        fs::create_dir_all(&directory).map_err(|e| e.to_string())?;
+        fs::write(directory.join("capture.bin"), capture).map_err(|e| e.to_string())?;
+
+        let status = process::Command::new(env!("CARGO_BIN_EXE_eve-logger"))
+            .args(["--replay", "capture.bin", "--sequence-numbers", "--also-format", "json"])
+            .current_dir(&directory)
+            .output()
+            .map_err(|e| e.to_string())?
+            .status;
+
+        if !status.success() {
+            return Err(format!("--replay exited with {}", status));
+        }
+
+        for &(module, count) in &[("a", 3), ("b", 2)] {
+            let text = fs::read_to_string(directory.join(format!("1234/{}.txt", module))).map_err(|e| e.to_string())?;
+            let json = fs::read_to_string(directory.join(format!("1234/{}.jsonl", module))).map_err(|e| e.to_string())?;
+
+            let numbered = (1..=count).all(|sequence| {
+                let line = |lines: &str| lines.lines().nth(sequence - 1).unwrap_or_default().to_string();
+
+                line(&text).starts_with(&format!("{} ", sequence)) && line(&text).ends_with(&format!("{}{}", module, sequence))
+                    && line(&json).contains(&format!("\"sequence\":{}", sequence))
+            });
+
+            if !numbered || text.lines().count() != count || json.lines().count() != count {
+                return Err(format!("wrote {:?} and {:?} for module {}", text, json, module));
+            }
+        }
+
+        Ok(())
+    })();
+
+    let _ = fs::remove_dir_all(&directory);
+    result
+}
+
+/// Pipes the same client into `--stdin` with `--anonymize-connection` in three runs, two with the
+/// same `--anon-salt` and one with another. The runs with the same salt have to log the same hashed
+/// Connection line, the other a different one, and none the machine name.
+#[test]
+fn anonymize_connection() -> Result<(), String> {
+    use std::{env, fs, process};
+
+    use std::io::Write;
+
+    let directory = env::temp_dir().join(format!("eve-logger-test-{}-anonymize", process::id()));
+
+    let result = (|| {
+        let mut capture = Vec::new();
+        write_packet(&mut capture, &connection()).map_err(|e| e.to_string())?;
+        fs::create_dir_all(&directory).map_err(|e| e.to_string())?;
+
+        let mut connected = Vec::new();
+
+        for salt in ["pepper", "pepper", "cumin"] {
+            let mut server = process::Command::new(env!("CARGO_BIN_EXE_eve-logger"))
+                .args(["--stdin", "--anonymize-connection", "--anon-salt", salt])
+                .current_dir(&directory)
+                .stdin(process::Stdio::piped())
+                .stdout(process::Stdio::piped())
+                .spawn()
+                .map_err(|e| e.to_string())?;
+
+            server.stdin.take().ok_or("no stdin")?.write_all(&capture).map_err(|e| e.to_string())?;
+            let output = server.wait_with_output().map_err(|e| e.to_string())?;
+            let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+
+            // Without the time it was logged at.
+            let line = stdout.lines().find_map(|line| line.find("Connected: ").map(|start| line[start..].to_string()));
+            connected.push(line.ok_or_else(|| format!("logged {:?}", stdout))?);
+        }
+
+        if connected[0] != connected[1] || connected[0] == connected[2] || connected.iter().any(|line| line.contains("HOST") || !line.contains("machine=anon-")) {
+            return Err(format!("logged {:?}", connected));
+        }
+
+        Ok(())
+    })();
+
+    let _ = fs::remove_dir_all(&directory);
+    result
+}
+
+/// Replays a message a second before midnight UTC and one a second after with `--date-dir`, the
+/// same module's file open for both. Each has to land in the directory of its own date.
+#[test]
+fn date_dir() -> Result<(), String> {
+    use std::{env, fs, process};
+
+    let directory = env::temp_dir().join(format!("eve-logger-test-{}-date-dir", process::id()));
+
+    let result = (|| {
+        // 2017-07-15 00:00:00 UTC.
+        let midnight = 1500076800;
+        let mut capture = Vec::new();
+        write_packet(&mut capture, &connection()).map_err(|e| e.to_string())?;
+
+        for &(timestamp, body) in &[(midnight - 1, "before"), (midnight + 1, "after")] {
+            if let Message::Text(message) = text(body) {
+                write_packet(&mut capture, &Message::Text(TextMessage { timestamp, ..message })).map_err(|e| e.to_string())?;
+            }
+        }
+
+        fs::create_dir_all(&directory).map_err(|e| e.to_string())?;
+        fs::write(directory.join("capture.bin"), capture).map_err(|e| e.to_string())?;
+
+        let status = process::Command::new(env!("CARGO_BIN_EXE_eve-logger"))
+            .args(["--replay", "capture.bin", "--date-dir"])
+            .current_dir(&directory)
+            .output()
+            .map_err(|e| e.to_string())?
+            .status;
+
+        if !status.success() {
+            return Err(format!("--replay exited with {}", status));
+        }
+
+        for &(date, body) in &[("2017-07-14", "before"), ("2017-07-15", "after")] {
+            let written = fs::read_to_string(directory.join(date).join("1234/module.txt")).map_err(|e| format!("{}: {}", date, e))?;
+
+            if written.lines().count() != 1 || !written.contains(body) {
+                return Err(format!("wrote {:?} under {}", written, date));
+            }
+        }
+
+        Ok(())
+    })();
+
+    let _ = fs::remove_dir_all(&directory);
+    result
+}
+
+/// Runs a server with `--max-connections 1` and connects two clients. The second has to be closed
+/// right away while the first is still served, its message written once it sends it.
+#[test]
+fn max_connections() -> Result<(), String> {
+    use std::{env, fs, io, process, thread};
+
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::time::{Duration, Instant};
+
+    let directory = env::temp_dir().join(format!("eve-logger-test-{}-max-connections", process::id()));
+
+    let result = (|| {
+        fs::create_dir_all(&directory).map_err(|e| e.to_string())?;
+
+        let address = TcpListener::bind("127.0.0.1:0").and_then(|listener| listener.local_addr()).map_err(|e| e.to_string())?;
+        let mut server = process::Command::new(env!("CARGO_BIN_EXE_eve-logger"))
+            .args(["--bind", &address.to_string(), "--max-connections", "1", "--max-runtime", "2"])
+            .current_dir(&directory)
+            .stdout(process::Stdio::piped())
+            .spawn()
+            .map_err(|e| e.to_string())?;
+
+        let started = Instant::now();
+        let mut first = loop {
+            match TcpStream::connect(address) {
+                Ok(stream) => break stream,
+                Err(_) if started.elapsed() < Duration::from_millis(500) => thread::sleep(Duration::from_millis(10)),
+                Err(e) => {
+                    let _ = server.kill();
+                    return Err(format!("could not connect: {}", e));
+                }
+            }
+        };
+
+        // Something to make sure the first is being served before the second connects.
+        let mut capture = Vec::new();
+        write_packet(&mut capture, &connection()).map_err(|e| e.to_string())?;
+        first.write_all(&capture).map_err(|e| e.to_string())?;
+        thread::sleep(Duration::from_millis(100));
+
+        let mut second = TcpStream::connect(address).map_err(|e| e.to_string())?;
+        second.set_read_timeout(Some(Duration::from_secs(1))).map_err(|e| e.to_string())?;
+
+        // Closed, or reset for what was sent into it, rather than left waiting for more.
+        let _ = second.write_all(&capture);
+        let rejected = match second.read(&mut [0; 1]) {
+            Err(ref e) => e.kind() != io::ErrorKind::WouldBlock && e.kind() != io::ErrorKind::TimedOut,
+            Ok(read) => read == 0
+        };
+
+        let mut served = Vec::new();
+        write_packet(&mut served, &text("still served")).map_err(|e| e.to_string())?;
+        first.write_all(&served).map_err(|e| e.to_string())?;
+        drop(first);
+
+        let output = server.wait_with_output().map_err(|e| e.to_string())?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let written = fs::read_to_string(directory.join("1234/module.txt")).map_err(|e| e.to_string())?;
+
+        if !rejected || !stdout.contains("at the limit of 1 connections") || !written.contains("still served") {
+            return Err(format!("rejected: {}, logged {:?} and wrote {:?}", rejected, stdout, written));
+        }
+
+        Ok(())
+    })();
+
+    let _ = fs::remove_dir_all(&directory);
+    result
+}
+
+/// Runs a server with `--http` on an address another listener holds. It has to say it could not
+/// bind and exit with 1, rather than panic.
+#[test]
+fn http_bind_taken() -> Result<(), String> {
+    use std::{env, fs, process};
+
+    use std::net::TcpListener;
+
+    let directory = env::temp_dir().join(format!("eve-logger-test-{}-http-bind", process::id()));
+
+    let result = (|| {
+        fs::create_dir_all(&directory).map_err(|e| e.to_string())?;
+
+        let taken = TcpListener::bind("127.0.0.1:0").map_err(|e| e.to_string())?;
+        let address = taken.local_addr().map_err(|e| e.to_string())?;
+
+        let output = process::Command::new(env!("CARGO_BIN_EXE_eve-logger"))
+            .args(["--http", &address.to_string(), "--bind", "127.0.0.1:0", "--max-runtime", "1"])
+            .current_dir(&directory)
+            .output()
+            .map_err(|e| e.to_string())?;
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        if output.status.code() != Some(1) || !stderr.contains(&format!("Could not bind {} for --http", address)) {
+            return Err(format!("exited with {} saying {:?}", output.status, stderr));
+        }
+
+        Ok(())
+    })();
+
+    let _ = fs::remove_dir_all(&directory);
+    result
+}
+
+/// Runs a server with `--session-ids`, `--reconnect-grace 5` and JSON close records, and connects
+/// pid 1234 twice, the second time right after the first left. Both close records have to be of
+/// session 1, though the second connection took the next id.
+#[test]
+fn reconnect_session_id() -> Result<(), String> {
+    use std::{env, fs, process, thread};
+
+    use std::io::Write;
+    use std::net::{TcpListener, TcpStream};
+    use std::time::{Duration, Instant};
+
+    let directory = env::temp_dir().join(format!("eve-logger-test-{}-reconnect", process::id()));
+    let closes = |written: &str| written.lines().filter(|line| line.contains("\"type\":\"close\"")).map(str::to_string).collect::<Vec<_>>();
+
+    let result = (|| {
+        fs::create_dir_all(&directory).map_err(|e| e.to_string())?;
+
+        let address = TcpListener::bind("127.0.0.1:0").and_then(|listener| listener.local_addr()).map_err(|e| e.to_string())?;
+        let args = ["--bind", &address.to_string(), "--session-ids", "--reconnect-grace", "5", "--close-records", "--format", "json", "--json-envelope", "--max-runtime", "2"];
+        let mut server = process::Command::new(env!("CARGO_BIN_EXE_eve-logger"))
+            .args(args)
+            .current_dir(&directory)
+            .stdout(process::Stdio::null())
+            .spawn()
+            .map_err(|e| e.to_string())?;
+
+        let mut capture = Vec::new();
+        write_packet(&mut capture, &connection()).map_err(|e| e.to_string())?;
+        write_packet(&mut capture, &text("again")).map_err(|e| e.to_string())?;
+
+        let started = Instant::now();
+        let sessions = directory.join("1234/session.jsonl");
+
+        for closed in 1..3 {
+            let mut stream = loop {
+                match TcpStream::connect(address) {
+                    Ok(stream) => break stream,
+                    Err(_) if started.elapsed() < Duration::from_millis(500) => thread::sleep(Duration::from_millis(10)),
+                    Err(e) => {
+                        let _ = server.kill();
+                        return Err(format!("could not connect: {}", e));
+                    }
+                }
+            };
+
+            stream.write_all(&capture).map_err(|e| e.to_string())?;
+            drop(stream);
+
+            // The next connection only carries on once this one has left.
+            while fs::read_to_string(&sessions).map_or(0, |written| closes(&written).len()) < closed && started.elapsed() < Duration::from_secs(2) {
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+
+        let status = server.wait().map_err(|e| e.to_string())?;
+        let written = fs::read_to_string(&sessions).map_err(|e| e.to_string())?;
+        let counter = fs::read_to_string(directory.join("session-counter")).map_err(|e| e.to_string())?;
+        let closed = closes(&written);
+
+        if !status.success() || closed.len() != 2 || closed.iter().any(|line| !line.contains("\"session\":1,")) || counter.trim() != "2" {
+            return Err(format!("exited with {} having written {:?} and counted to {:?}", status, written, counter));
+        }
+
+        Ok(())
+    })();
+
+    let _ = fs::remove_dir_all(&directory);
+    result
+}
+
+/// Pipes a client sending a text with a timestamp of 2^60 seconds, far past what a date holds,
+/// into `--stdin`. It has to be written under the time it arrived rather than bring the server down,
+/// and the message after it as usual.
+#[test]
+fn out_of_range_timestamp() -> Result<(), String> {
+    use std::{env, fs, process};
+
+    use std::io::Write;
+
+    let directory = env::temp_dir().join(format!("eve-logger-test-{}-out-of-range", process::id()));
+
+    let result = (|| {
+        let mut capture = Vec::new();
+        write_packet(&mut capture, &connection()).map_err(|e| e.to_string())?;
+
+        if let Message::Text(mut bogus) = text("from far ahead") {
+            bogus.timestamp = 1 << 60;
+            write_packet(&mut capture, &Message::Text(bogus)).map_err(|e| e.to_string())?;
+        }
+
+        write_packet(&mut capture, &text("on time")).map_err(|e| e.to_string())?;
+        fs::create_dir_all(&directory).map_err(|e| e.to_string())?;
+
+        let mut server = process::Command::new(env!("CARGO_BIN_EXE_eve-logger"))
+            .arg("--stdin")
+            .current_dir(&directory)
+            .stdin(process::Stdio::piped())
+            .stdout(process::Stdio::null())
+            .spawn()
+            .map_err(|e| e.to_string())?;
+
+        server.stdin.take().ok_or("no stdin")?.write_all(&capture).map_err(|e| e.to_string())?;
+        let status = server.wait().map_err(|e| e.to_string())?;
+
+        if !status.success() {
+            return Err(format!("--stdin exited with {}", status));
+        }
+
+        let written = fs::read_to_string(directory.join("1234/module.txt")).map_err(|e| e.to_string())?;
+
+        if written.lines().count() != 2 || !written.contains("from far ahead") || !written.contains("on time") {
+            return Err(format!("wrote {:?}", written));
+        }
+
+        Ok(())
+    })();
+
+    let _ = fs::remove_dir_all(&directory);
+    result
+}
+
+/// Pipes a client sending a text with a timestamp out of range and one on time into `--stdin`. The
+/// skew in its Disconnected line has to show the bogus timestamp as it was sent.
+#[test]
+fn skew_raw_timestamps() -> Result<(), String> {
+    use std::{env, fs, process};
+
+    use std::io::Write;
+
+    let directory = env::temp_dir().join(format!("eve-logger-test-{}-skew", process::id()));
+
+    let result = (|| {
+        let mut capture = Vec::new();
+        write_packet(&mut capture, &connection()).map_err(|e| e.to_string())?;
+
+        if let Message::Text(mut bogus) = text("from far ahead") {
+            bogus.timestamp = 1 << 60;
+            write_packet(&mut capture, &Message::Text(bogus)).map_err(|e| e.to_string())?;
+        }
+
+        write_packet(&mut capture, &text("on time")).map_err(|e| e.to_string())?;
+        fs::create_dir_all(&directory).map_err(|e| e.to_string())?;
+
+        let mut server = process::Command::new(env!("CARGO_BIN_EXE_eve-logger"))
+            .arg("--stdin")
+            .current_dir(&directory)
+            .stdin(process::Stdio::piped())
+            .stdout(process::Stdio::piped())
+            .spawn()
+            .map_err(|e| e.to_string())?;
+
+        server.stdin.take().ok_or("no stdin")?.write_all(&capture).map_err(|e| e.to_string())?;
+        let output = server.wait_with_output().map_err(|e| e.to_string())?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+
+        let disconnected = stdout.lines().find(|line| line.contains("Disconnected: pid=1234")).ok_or_else(|| format!("logged {:?}", stdout))?;
+
+        if !disconnected.contains(&format!("(raw timestamps first={} last=1500000000)", 1u64 << 60)) {
+            return Err(format!("logged {:?}", disconnected));
+        }
+
+        Ok(())
+    })();
+
+    let _ = fs::remove_dir_all(&directory);
+    result
+}
+
+fn connection() -> Message {
+    Message::Connection(ConnectionMessage {
+        version: 1,
+        pid: 1234,
+        machine_name: "HOST".to_string(),
+        executable_path: "C:\\EVE\\bin\\exefile.exe".to_string()
+    })
+}
+
+fn text(message: &str) -> Message {
+    Message::Text(TextMessage {
+        timestamp: 1500000000,
+        severity: Severity::Warn,
+        module: "module".into(),
+        channel: "channel".into(),
+        message: message.to_string(),
+        truncated: false
+    })
+}